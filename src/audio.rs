@@ -1,19 +1,33 @@
-use crate::{ midi::MidiMessage, synth::{self, HasConstructor, HasEngine, HasMidiInput, HasParameters, Synth}, HarmonicModel, ParameterUpdate};
+use laminaria::engine::{AudioEngine, EngineMessage};
+use laminaria::midi::TimedMidiMessage;
+use laminaria::model_switch::ModelSwap;
+use laminaria::parameters::ParameterCapsule;
+use laminaria::queue::{QueueReceiver, QueueSender};
+use laminaria::synth::Synth;
 use cpal::{
     traits::{DeviceTrait, HostTrait},
     SizedSample,
 };
-use cpal::{FromSample, Sample};
-use std::{sync::mpsc::Receiver};
+use cpal::FromSample;
+use crate::settings::AudioSettings;
+use crate::ui::option_menu;
+use std::time::Instant;
 
+///`capsules` are the model parameters, used to map the incoming CC.
+///Also return a description of the chosen audio setup and the sample rate
 pub fn stream_setup_for(
-    parameter_receiver: Receiver<ParameterUpdate>,
-    midi_receiver: Receiver<MidiMessage>,
+    parameter_receiver: QueueReceiver<EngineMessage>,
+    midi_receiver: QueueReceiver<TimedMidiMessage>,
+    retired_sender: QueueSender<Box<ModelSwap>>,
     synth_model: Box<dyn Synth>,
-) -> Result<cpal::Stream, anyhow::Error>
+    capsules: Vec<ParameterCapsule>,
+    settings: &AudioSettings,
+) -> Result<(cpal::Stream, String, f32), anyhow::Error>
 where
 {
-    let (_host, device, config) = host_device_setup()?;
+    let (_host, device, config, stream_config) = host_device_setup(settings)?;
+    let description = describe_setup(&_host, &device, &stream_config);
+    println!("Audio setup : {}", description);
 
     let result = match config.sample_format() {
     f @ cpal::SampleFormat::I8  => make_stream::<i8>,
@@ -29,37 +43,186 @@ where
     other => return Err(anyhow::anyhow!("Unsupported sample format '{other:?}'")),
 };
 
-result(&device, &config.into(), parameter_receiver, midi_receiver, synth_model)
+let stream = result(&device, &stream_config, parameter_receiver, midi_receiver, retired_sender, synth_model, capsules)?;
+Ok((stream, description, stream_config.sample_rate.0 as f32))
 }
 
+///host, device and config from the settings, default ones for everything not set
 pub fn host_device_setup(
-) -> Result<(cpal::Host, cpal::Device, cpal::SupportedStreamConfig), anyhow::Error> {
-    let host = cpal::default_host();
+    settings: &AudioSettings,
+) -> Result<(cpal::Host, cpal::Device, cpal::SupportedStreamConfig, cpal::StreamConfig), anyhow::Error> {
+    let host = match &settings.host {
+        None => cpal::default_host(),
+        Some(name) => {
+            let host_id = cpal::available_hosts()
+                .into_iter()
+                .find(|id| id.name().eq_ignore_ascii_case(name))
+                .ok_or_else(|| anyhow::anyhow!("Audio host '{name}' is not available"))?;
+            cpal::host_from_id(host_id)?
+        }
+    };
 
-    let device = host
-        .default_output_device()
-        .ok_or_else(|| anyhow::Error::msg("Default output device is not available"))?;
+    let device = match &settings.device {
+        None => host
+            .default_output_device()
+            .ok_or_else(|| anyhow::Error::msg("Default output device is not available"))?,
+        Some(name) => host
+            .output_devices()?
+            .find(|device| device.name().map(|x| x == *name).unwrap_or(false))
+            .ok_or_else(|| anyhow::anyhow!("Output device '{name}' is not available"))?,
+    };
     println!("Output device : {}", device.name()?);
 
-    let config = device.default_output_config()?;
-    println!("Default output config : {:?}", config);
+    let config = match settings.sample_rate {
+        None => device.default_output_config()?,
+        Some(sample_rate) => {
+            let default_config = device.default_output_config()?;
+            let candidates: Vec<cpal::SupportedStreamConfigRange> = device
+                .supported_output_configs()?
+                .filter(|range| {
+                    range.min_sample_rate().0 <= sample_rate && sample_rate <= range.max_sample_rate().0
+                })
+                .collect();
+            //keep the default sample format and channels, other ones only when the rate needs it
+            candidates
+                .iter()
+                .find(|range| {
+                    range.sample_format() == default_config.sample_format()
+                        && range.channels() == default_config.channels()
+                })
+                .or(candidates.first())
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Sample rate {sample_rate} Hz is not supported"))?
+                .with_sample_rate(cpal::SampleRate(sample_rate))
+        }
+    };
+    println!("Output config : {:?}", config);
+
+    let mut stream_config: cpal::StreamConfig = config.clone().into();
+    if let Some(buffer_size) = settings.buffer_size {
+        if let cpal::SupportedBufferSize::Range { min, max } = config.buffer_size() {
+            if buffer_size < *min || buffer_size > *max {
+                return Err(anyhow::anyhow!(
+                    "Buffer size {buffer_size} is not supported, use {min} to {max}"
+                ));
+            }
+        }
+        stream_config.buffer_size = cpal::BufferSize::Fixed(buffer_size);
+    }
+
+    Ok((host, device, config, stream_config))
+}
+
+pub fn describe_setup(host: &cpal::Host, device: &cpal::Device, config: &cpal::StreamConfig) -> String {
+    let buffer_size = match config.buffer_size {
+        cpal::BufferSize::Default => "default".to_string(),
+        cpal::BufferSize::Fixed(size) => size.to_string(),
+    };
+    format!(
+        "{} - {} - {} Hz - buffer {}",
+        host.id().name(),
+        device.name().unwrap_or("unknown device".to_string()),
+        config.sample_rate.0,
+        buffer_size
+    )
+}
 
-    Ok((host, device, config))
+///print every host and its output devices
+pub fn list_devices() -> Result<(), anyhow::Error> {
+    for host_id in cpal::available_hosts() {
+        println!("{}", host_id.name());
+        let host = cpal::host_from_id(host_id)?;
+        for device in host.output_devices()? {
+            println!("    {}", device.name()?);
+        }
+    }
+    Ok(())
+}
+
+const SAMPLE_RATES: [u32; 5] = [22050, 44100, 48000, 88200, 96000];
+const BUFFER_SIZES: [u32; 6] = [64, 128, 256, 512, 1024, 2048];
+
+///let the user pick the host, device, sample rate and buffer size in option menus
+pub fn select_audio_settings() -> Result<AudioSettings, anyhow::Error> {
+    let mut settings = AudioSettings::default();
+
+    let host_ids = cpal::available_hosts();
+    let host_names: Vec<String> = host_ids.iter().map(|id| id.name().to_string()).collect();
+    let host_id = match host_ids.len() {
+        1 => host_ids[0],
+        _ => host_ids[option_menu(host_names.clone(), "Select Audio Host".to_string())],
+    };
+    settings.host = Some(host_id.name().to_string());
+    let host = cpal::host_from_id(host_id)?;
+
+    let mut device_names = vec!["default".to_string()];
+    for device in host.output_devices()? {
+        device_names.push(device.name()?);
+    }
+    let selection = option_menu(device_names.clone(), "Select Output Device".to_string());
+    if selection > 0 {
+        settings.device = Some(device_names[selection].clone());
+    }
+    let (_, device, default_config, _) = host_device_setup(&settings)?;
+
+    //only propose the rates the device supports
+    let mut sample_rates = vec![];
+    for range in device.supported_output_configs()? {
+        for rate in SAMPLE_RATES {
+            if range.min_sample_rate().0 <= rate
+                && rate <= range.max_sample_rate().0
+                && !sample_rates.contains(&rate)
+            {
+                sample_rates.push(rate);
+            }
+        }
+    }
+    sample_rates.sort();
+    let mut options = vec![format!("default ({} Hz)", default_config.sample_rate().0)];
+    options.extend(sample_rates.iter().map(|rate| format!("{rate} Hz")));
+    let selection = option_menu(options, "Select Sample Rate".to_string());
+    if selection > 0 {
+        settings.sample_rate = Some(sample_rates[selection - 1]);
+    }
+
+    let buffer_sizes: Vec<u32> = match default_config.buffer_size() {
+        cpal::SupportedBufferSize::Range { min, max } => BUFFER_SIZES
+            .into_iter()
+            .filter(|size| min <= size && size <= max)
+            .collect(),
+        cpal::SupportedBufferSize::Unknown => BUFFER_SIZES.to_vec(),
+    };
+    let mut options = vec!["default".to_string()];
+    options.extend(buffer_sizes.iter().map(|size| size.to_string()));
+    let selection = option_menu(options, "Select Buffer Size".to_string());
+    if selection > 0 {
+        settings.buffer_size = Some(buffer_sizes[selection - 1]);
+    }
+
+    Ok(settings)
 }
 
 pub fn make_stream<T>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
-    interface_receiver: Receiver<ParameterUpdate>,
-    midi_receiver: Receiver<MidiMessage>,
-    mut synth_model: Box<dyn Synth>,
+    interface_receiver: QueueReceiver<EngineMessage>,
+    midi_receiver: QueueReceiver<TimedMidiMessage>,
+    retired_sender: QueueSender<Box<ModelSwap>>,
+    synth_model: Box<dyn Synth>,
+    capsules: Vec<ParameterCapsule>,
 ) -> Result<cpal::Stream, anyhow::Error>
 where
     T: SizedSample + FromSample<f32>,
 {
     let num_channels = config.channels as usize;
-
-    let mut synth = synth_model.init(config.sample_rate.0 as f32);
+    let mut engine = AudioEngine::new(
+        synth_model,
+        config.sample_rate.0 as f32,
+        interface_receiver,
+        midi_receiver,
+        retired_sender,
+        capsules,
+    );
     let err_fn = |err| eprintln!("Error building output sound stream: {}", err);
 
     let time_at_start = std::time::Instant::now();
@@ -67,17 +230,8 @@ where
     //create the audio stream
     let stream = device.build_output_stream(
         config,
-        //check for new parameter values
         move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
-            if let Ok((id, value)) = interface_receiver.try_recv() {
-                synth_model.set_parameter((id, value))
-            }
-            //check for new midi value
-            if let Ok(message) = midi_receiver.try_recv() {
-                synth_model.set_note(message);
-            }
-            //process buffer
-            process_frame(output, &mut synth_model, num_channels)
+            engine.process(output, num_channels, Instant::now())
         },
         err_fn,
         None,
@@ -85,17 +239,3 @@ where
 
     Ok(stream)
 }
-
-fn process_frame<SampleType>(output: &mut [SampleType], synth_model: &mut Box<dyn Synth>, num_channels: usize)
-where
-    SampleType: Sample + FromSample<f32>,
-{
-    for frame in output.chunks_mut(num_channels) {
-        let value: SampleType = SampleType::from_sample(synth_model.process());
-
-        // copy the same value to all channels
-        for sample in frame.iter_mut() {
-            *sample = value;
-        }
-    }
-}
@@ -6,6 +6,18 @@ enum InterpolationMode {
     Allpass,
 }
 
+/// Linear interpolation of `samples` at a fractional `position`, wrapping around,
+/// shared by the ring buffer and the sampler
+pub fn interpolate(samples: &[f32], position: f32) -> f32 {
+    let length = samples.len();
+    let position = position.rem_euclid(length as f32);
+    let index = position.floor() as usize % length;
+    let frac = position - position.floor();
+    // S[n]=frac * Buf[i+1]+(1-frac)*Buf[i]
+    frac * samples[(index + 1) % length] + (1. - frac) * samples[index]
+}
+
+///Circular buffer read at an interpolated position, the memory of the delays, chorus and looper
 // #[derive(Copy, Clone)]
 pub struct RingBuffer {
     interpolation_mode: InterpolationMode,
@@ -64,17 +76,16 @@ impl RingBuffer {
         self.size_goal= (buffer_size / 2) as i32;
     }
 
+    ///silence, the pointers stay
+    pub fn clear(&mut self) {
+        self.buffer.fill(0.);
+        self.output_sample = 0.;
+    }
+
     /// @brief increment pointer and set its int, incremented int and frac value
     fn increment_read_pointer(&mut self) {
         self.read += self.step_size;
         self.check_for_read_index_overflow();
-        if self.read > self.buffer_size as f32 {
-            self.read -= self.buffer_size as f32
-        }
-        // in case of reverse read
-        else if self.read < 0. {
-            self.read += self.buffer_size as f32
-        }
     }
 
     /// increment read pointer and return sample from interpolation
@@ -113,15 +124,13 @@ impl RingBuffer {
 
     /// Interpolation lineaire du buffer a un index flottant donne
     fn linear_interpolation(&mut self) {
-        // S[n]=frac * Buf[i+1]+(1-frac)*Buf[i]
-        self.output_sample = (self.frac * self.buffer[self.i_read_next as usize])
-            + ((1. - self.frac) * self.buffer[self.i_read as usize]);
+        self.output_sample = interpolate(&self.buffer, self.read);
     }
 
     /// Interpolation passe-tout, recursion
     fn allpass_interpolation(&mut self) {
         // S[n]=Buf[i+1]+(1-frac)*Buf[i]-(1-frac)*S[n-1]
-        self.output_sample = (self.buffer[(self.i_read + 1) as usize])
+        self.output_sample = (self.buffer[self.i_read_next as usize])
             + ((1. - self.frac) * self.buffer[(self.i_read) as usize])
             - ((1. - self.frac) * self.output_sample);
     }
@@ -140,6 +149,17 @@ impl RingBuffer {
         }
     }
 
+    /// Linear interpolation `delay` samples behind the last written one,
+    /// so any number of readers can share the buffer.
+    pub fn read_behind(&self, delay: f32) -> f32 {
+        interpolate(&self.buffer, self.write - delay)
+    }
+
+    /// number of samples the buffer holds
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
     pub fn set_step_size(&mut self, step_size: f32) {
         self.step_size = step_size;
     }
@@ -189,6 +209,15 @@ impl RingBuffer {
         self.size_goal = (delay_in_samples.clamp(4, self.buffer_size as i32 - 4)) as i32;
     }
 
+    /// Fractional delay in samples, reached at once instead of gliding like
+    /// `set_delay_time`. Reading then writing each sample gives exactly this delay.
+    pub fn set_delay_in_samples(&mut self, delay: f32) {
+        let delay = delay.clamp(1., (self.buffer_size - 4) as f32);
+        self.read = (self.write - delay).rem_euclid(self.buffer.len() as f32);
+        self.actual_size = delay;
+        self.size_goal = delay.round() as i32;
+    }
+
     pub fn set_sample_rate(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate;
     }
@@ -205,12 +234,14 @@ impl RingBuffer {
         self.step_size = self.size_on_freeze / self.size_goal as f32;
     }
 
+    //the read pointer wraps on the whole buffer like the write one, so the delay doesn't drift
     fn check_for_read_index_overflow(&mut self) {
+        let length = self.buffer.len() as f32;
         if self.read < 0. {
-            self.read += self.buffer_size as f32;
+            self.read += length;
         }
-        if self.read > self.buffer_size as f32 {
-            self.read -= self.buffer_size as f32;
+        if self.read >= length {
+            self.read -= length;
         }
     }
 
@@ -220,7 +251,7 @@ impl RingBuffer {
         // get fraction
         self.frac = self.read - (self.i_read as f32);
         // Get next sample
-        self.i_read_next = (self.i_read + 1) % (self.buffer_size - 1);
+        self.i_read_next = (self.i_read + 1) % self.buffer.len() as i32;
     }
 
     fn freeze_increment_read_pointer(&mut self) {
@@ -252,6 +283,7 @@ pub enum DelayMode {
     //basic feedback
     Comb,
 }
+///Feedback delay, comb or allpass, used by the delay effect and the reverb
 // #[derive(Clone, Copy)]
 pub struct DelayLine {
     buffer: RingBuffer,
@@ -308,6 +340,12 @@ impl DelayLine {
         self.buffer.set_delay_time(delay_time * 1000.);
     }
 
+    ///fractional, without glide, for tuned delays as in a plucked string
+    pub fn set_delay_in_samples(&mut self, delay: f32) {
+        self.delay_time = delay / self.buffer.sample_rate;
+        self.buffer.set_delay_in_samples(delay);
+    }
+
     pub fn set_feedback(&mut self, feedback: f32) {
         self.feedback = feedback;
     }
@@ -315,4 +353,45 @@ impl DelayLine {
     pub fn set_freeze(&mut self, freeze: bool) {
         self.buffer.set_freezed(freeze)
     }
-}
\ No newline at end of file
+
+    pub fn clear(&mut self) {
+        self.buffer.clear()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_behind() {
+        let mut buffer = RingBuffer::new(0.001);
+        buffer.init(48000.);
+        for i in 0..100 {
+            buffer.write_sample(i as f32);
+        }
+        assert_eq!(buffer.read_behind(0.), 99.);
+        assert_eq!(buffer.read_behind(10.5), 88.5);
+        buffer.set_freezed(true);
+        buffer.write_sample(-1.);
+        assert_eq!(buffer.read_behind(0.), 99.);
+    }
+
+    #[test]
+    fn fractional_delay() {
+        let mut delay = DelayLine::new(0.01, DelayMode::Comb);
+        delay.init(48000.);
+        delay.set_feedback(0.);
+        delay.set_delay_in_samples(10.25);
+        //an impulse comes out between the 10th and 11th sample, more on the 10th
+        let output: Vec<f32> = (0..2000)
+            .map(|i| delay.process(if i % 500 == 0 { 1. } else { 0. }))
+            .collect();
+        //several times around the buffer, the delay doesn't drift
+        for start in [0, 500, 1000, 1500] {
+            assert_eq!(output[start + 10], 0.75);
+            assert_eq!(output[start + 11], 0.25);
+        }
+        assert_eq!(output.iter().sum::<f32>(), 4.);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::Lfo;
 use crate::RingBuffer;
+///Ring buffer read at a position modulated by an `Lfo`
 pub struct Chorus {
     lfo: Lfo,
     buffer: RingBuffer,
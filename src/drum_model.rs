@@ -0,0 +1,430 @@
+use crate::envelope;
+use crate::envelope::Envelope;
+use crate::filter::FilterType;
+use crate::midi::MidiMessage;
+use crate::oscillator::{BlepWaveform, PolyBlepOscillator};
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::Biquad;
+use crate::ParameterUpdate;
+use crate::SineWave;
+use num_derive::FromPrimitive;
+
+const NUMBER_OF_DRUMS: usize = 8;
+//kick, snare, hats, clap and toms, the two hats and the three toms share their parameters
+const NUMBER_OF_GROUPS: usize = 5;
+const NUMBER_OF_DRUM_PARAM: usize = 3;
+const NB_SYNTH_PARAM: usize = 1 + NUMBER_OF_GROUPS * NUMBER_OF_DRUM_PARAM;
+//drum parameters ids start after the volume
+const FIRST_DRUM_ID: i32 = 1;
+
+//in ms, the three first hits of the clap
+const CLAP_BURST_TIME: f32 = 10.;
+const CLAP_BURSTS: usize = 3;
+//frequencies of the six square waves of the 808 hats
+const HAT_FREQUENCIES: [f32; 6] = [205.3, 304.4, 369.6, 522.7, 540., 800.];
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum DrumModelParamID {
+    Volume,
+}
+
+///the parameters of each group, their id is `FIRST_DRUM_ID + group * 3 + this`
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)]
+pub enum DrumParamID {
+    Decay,
+    Tune,
+    Tone,
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum DrumKind {
+    Kick,
+    Snare,
+    Clap,
+    ClosedHat,
+    OpenHat,
+    LowTom,
+    MidTom,
+    HighTom,
+}
+
+const DRUMS: [DrumKind; NUMBER_OF_DRUMS] = [
+    DrumKind::Kick,
+    DrumKind::Snare,
+    DrumKind::Clap,
+    DrumKind::ClosedHat,
+    DrumKind::OpenHat,
+    DrumKind::LowTom,
+    DrumKind::MidTom,
+    DrumKind::HighTom,
+];
+
+///the general midi drum notes, in every octave, from C
+pub const NOTE_MAP: [DrumKind; 12] = [
+    DrumKind::Kick,
+    DrumKind::Snare,
+    DrumKind::Snare,
+    DrumKind::Clap,
+    DrumKind::Snare,
+    DrumKind::LowTom,
+    DrumKind::ClosedHat,
+    DrumKind::LowTom,
+    DrumKind::ClosedHat,
+    DrumKind::MidTom,
+    DrumKind::OpenHat,
+    DrumKind::HighTom,
+];
+
+impl DrumKind {
+    fn group(&self) -> usize {
+        match self {
+            DrumKind::Kick => 0,
+            DrumKind::Snare => 1,
+            DrumKind::ClosedHat | DrumKind::OpenHat => 2,
+            DrumKind::Clap => 3,
+            DrumKind::LowTom | DrumKind::MidTom | DrumKind::HighTom => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct DrumSettings {
+    //in ms
+    decay: f32,
+    //frequency ratio
+    tune: f32,
+    tone: f32,
+}
+
+///one drum, retriggered by its notes
+struct DrumVoice {
+    kind: DrumKind,
+    envelope: Envelope,
+    //pitch drop of the kick and toms, body of the snare
+    pitch_envelope: Envelope,
+    oscillator: SineWave,
+    metal: [PolyBlepOscillator; 6],
+    noise: PolyBlepOscillator,
+    filter: Biquad,
+    //samples since the trigger, for the bursts of the clap
+    age: usize,
+    sample_rate: f32,
+}
+
+impl DrumVoice {
+    fn new(index: usize) -> Self {
+        let kind = DRUMS[index];
+        let mut noise = PolyBlepOscillator::new();
+        noise.waveform = BlepWaveform::Noise;
+        noise.set_seed(index as u32);
+        let mut metal = [PolyBlepOscillator::new(); 6];
+        metal.iter_mut().for_each(|x| x.waveform = BlepWaveform::Pulse);
+        let filter_type = match kind {
+            DrumKind::Snare | DrumKind::ClosedHat | DrumKind::OpenHat => FilterType::HPF,
+            DrumKind::Clap => FilterType::BPF,
+            _ => FilterType::LPF,
+        };
+        DrumVoice {
+            kind,
+            envelope: Envelope::new(),
+            pitch_envelope: Envelope::new(),
+            oscillator: SineWave::new(),
+            metal,
+            noise,
+            filter: Biquad::new(filter_type),
+            age: 0,
+            sample_rate: 0.,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.envelope.init(sample_rate);
+        self.pitch_envelope.init(sample_rate);
+        self.oscillator.init(sample_rate);
+        self.metal.iter_mut().for_each(|x| x.init(sample_rate));
+        self.noise.init(sample_rate);
+        self.filter.init(sample_rate);
+    }
+
+    ///base frequency of the tonal drums
+    fn frequency(&self, settings: &DrumSettings) -> f32 {
+        settings.tune
+            * match self.kind {
+                DrumKind::Kick => 50.,
+                DrumKind::Snare => 185.,
+                DrumKind::LowTom => 80.,
+                DrumKind::MidTom => 110.,
+                DrumKind::HighTom => 150.,
+                _ => 1000.,
+            }
+    }
+
+    fn configure(&mut self, settings: &DrumSettings) {
+        let decay = match self.kind {
+            DrumKind::OpenHat => settings.decay * 6.,
+            _ => settings.decay,
+        };
+        self.envelope.set_decay(decay);
+        let pitch_decay = match self.kind {
+            DrumKind::Kick => 10. + 90. * settings.tone,
+            DrumKind::Snare => decay * 0.5,
+            _ => 60.,
+        };
+        self.pitch_envelope.set_decay(pitch_decay);
+
+        let frequency = self.frequency(settings);
+        match self.kind {
+            DrumKind::Snare => self
+                .filter
+                .set_frequence_and_resonance(1500. * settings.tune, 0.7),
+            DrumKind::ClosedHat | DrumKind::OpenHat => {
+                for (oscillator, frequency) in self.metal.iter_mut().zip(HAT_FREQUENCIES) {
+                    oscillator.frequency_hz = frequency * settings.tune;
+                }
+                //the tone opens from 4 to 16kHz
+                self.filter
+                    .set_frequence_and_resonance(4000. * 4_f32.powf(settings.tone), 0.7)
+            }
+            DrumKind::Clap => self
+                .filter
+                .set_frequence_and_resonance(frequency * (1. + 1.5 * settings.tone), 2.),
+            _ => self
+                .filter
+                .set_frequence_and_resonance(frequency * 8., 0.7),
+        }
+    }
+
+    fn trigger(&mut self, settings: &DrumSettings) {
+        self.configure(settings);
+        self.envelope.trigger();
+        self.pitch_envelope.trigger();
+        self.age = 0;
+    }
+
+    fn process(&mut self, settings: &DrumSettings) -> f32 {
+        //squared, closer to the exponential decay of a real drum
+        let level = self.envelope.process().powi(2);
+        let pitch = self.pitch_envelope.process().powi(2);
+        let frequency = self.frequency(settings);
+        self.age += 1;
+        match self.kind {
+            DrumKind::Kick => {
+                self.oscillator.frequency_hz = frequency * (1. + (1. + 5. * settings.tone) * pitch);
+                self.oscillator.process() * level
+            }
+            DrumKind::Snare => {
+                self.oscillator.frequency_hz = frequency * (1. + 0.5 * pitch);
+                let body = self.oscillator.process() * pitch * (1. - settings.tone);
+                let noise = self.filter.process(self.noise.process()) * level * settings.tone;
+                body + noise
+            }
+            DrumKind::ClosedHat | DrumKind::OpenHat => {
+                let metal = self.metal.iter_mut().map(|x| x.process()).sum::<f32>() / 6.;
+                let hat = metal + self.noise.process() * 0.5;
+                self.filter.process(hat) * level
+            }
+            DrumKind::Clap => {
+                let burst_length = (CLAP_BURST_TIME / 1000. * self.sample_rate) as usize;
+                let amplitude = if self.age < burst_length * CLAP_BURSTS {
+                    1. - (self.age % burst_length) as f32 / burst_length as f32
+                } else {
+                    level
+                };
+                self.filter.process(self.noise.process()) * amplitude
+            }
+            DrumKind::LowTom | DrumKind::MidTom | DrumKind::HighTom => {
+                self.oscillator.frequency_hz = frequency * (1. + 0.6 * pitch);
+                let noise = self.filter.process(self.noise.process()) * pitch * settings.tone;
+                (self.oscillator.process() + noise) * level
+            }
+        }
+    }
+}
+
+pub struct DrumModel {
+    voices: [DrumVoice; NUMBER_OF_DRUMS],
+    settings: [DrumSettings; NUMBER_OF_GROUPS],
+    volume: f32,
+}
+
+impl HasDescription for DrumModel {
+    const NAME: &'static str = "Drums";
+    const DESCRIPTION: &'static str = "kick, snare, clap, hats and toms on the general midi notes of every octave";
+}
+
+impl HasConstructor for DrumModel {
+    fn new() -> Self {
+        DrumModel {
+            voices: std::array::from_fn(DrumVoice::new),
+            settings: [DrumSettings {
+                decay: 200.,
+                tune: 1.,
+                tone: 0.5,
+            }; NUMBER_OF_GROUPS],
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.voices.iter_mut().for_each(|x| x.init(sample_rate));
+    }
+}
+
+impl HasMidiInput for DrumModel {
+    ///the drums ring on their own, note offs are ignored
+    fn set_note(&mut self, message: MidiMessage) {
+        if let MidiMessage::NoteOn(midi_note) = message {
+            let kind = NOTE_MAP[midi_note as usize % 12];
+            if let Some(voice) = self.voices.iter_mut().find(|voice| voice.kind == kind) {
+                voice.trigger(&self.settings[kind.group()]);
+            }
+        }
+    }
+}
+
+impl HasEngine for DrumModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.fill(0.);
+        for voice in self.voices.iter_mut() {
+            let settings = &self.settings[voice.kind.group()];
+            for sample in left.iter_mut() {
+                if voice.envelope.status == envelope::Segment::Off {
+                    break;
+                }
+                *sample += voice.process(settings);
+            }
+        }
+        for sample in left.iter_mut() {
+            //vca
+            *sample *= self.volume;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for DrumModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = DrumModelParamID;
+        type DP = DrumParamID;
+        type P = ParameterCapsule;
+
+        let mut capsules = vec![P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.)];
+        //decay, tune and tone of each group on three keys
+        let groups = [
+            ("kick", ['q', 'w', 'e'], 16, 10),
+            ("snare", ['r', 't', 'y'], 11, 17),
+            ("hats", ['u', 'i', 'o'], 7, 17),
+            ("clap", ['a', 's', 'd'], 12, 10),
+            ("toms", ['f', 'g', 'h'], 16, 5),
+        ];
+        for (group, (name, ccs, decay, tone)) in groups.iter().enumerate() {
+            let id = |param: DrumParamID| drum_param_id(group, param);
+            let name = |param: &str| format!("{}-{}", name, param);
+            capsules.extend([
+                //in ms
+                P::new(id(DP::Decay), &name("dcy"), *decay, ccs[0], 10., 2000., 2.),
+                //in semitones
+                P::new(id(DP::Tune), &name("tune"), 17, ccs[1], -17., 18., 1.),
+                P::new(id(DP::Tone), &name("tone"), *tone, ccs[2], 0., 1., 1.),
+            ]);
+        }
+
+        let params = Parameters {
+            capsules,
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = DrumModelParamID;
+        if id >= FIRST_DRUM_ID {
+            return self.set_drum_parameter(id - FIRST_DRUM_ID, new_value);
+        }
+        let typed_id: DrumModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+        }
+    }
+}
+
+fn drum_param_id(group: usize, param: DrumParamID) -> i32 {
+    FIRST_DRUM_ID + (group * NUMBER_OF_DRUM_PARAM) as i32 + param as i32
+}
+
+impl DrumModel {
+    ///`id` counted from the first drum parameter
+    fn set_drum_parameter(&mut self, id: i32, new_value: f32) {
+        type DP = DrumParamID;
+        let group = id as usize / NUMBER_OF_DRUM_PARAM;
+        let typed_id: DrumParamID =
+            num::FromPrimitive::from_i32(id % NUMBER_OF_DRUM_PARAM as i32).unwrap();
+        let settings = &mut self.settings[group];
+        match typed_id {
+            DP::Decay => settings.decay = new_value,
+            DP::Tune => settings.tune = 2_f32.powf(new_value.round() / 12.),
+            DP::Tone => settings.tone = new_value,
+        }
+        for voice in self.voices.iter_mut() {
+            if voice.kind.group() == group {
+                voice.configure(settings);
+            }
+        }
+    }
+}
+
+impl Synth for DrumModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{model, render};
+
+    #[test]
+    fn every_drum_is_a_one_shot() {
+        let mut model: DrumModel = model();
+        assert_eq!(model.get_parameters().capsules.len(), NB_SYNTH_PARAM);
+        for (pitch_class, kind) in NOTE_MAP.iter().enumerate() {
+            let midi_note = 36 + pitch_class as u8;
+            model.set_note(MidiMessage::NoteOn(midi_note));
+            model.set_note(MidiMessage::NoteOff(midi_note));
+            let output = render(&mut model, 2400);
+            assert!(
+                output.iter().any(|sample| sample.abs() > 0.01),
+                "{:?} is silent",
+                kind
+            );
+        }
+        //every decay is over after a few seconds
+        render(&mut model, 48000 * 13);
+        assert!(model
+            .voices
+            .iter()
+            .all(|voice| voice.envelope.status == envelope::Segment::Off));
+    }
+
+    #[test]
+    fn parameters_of_a_group() {
+        let mut model: DrumModel = model();
+        let short = drum_param_id(0, DrumParamID::Decay);
+        model.set_parameter((short, 50.));
+        model.set_note(MidiMessage::NoteOn(36));
+        render(&mut model, 2400);
+        assert!(model.voices[0].envelope.status == envelope::Segment::Off);
+
+        //the toms share their tune
+        model.set_parameter((drum_param_id(4, DrumParamID::Tune), 12.));
+        assert_eq!(model.settings[4].tune, 2.);
+        assert_eq!(model.voices[7].frequency(&model.settings[4]), 300.);
+    }
+}
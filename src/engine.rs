@@ -0,0 +1,232 @@
+use crate::midi::{cc_value_to_orca, MidiMessage, TimedMidiMessage};
+use crate::model_switch::ModelSwap;
+use crate::outils;
+use crate::parameters::ParameterCapsule;
+use crate::queue::{QueueReceiver, QueueSender};
+use crate::synth::{Synth, MAXIMUM_BLOCK_SIZE};
+use crate::textparsing::PhonemeSequence;
+use crate::ParameterUpdate;
+use dasp_sample::{FromSample, Sample};
+use std::time::Instant;
+
+///Messages from the UI thread, in one ring so parameters never reach the wrong model
+pub enum EngineMessage {
+    Parameter(ParameterUpdate),
+    ///the new model, the old one is faded out and sent back in the box
+    SwapModel(Box<ModelSwap>),
+    ///phonemes to sing, parsed outside of the audio thread
+    Text(PhonemeSequence),
+}
+
+///length of the crossfade between two models, in seconds
+const MODEL_CROSSFADE_TIME: f32 = 0.02;
+
+///Everything the audio callback does, apart from the device.
+///Driven by the cpal stream, or by a `NullBackend` in the tests.
+pub struct AudioEngine {
+    synth_model: Box<dyn Synth>,
+    sample_rate: f32,
+    interface_receiver: QueueReceiver<EngineMessage>,
+    midi_receiver: QueueReceiver<TimedMidiMessage>,
+    retired_sender: QueueSender<Box<ModelSwap>>,
+    capsules: Vec<ParameterCapsule>,
+    left_block: Vec<f32>,
+    right_block: Vec<f32>,
+    //previous model, while it fades out
+    fading: Option<Box<ModelSwap>>,
+    fade_position: usize,
+    fade_length: usize,
+    fade_left_block: Vec<f32>,
+    fade_right_block: Vec<f32>,
+    //next model, waiting for the fade to end so nothing is cut
+    queued: Option<Box<ModelSwap>>,
+}
+
+impl AudioEngine {
+    ///init the model, `capsules` are its parameters, used to map the incoming CC.
+    ///Faded out models are sent back through `retired_sender`, to be dropped outside of the audio thread
+    pub fn new(
+        mut synth_model: Box<dyn Synth>,
+        sample_rate: f32,
+        interface_receiver: QueueReceiver<EngineMessage>,
+        midi_receiver: QueueReceiver<TimedMidiMessage>,
+        retired_sender: QueueSender<Box<ModelSwap>>,
+        capsules: Vec<ParameterCapsule>,
+    ) -> Self {
+        synth_model.init(sample_rate);
+        AudioEngine {
+            synth_model,
+            sample_rate,
+            interface_receiver,
+            midi_receiver,
+            retired_sender,
+            capsules,
+            //allocated here so the audio callback never has to
+            left_block: vec![0.; MAXIMUM_BLOCK_SIZE],
+            right_block: vec![0.; MAXIMUM_BLOCK_SIZE],
+            fading: None,
+            fade_position: 0,
+            fade_length: (sample_rate * MODEL_CROSSFADE_TIME) as usize,
+            fade_left_block: vec![0.; MAXIMUM_BLOCK_SIZE],
+            fade_right_block: vec![0.; MAXIMUM_BLOCK_SIZE],
+            queued: None,
+        }
+    }
+
+    ///swap the boxed model with the playing one, and start fading the old one out.
+    ///During a fade the model waits its turn, only the last one asked for is kept
+    fn swap_model(&mut self, mut swap: Box<ModelSwap>) {
+        if self.fading.is_some() {
+            //never played, it can go without a click
+            if let Some(skipped) = self.queued.replace(swap) {
+                self.retire(skipped);
+            }
+            return;
+        }
+        std::mem::swap(&mut self.synth_model, &mut swap.synth_model);
+        std::mem::swap(&mut self.capsules, &mut swap.capsules);
+        self.fading = Some(swap);
+        self.fade_position = 0;
+    }
+
+    fn retire(&mut self, model: Box<ModelSwap>) {
+        //if the ring is full the model is dropped here, it should never happen
+        let _ = self.retired_sender.send(model);
+    }
+
+    ///mix the fading model in the blocks, and retire it once it's silent
+    fn crossfade(&mut self, frames: usize) {
+        let Some(fading) = &mut self.fading else {
+            return;
+        };
+        let (fade_left, fade_right) = (
+            &mut self.fade_left_block[..frames],
+            &mut self.fade_right_block[..frames],
+        );
+        fading.synth_model.process_block(fade_left, fade_right);
+        for i in 0..frames {
+            let new_gain = ((self.fade_position + i) as f32 / self.fade_length as f32).min(1.);
+            self.left_block[i] =
+                outils::equal_power_crossfade(fade_left[i], self.left_block[i], new_gain);
+            self.right_block[i] =
+                outils::equal_power_crossfade(fade_right[i], self.right_block[i], new_gain);
+        }
+        self.fade_position += frames;
+        if self.fade_position >= self.fade_length {
+            if let Some(fading) = self.fading.take() {
+                self.retire(fading);
+            }
+            if let Some(queued) = self.queued.take() {
+                self.swap_model(queued);
+            }
+        }
+    }
+
+    ///fill an interleaved buffer, `callback_time` place the midi messages inside it
+    pub fn process<SampleType>(
+        &mut self,
+        output: &mut [SampleType],
+        num_channels: usize,
+        callback_time: Instant,
+    ) where
+        SampleType: Sample + FromSample<f32>,
+    {
+        //check for new parameter values
+        while let Some(message) = self.interface_receiver.try_recv() {
+            match message {
+                EngineMessage::Parameter((id, value)) => self.synth_model.set_parameter((id, value)),
+                EngineMessage::SwapModel(swap) => self.swap_model(swap),
+                EngineMessage::Text(phonemes) => self.synth_model.set_text(&phonemes),
+            }
+        }
+        //check for new midi values, and process the buffer up to each of them
+        let frames = output.len() / num_channels;
+        let mut position = 0;
+        while let Some((message, time)) = self.midi_receiver.try_recv() {
+            let offset = event_offset(time, callback_time, frames, self.sample_rate);
+            if offset > position {
+                self.process_frame(
+                    &mut output[position * num_channels..offset * num_channels],
+                    num_channels,
+                );
+                position = offset;
+            }
+            self.apply_midi_message(message);
+        }
+        //process the rest of the buffer
+        self.process_frame(&mut output[position * num_channels..], num_channels)
+    }
+
+    ///notes go to the model, CC are mapped to the parameter with the same ORCA cc
+    fn apply_midi_message(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::ControlChange(cc, midi_value) => {
+                //if cc is not bounded, do nothing
+                for capsule in self.capsules.iter_mut() {
+                    if outils::get_orca_integer(capsule.parameter.midicc).unwrap_or(0) == cc {
+                        capsule.parameter.value = cc_value_to_orca(midi_value);
+                        self.synth_model
+                            .set_parameter((capsule.id, capsule.parameter.get_raw_value()));
+                    }
+                }
+            }
+            _ => self.synth_model.set_note(message),
+        }
+    }
+
+    fn process_frame<SampleType>(&mut self, output: &mut [SampleType], num_channels: usize)
+    where
+        SampleType: Sample + FromSample<f32>,
+    {
+        for chunk in output.chunks_mut(num_channels * self.left_block.len()) {
+            let frames = chunk.len() / num_channels;
+            self.synth_model
+                .process_block(&mut self.left_block[..frames], &mut self.right_block[..frames]);
+            self.crossfade(frames);
+
+            for (i, frame) in chunk.chunks_mut(num_channels).enumerate() {
+                let (left, right) = (self.left_block[i], self.right_block[i]);
+
+                // mono device get the sum, others get left on even channels and right on odd ones
+                if num_channels == 1 {
+                    frame[0] = SampleType::from_sample((left + right) * 0.5);
+                    continue;
+                }
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    *sample = match channel % 2 {
+                        0 => SampleType::from_sample(left),
+                        _ => SampleType::from_sample(right),
+                    };
+                }
+            }
+        }
+    }
+}
+
+///Events are played one buffer after they were received, at the same distance from each other.
+///Events older than a buffer go at the start, so nothing is ever dropped.
+fn event_offset(time: Instant, callback_time: Instant, frames: usize, sample_rate: f32) -> usize {
+    let age = callback_time.saturating_duration_since(time).as_secs_f32();
+    let offset = frames as f32 - age * sample_rate;
+    offset.clamp(0., frames.saturating_sub(1) as f32) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::event_offset;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn offsets() {
+        let callback_time = Instant::now();
+        let sample_rate = 1000.;
+        //buffer of 100 ms, events are one buffer late
+        let at = |ms: u64| callback_time - Duration::from_millis(ms);
+        assert_eq!(event_offset(at(100), callback_time, 100, sample_rate), 0);
+        assert_eq!(event_offset(at(60), callback_time, 100, sample_rate), 40);
+        assert_eq!(event_offset(at(10), callback_time, 100, sample_rate), 90);
+        //late events go at the start, early ones at the end
+        assert_eq!(event_offset(at(500), callback_time, 100, sample_rate), 0);
+        assert_eq!(event_offset(callback_time + Duration::from_millis(5), callback_time, 100, sample_rate), 99);
+    }
+}
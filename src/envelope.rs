@@ -3,6 +3,8 @@ use crate::outils::convert_ms_to_sample;
 //in milliseconds
 pub static MAXIMUM_ENVELOPE_TIME: f32 = 10000.;
 pub static MINIMUM_ENVELOPE_TIME: f32 = 10.;
+//fade out of a stolen voice
+pub static STEAL_TIME: f32 = 5.;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum Segment {
@@ -11,6 +13,8 @@ pub enum Segment {
     Release,
     Off,
 }
+///Linear attack, sustain, release envelope, gated by `note_on` and `note_off`,
+///or a one shot decay started by `trigger`
 #[derive(Clone, Copy)]
 pub struct Envelope {
     value: f32,
@@ -19,9 +23,17 @@ pub struct Envelope {
     sample_rate: f32,
     increment: f32,
     decrement: f32,
+    steal_decrement: f32,
+    stealing: bool,
 }
 
 
+impl Default for Envelope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Envelope {
     pub fn new() -> Self {
         Self {
@@ -29,12 +41,15 @@ impl Envelope {
             status: Off,
             increment: 0.001,
             decrement: 0.001,
+            steal_decrement: 0.01,
+            stealing: false,
             sample_rate: 0.0,
         }
     }
 
     pub fn init(&mut self, sample_rate: f32){
         self.sample_rate = sample_rate;
+        self.steal_decrement = 1. / convert_ms_to_sample(STEAL_TIME, sample_rate);
     }
 
     ///time in ms
@@ -60,7 +75,19 @@ impl Envelope {
             _ => {}
         }
     }
+    ///time in ms of the one shot decay, it is the release segment
+    pub fn set_decay(&mut self, time: f32) {
+        self.set_release(time)
+    }
+
+    ///one shot for percussions : jump to full level then decay, no gate to hold
+    pub fn trigger(&mut self) {
+        self.value = 1.;
+        self.status = Release
+    }
+
     pub fn note_on(&mut self){
+        self.stealing = false;
         self.status = Attack
     }
 
@@ -68,6 +95,18 @@ impl Envelope {
         self.status = Release
     }
 
+    ///quick fade out of a voice given to another note, it is Off once silent
+    pub fn steal(&mut self){
+        if self.status != Off {
+            self.stealing = true;
+            self.status = Release
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
     pub fn process(&mut self) -> f32 {
         match self.status {
             Off => {}
@@ -79,8 +118,9 @@ impl Envelope {
             }
             Sustain => {}
             Release => {
-                self.value -= self.decrement;
+                self.value -= if self.stealing { self.steal_decrement } else { self.decrement };
                 if self.value <= 0. {
+                    self.stealing = false;
                     self.status = Segment::Off
                 }
             }
@@ -89,3 +129,98 @@ impl Envelope {
         // .powf(0.7);
     }
 }
+
+///The steal fade of the voices without an envelope, ex: a ringing string given to another note
+#[derive(Clone, Copy)]
+pub struct StealFade {
+    gain: f32,
+    decrement: f32,
+    length: usize,
+}
+
+impl Default for StealFade {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StealFade {
+    pub fn new() -> Self {
+        StealFade {
+            gain: 1.,
+            decrement: 0.,
+            length: 1,
+        }
+    }
+
+    pub fn init(&mut self, sample_rate: f32) {
+        self.length = convert_ms_to_sample(STEAL_TIME, sample_rate) as usize;
+    }
+
+    ///back to full level, for the next note
+    pub fn reset(&mut self) {
+        self.gain = 1.;
+        self.decrement = 0.;
+    }
+
+    ///faded since the last reset, what the voice holds is the stolen note
+    pub fn is_stolen(&self) -> bool {
+        self.decrement > 0.
+    }
+
+    ///start fading, silent after `length` samples
+    pub fn start(&mut self) -> usize {
+        self.decrement = 1. / self.length as f32;
+        self.length
+    }
+
+    pub fn process(&mut self) -> f32 {
+        self.gain = (self.gain - self.decrement).max(0.);
+        self.gain
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn one_shot() {
+        let mut envelope = Envelope::new();
+        envelope.init(1000.);
+        envelope.set_decay(100.);
+        envelope.trigger();
+        let first = envelope.process();
+        assert!(first > 0.98 && first < 1.);
+        //the note off of a held key doesn't cut it
+        envelope.note_off();
+        for _ in 0..98 {
+            envelope.process();
+        }
+        assert!(envelope.status == Release);
+        envelope.process();
+        envelope.process();
+        assert!(envelope.status == Off);
+    }
+
+    #[test]
+    fn stolen() {
+        let mut envelope = Envelope::new();
+        envelope.init(1000.);
+        envelope.set_release(1000.);
+        envelope.note_on();
+        for _ in 0..100 {
+            envelope.process();
+        }
+        //faded out in STEAL_TIME instead of the release time
+        envelope.steal();
+        for _ in 0..5 {
+            envelope.process();
+        }
+        assert!(envelope.status == Off);
+        envelope.note_on();
+        envelope.note_off();
+        envelope.process();
+        assert!(envelope.status == Off);
+    }
+}
@@ -5,6 +5,7 @@ pub enum FilterType {
     PEAK,
 }
 
+///Two poles two zeros filter (RBJ cookbook), low-pass, high-pass, band-pass or peak
 pub struct Biquad {
     //Parameters
     frequency_cutoff: f32,
@@ -12,6 +13,7 @@ pub struct Biquad {
     resonance: f32,
     peak_gain: f32,
     modulation: f32,
+    resonance_feedback: bool,
 
     //Computation variable
     sample_rate: f32,
@@ -45,6 +47,7 @@ impl Biquad {
             sinomega: 0.,
             alpha: 0.,
             modulation: 0.,
+            resonance_feedback: true,
         }
     }
 
@@ -141,6 +144,13 @@ impl Biquad {
 
     pub fn set_type(&mut self, filter_type: FilterType) {
         self.filter_type = filter_type;
+        self.compute_coef();
+    }
+
+    ///forget the past samples, the coefficients stay
+    pub fn reset(&mut self) {
+        self.a = [0.; 3];
+        self.b = [0.; 3];
     }
 
     pub fn get_type(self) -> FilterType {
@@ -170,7 +180,11 @@ impl Biquad {
     }
 
     pub fn modulate(&mut self, modulation: f32) {
+        if self.modulation == modulation {
+            return;
+        }
         self.modulation = modulation;
+        self.compute_coef();
     }
 
     pub fn set_frequency(&mut self, frequence: f32) {
@@ -181,22 +195,30 @@ impl Biquad {
         self.compute_coef();
     }
 
+    ///The output fed back to the input makes a resonant filter self oscillate around Q = 38.
+    ///Resonators ringing at very high Q turn it off.
+    pub fn set_resonance_feedback(&mut self, enabled: bool) {
+        self.resonance_feedback = enabled;
+    }
+
     pub fn set_sample_rate(&mut self, _sample_rate: f32) {
         self.sample_rate = _sample_rate;
         self.compute_coef();
     }
 
+    ///coefficients are only computed when a parameter change, not for every sample
     pub fn process(&mut self, mut b0: f32) -> f32 {
-        self.compute_coef();
         //feedback & clipping
-        let mut feedback = self.a[0];
-        //1500 chosed by experimentation w/ sinensis, self osc around Q = 38
-        feedback *= self.resonance / 1500.;
-        if feedback < -4.5 || feedback > 4.5 {
-            feedback /= 10.;
+        if self.resonance_feedback {
+            let mut feedback = self.a[0];
+            //1500 chosed by experimentation w/ sinensis, self osc around Q = 38
+            feedback *= self.resonance / 1500.;
+            if feedback < -4.5 || feedback > 4.5 {
+                feedback /= 10.;
+            }
+            feedback = feedback.clamp(-5., 5.);
+            b0 += feedback;
         }
-        feedback = feedback.clamp(-5., 5.);
-        b0 += feedback;
         //shift new value in
         self.b[2] = self.b[1].clamp(-100., 100.);
         self.b[1] = self.b[0].clamp(-100., 100.);
@@ -211,4 +233,29 @@ impl Biquad {
 
         return self.a[0];
     }
+
+    ///filter the buffer in place
+    pub fn process_block(&mut self, buffer: &mut [f32]) {
+        for sample in buffer.iter_mut() {
+            *sample = self.process(*sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_type_updates_the_coefficients() {
+        let mut switched = Biquad::new(FilterType::LPF);
+        let mut high_pass = Biquad::new(FilterType::HPF);
+        switched.init(48000.);
+        high_pass.init(48000.);
+        switched.set_type(FilterType::HPF);
+        for i in 0..480 {
+            let input = (i % 7) as f32 * 0.1;
+            assert_eq!(switched.process(input), high_pass.process(input));
+        }
+    }
 }
@@ -0,0 +1,467 @@
+use crate::envelope;
+use crate::envelope::Envelope;
+use crate::midi::MidiMessage;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode};
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::ParameterUpdate;
+use crate::SineWave;
+use num_derive::FromPrimitive;
+
+const NUMBER_OF_VOICES: usize = 4;
+pub const NUMBER_OF_OPERATORS: usize = 4;
+
+const NB_SYNTH_PARAM: usize = 2 + NUMBER_OF_OPERATORS * NUMBER_OF_OPERATOR_PARAM;
+const NUMBER_OF_OPERATOR_PARAM: usize = 5;
+//operator parameters ids start after the global ones
+const FIRST_OPERATOR_ID: i32 = 2;
+//phase deviation in cycles of a modulator at full level
+const MODULATION_INDEX: f32 = 2.;
+const FEEDBACK_INDEX: f32 = 0.5;
+
+///Routing of the operators. Operators are only modulated by the ones after them,
+///so they are computed from the last to the first. Operators in no list are not computed.
+pub struct Algorithm {
+    pub name: &'static str,
+    ///the operators modulating each operator
+    pub modulators: [&'static [usize]; NUMBER_OF_OPERATORS],
+    ///the operators heard
+    pub carriers: &'static [usize],
+}
+
+pub const ALGORITHMS: [Algorithm; 8] = [
+    Algorithm {
+        name: "2>1",
+        modulators: [&[1], &[], &[], &[]],
+        carriers: &[0],
+    },
+    Algorithm {
+        name: "3>2>1",
+        modulators: [&[1], &[2], &[], &[]],
+        carriers: &[0],
+    },
+    Algorithm {
+        name: "2+3>1",
+        modulators: [&[1, 2], &[], &[], &[]],
+        carriers: &[0],
+    },
+    Algorithm {
+        name: "4>3>2>1",
+        modulators: [&[1], &[2], &[3], &[]],
+        carriers: &[0],
+    },
+    Algorithm {
+        name: "2+(4>3)>1",
+        modulators: [&[1, 2], &[], &[3], &[]],
+        carriers: &[0],
+    },
+    Algorithm {
+        name: "2>1 4>3",
+        modulators: [&[1], &[], &[3], &[]],
+        carriers: &[0, 2],
+    },
+    Algorithm {
+        name: "4>1,2,3",
+        modulators: [&[3], &[3], &[3], &[]],
+        carriers: &[0, 1, 2],
+    },
+    Algorithm {
+        name: "1+2+3+4",
+        modulators: [&[], &[], &[], &[]],
+        carriers: &[0, 1, 2, 3],
+    },
+];
+
+impl Algorithm {
+    fn uses(&self, operator: usize) -> bool {
+        self.carriers.contains(&operator)
+            || self
+                .modulators
+                .iter()
+                .any(|modulators| modulators.contains(&operator))
+    }
+}
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum FmModelParamID {
+    Algorithm,
+    Volume,
+}
+
+///the parameters of each operator, their id is `FIRST_OPERATOR_ID + operator * 5 + this`
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)]
+pub enum OperatorParamID {
+    Ratio,
+    Level,
+    Feedback,
+    EnvelopeAttack,
+    EnvelopeRelease,
+}
+
+///a sine and its envelope, `ratio` times the note frequency
+#[derive(Clone, Copy)]
+struct Operator {
+    oscillator: SineWave,
+    envelope: Envelope,
+    ratio: f32,
+    level: f32,
+    feedback: f32,
+    //the two last outputs, averaged to keep the feedback stable
+    previous: [f32; 2],
+}
+
+impl Operator {
+    fn new() -> Self {
+        Operator {
+            oscillator: SineWave::new(),
+            envelope: Envelope::new(),
+            ratio: 1.,
+            level: 0.,
+            feedback: 0.,
+            previous: [0.; 2],
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.oscillator.init(sample_rate);
+        self.envelope.init(sample_rate);
+    }
+
+    ///`modulation` in cycles
+    fn process(&mut self, modulation: f32) -> f32 {
+        let feedback = self.feedback * FEEDBACK_INDEX * (self.previous[0] + self.previous[1]) * 0.5;
+        let output = self.oscillator.process_phase_modulated(modulation + feedback)
+            * self.envelope.process()
+            * self.level;
+        self.previous = [output, self.previous[0]];
+        output
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FmVoice {
+    operators: [Operator; NUMBER_OF_OPERATORS],
+    frequency_hz: f32,
+}
+
+impl FmVoice {
+    fn update_frequencies(&mut self) {
+        for operator in self.operators.iter_mut() {
+            operator.oscillator.frequency_hz = self.frequency_hz * operator.ratio;
+        }
+    }
+
+    ///envelope of the loudest carrier
+    fn carrier_level(&self, algorithm: &Algorithm) -> f32 {
+        algorithm
+            .carriers
+            .iter()
+            .map(|carrier| self.operators[*carrier].envelope.value())
+            .fold(0., f32::max)
+    }
+
+    fn is_off(&self, algorithm: &Algorithm) -> bool {
+        algorithm
+            .carriers
+            .iter()
+            .all(|carrier| self.operators[*carrier].envelope.status == envelope::Segment::Off)
+    }
+
+    fn process(&mut self, algorithm: &Algorithm) -> f32 {
+        let mut outputs = [0.; NUMBER_OF_OPERATORS];
+        for operator in (0..NUMBER_OF_OPERATORS).rev() {
+            if !algorithm.uses(operator) {
+                continue;
+            }
+            let modulation: f32 = algorithm.modulators[operator]
+                .iter()
+                .map(|modulator| outputs[*modulator])
+                .sum::<f32>()
+                * MODULATION_INDEX;
+            outputs[operator] = self.operators[operator].process(modulation);
+        }
+        algorithm
+            .carriers
+            .iter()
+            .map(|carrier| outputs[*carrier])
+            .sum::<f32>()
+            / algorithm.carriers.len() as f32
+    }
+}
+
+///the context is the algorithm index
+impl Voice for FmVoice {
+    type Context = usize;
+
+    fn start(&mut self, _midi_note: u8, retrigger: bool, _: &mut usize) {
+        if retrigger {
+            self.operators
+                .iter_mut()
+                .for_each(|operator| operator.envelope.note_on());
+        }
+    }
+
+    fn release(&mut self, _: &usize) {
+        self.operators
+            .iter_mut()
+            .for_each(|operator| operator.envelope.note_off());
+    }
+
+    fn steal(&mut self, _: &usize) {
+        self.operators
+            .iter_mut()
+            .for_each(|operator| operator.envelope.steal());
+    }
+
+    fn level(&self, algorithm: &usize) -> f32 {
+        self.carrier_level(&ALGORITHMS[*algorithm])
+    }
+
+    fn is_silent(&self, algorithm: &usize) -> bool {
+        self.is_off(&ALGORITHMS[*algorithm])
+    }
+
+    fn set_frequency(&mut self, frequency_hz: f32) {
+        self.frequency_hz = frequency_hz;
+        self.update_frequencies();
+    }
+}
+
+pub struct FmModel {
+    voices: VoiceManager<FmVoice>,
+    //parameters
+    algorithm: usize,
+    volume: f32,
+}
+
+impl HasDescription for FmModel {
+    const NAME: &'static str = "FM";
+    const DESCRIPTION: &'static str = "four operators phase modulation, eight algorithms, for bells and metallic tones";
+}
+
+impl HasConstructor for FmModel {
+    fn new() -> Self {
+        FmModel {
+            voices: VoiceManager::new(
+                vec![
+                    FmVoice {
+                        operators: [Operator::new(); NUMBER_OF_OPERATORS],
+                        frequency_hz: 440.,
+                    };
+                    NUMBER_OF_VOICES
+                ],
+                VoiceMode::Oldest,
+            ),
+            algorithm: 0,
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.voices.init(sample_rate);
+        for voice in self.voices.iter_mut() {
+            voice.operators.iter_mut().for_each(|x| x.init(sample_rate));
+        }
+    }
+}
+
+impl HasMidiInput for FmModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut self.algorithm),
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut self.algorithm),
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for FmModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let algorithm = &ALGORITHMS[self.algorithm];
+        left.fill(0.);
+        for i in 0..self.voices.len() {
+            for sample in left.iter_mut() {
+                self.voices.update(i, &mut self.algorithm);
+                if self.voices[i].is_off(algorithm) {
+                    break;
+                }
+                *sample += self.voices[i].process(algorithm);
+            }
+        }
+        for sample in left.iter_mut() {
+            //vca
+            *sample *= self.volume / NUMBER_OF_VOICES as f32;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for FmModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = FmModelParamID;
+        type OP = OperatorParamID;
+        type P = ParameterCapsule;
+
+        let mut capsules = vec![
+            P::new(
+                ID::Algorithm as i32,
+                "algorithm",
+                0,
+                'x',
+                0.,
+                (ALGORITHMS.len() - 1) as f32,
+                1.,
+            ),
+            P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+        ];
+        //one keyboard row per operator : qwert, yuiop, asdfg, hjklm
+        let operator_ccs = [
+            ['q', 'w', 'e', 'r', 't'],
+            ['y', 'u', 'i', 'o', 'p'],
+            ['a', 's', 'd', 'f', 'g'],
+            ['h', 'j', 'k', 'l', 'm'],
+        ];
+        for (operator, ccs) in operator_ccs.iter().enumerate() {
+            let id = |param: OperatorParamID| operator_param_id(operator, param);
+            let name = |param: &str| format!("op{}-{}", operator + 1, param);
+            //the first operator is heard at full level, the second modulates it a little
+            let level = match operator {
+                0 => 35,
+                1 => 12,
+                _ => 0,
+            };
+            capsules.extend([
+                //ratios from 0.5 to 18 by steps of 0.5, 1 by default
+                P::new(id(OP::Ratio), &name("ratio"), 1, ccs[0], 0.5, 18., 1.),
+                P::new(id(OP::Level), &name("level"), level, ccs[1], 0., 1., 1.),
+                P::new(id(OP::Feedback), &name("fdbk"), 0, ccs[2], 0., 1., 1.),
+                P::new(
+                    id(OP::EnvelopeAttack),
+                    &name("atk"),
+                    3,
+                    ccs[3],
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    id(OP::EnvelopeRelease),
+                    &name("rel"),
+                    10,
+                    ccs[4],
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+            ]);
+        }
+
+        let params = Parameters {
+            capsules,
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = FmModelParamID;
+        if id >= FIRST_OPERATOR_ID {
+            return self.set_operator_parameter(id - FIRST_OPERATOR_ID, new_value);
+        }
+        let typed_id: FmModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Algorithm => {
+                self.algorithm = (new_value.round() as usize).min(ALGORITHMS.len() - 1)
+            }
+        }
+    }
+}
+
+fn operator_param_id(operator: usize, param: OperatorParamID) -> i32 {
+    FIRST_OPERATOR_ID + (operator * NUMBER_OF_OPERATOR_PARAM) as i32 + param as i32
+}
+
+impl FmModel {
+    ///`id` counted from the first operator parameter
+    fn set_operator_parameter(&mut self, id: i32, new_value: f32) {
+        type OP = OperatorParamID;
+        let operator = id as usize / NUMBER_OF_OPERATOR_PARAM;
+        let typed_id: OperatorParamID =
+            num::FromPrimitive::from_i32(id % NUMBER_OF_OPERATOR_PARAM as i32).unwrap();
+        for voice in self.voices.iter_mut() {
+            let op = &mut voice.operators[operator];
+            match typed_id {
+                OP::Ratio => {
+                    op.ratio = new_value;
+                    voice.update_frequencies();
+                }
+                OP::Level => op.level = new_value,
+                OP::Feedback => op.feedback = new_value,
+                OP::EnvelopeAttack => op.envelope.set_attack(new_value),
+                OP::EnvelopeRelease => op.envelope.set_release(new_value),
+            }
+        }
+    }
+}
+
+impl Synth for FmModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::model;
+
+    #[test]
+    fn parameters_and_routing() {
+        let mut model: FmModel = model();
+        assert_eq!(model.get_parameters().capsules.len(), NB_SYNTH_PARAM);
+        //the default ratio is exactly 1
+        assert_eq!(model.voices[0].operators[3].ratio, 1.);
+
+        model.set_parameter((operator_param_id(1, OperatorParamID::Ratio), 3.5));
+        model.set_note(MidiMessage::NoteOn(69));
+        assert_eq!(model.voices[0].operators[1].oscillator.frequency_hz, 440. * 3.5);
+
+        for algorithm in ALGORITHMS.iter() {
+            //every operator is computed after the ones modulating it
+            for (operator, modulators) in algorithm.modulators.iter().enumerate() {
+                assert!(modulators.iter().all(|modulator| *modulator > operator));
+            }
+            assert!(!algorithm.carriers.is_empty());
+        }
+    }
+
+    #[test]
+    fn modulation_changes_the_tone() {
+        let mut plain: FmModel = model();
+        let mut modulated: FmModel = model();
+        plain.set_parameter((operator_param_id(1, OperatorParamID::Level), 0.));
+        modulated.set_parameter((operator_param_id(1, OperatorParamID::Level), 1.));
+        let mut outputs = vec![];
+        for model in [&mut plain, &mut modulated] {
+            model.set_parameter((operator_param_id(0, OperatorParamID::EnvelopeAttack), 0.));
+            model.set_note(MidiMessage::NoteOn(69));
+            let (mut left, mut right) = ([0.; 256], [0.; 256]);
+            model.process_block(&mut left, &mut right);
+            assert!(left.iter().any(|sample| sample.abs() > 0.01));
+            outputs.push(left);
+        }
+        assert_ne!(outputs[0], outputs[1]);
+
+        //the voice stops once the carrier envelope is done
+        plain.set_note(MidiMessage::NoteOff(69));
+        plain.set_parameter((operator_param_id(0, OperatorParamID::EnvelopeRelease), 0.));
+        let (mut left, mut right) = ([0.; 1024], [0.; 1024]);
+        plain.process_block(&mut left, &mut right);
+        assert!(plain.voices[0].is_off(&ALGORITHMS[0]));
+    }
+}
@@ -0,0 +1,595 @@
+use crate::envelope::{self, Envelope};
+use crate::filter::FilterType;
+use crate::midi::MidiMessage;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode};
+use crate::oscillator::{BlepWaveform, Lfo, PolyBlepOscillator, Waveform};
+use crate::outils::midi_to_frequence;
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::reverb::Reverb;
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::textparsing::{text_to_phonemes, Phoneme, PhonemeSequence};
+use crate::Biquad;
+use crate::ParameterUpdate;
+use num_derive::FromPrimitive;
+
+const NUMBER_OF_VOICES: usize = 4;
+
+const NB_SYNTH_PARAM: usize = 11;
+//sung until a text is received
+const DEFAULT_TEXT: &str = "laminaria";
+//the articulation is updated every CONTROL_RATE samples
+const CONTROL_RATE: usize = 32;
+//in seconds, time for the formants to move from a phoneme to the next one
+const GLIDE_TIME: f32 = 0.02;
+//in hertz, width of the three formants
+const FORMANT_BANDWIDTHS: [f32; 3] = [80., 100., 120.];
+const VIBRATO_RATE: f32 = 5.5;
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum FormantModelParamID {
+    Speed,
+    ConsonantLength,
+    FormantShift,
+    Breath,
+    Vibrato,
+    EnvelopeAttack,
+    EnvelopeRelease,
+    ReverbDryWet,
+    ReverbTime,
+    Volume,
+    Glide,
+}
+
+///What the vocal tract does during a phoneme
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Articulation {
+    formants: [f32; 3],
+    gains: [f32; 3],
+    //level of the glottal pulses in the formants
+    voicing: f32,
+    //level of the band-passed noise of the consonants
+    noise: f32,
+    noise_frequency: f32,
+}
+
+const SILENCE: Articulation = Articulation {
+    formants: [500., 1500., 2500.],
+    gains: [0.; 3],
+    voicing: 0.,
+    noise: 0.,
+    noise_frequency: 2000.,
+};
+
+///formants of a vowel, or of a voiced consonant
+const fn formants(formants: [f32; 3], gains: [f32; 3]) -> Articulation {
+    Articulation {
+        formants,
+        gains,
+        voicing: 1.,
+        noise: 0.,
+        noise_frequency: 2000.,
+    }
+}
+
+///noise of a fricative or a plosive burst, `voicing` for the voiced ones
+const fn noise(noise_frequency: f32, level: f32, voicing: f32) -> Articulation {
+    Articulation {
+        formants: [250., 1500., 2500.],
+        gains: [1., 0.2, 0.1],
+        voicing,
+        noise: level,
+        noise_frequency,
+    }
+}
+
+///target of the vocal tract, `progress` goes from 0 to 1 during a consonant
+fn articulation(phoneme: Phoneme, progress: f32) -> Articulation {
+    type Ph = Phoneme;
+    match phoneme {
+        Ph::A => formants([800., 1150., 2900.], [1., 0.5, 0.1]),
+        Ph::E => formants([400., 2000., 2550.], [1., 0.3, 0.2]),
+        Ph::I => formants([280., 2250., 2900.], [1., 0.25, 0.2]),
+        Ph::O => formants([450., 800., 2830.], [1., 0.5, 0.1]),
+        Ph::U => formants([325., 700., 2530.], [1., 0.3, 0.05]),
+        //closed mouth
+        Ph::M => formants([250., 1100., 2500.], [0.6, 0.05, 0.02]),
+        Ph::N => formants([250., 1700., 2600.], [0.6, 0.08, 0.03]),
+        Ph::L => formants([360., 1300., 2700.], [0.8, 0.3, 0.1]),
+        Ph::R => formants([420., 1300., 1600.], [0.8, 0.3, 0.2]),
+        Ph::W => formants([300., 610., 2200.], [0.8, 0.3, 0.05]),
+        Ph::Y => formants([280., 2250., 2900.], [0.8, 0.3, 0.2]),
+        Ph::F => noise(7000., 0.4, 0.),
+        Ph::V => noise(7000., 0.25, 0.5),
+        Ph::S => noise(6000., 0.8, 0.),
+        Ph::Z => noise(6000., 0.5, 0.5),
+        Ph::Sh => noise(2500., 0.8, 0.),
+        Ph::Th => noise(5500., 0.3, 0.),
+        Ph::H => noise(1500., 0.4, 0.),
+        //a closure, then the burst
+        Ph::P | Ph::B | Ph::T | Ph::D | Ph::K | Ph::G => {
+            let (noise_frequency, voicing) = match phoneme {
+                Ph::P => (800., 0.),
+                Ph::B => (800., 0.3),
+                Ph::T => (4000., 0.),
+                Ph::D => (3500., 0.3),
+                Ph::K => (2000., 0.),
+                _ => (1800., 0.3),
+            };
+            if progress < 0.7 {
+                noise(noise_frequency, 0., voicing * 0.5)
+            } else {
+                noise(noise_frequency, 1., voicing)
+            }
+        }
+        Ph::Pause => SILENCE,
+    }
+}
+
+///in seconds, None for the vowels that are held
+fn duration(phoneme: Phoneme) -> Option<f32> {
+    type Ph = Phoneme;
+    match phoneme {
+        Ph::A | Ph::E | Ph::I | Ph::O | Ph::U => None,
+        Ph::P | Ph::B | Ph::T | Ph::D | Ph::K | Ph::G => Some(0.06),
+        Ph::F | Ph::V | Ph::S | Ph::Z | Ph::Sh | Ph::Th | Ph::H => Some(0.09),
+        Ph::M | Ph::N | Ph::L | Ph::R | Ph::W | Ph::Y => Some(0.07),
+        Ph::Pause => Some(0.1),
+    }
+}
+
+///consonants closing a word, they are sung when the note is released
+fn is_coda(phonemes: &[Phoneme], position: usize) -> bool {
+    phonemes[position..]
+        .iter()
+        .take_while(|phoneme| **phoneme != Phoneme::Pause)
+        .all(|phoneme| phoneme.is_consonant())
+        && phonemes.get(position).is_some_and(|phoneme| phoneme.is_consonant())
+}
+
+///start of the syllable after the one starting at `position`, back to the beginning after the last one
+fn next_syllable(phonemes: &[Phoneme], position: usize) -> usize {
+    let Some(vowel) = phonemes[position.min(phonemes.len())..]
+        .iter()
+        .position(|phoneme| phoneme.is_vowel())
+    else {
+        return 0;
+    };
+    let mut next = position + vowel + 1;
+    if is_coda(phonemes, next) {
+        while phonemes.get(next).is_some_and(|phoneme| phoneme.is_consonant()) {
+            next += 1;
+        }
+    }
+    while phonemes.get(next) == Some(&Phoneme::Pause) {
+        next += 1;
+    }
+    if next >= phonemes.len() {
+        0
+    } else {
+        next
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FormantSettings {
+    //syllables per second while a note is held, 0 sings one syllable per note
+    speed: f32,
+    consonant_length: f32,
+    //ratio applied to the formants, a smaller or bigger throat
+    formant_shift: f32,
+    breath: f32,
+    //in semitones
+    vibrato: f32,
+    glide: f32,
+}
+
+///a glottal saw through three formant band-pass, and a noise band-pass for the consonants
+struct FormantVoice {
+    glottis: PolyBlepOscillator,
+    noise: PolyBlepOscillator,
+    formants: [Biquad; 3],
+    resonances: [f32; 3],
+    fricative: Biquad,
+    envelope: Envelope,
+    vibrato: Lfo,
+    sample_rate: f32,
+    note: Option<u8>,
+    frequency_hz: f32,
+    //phoneme sung and the time spent on it, in seconds
+    position: usize,
+    elapsed: f32,
+    articulation: Articulation,
+    control_counter: usize,
+}
+
+impl FormantVoice {
+    fn new(index: usize) -> Self {
+        let mut noise = PolyBlepOscillator::new();
+        noise.waveform = BlepWaveform::Noise;
+        noise.set_seed(index as u32);
+        let mut vibrato = Lfo::new(VIBRATO_RATE);
+        vibrato.set_freq_and_shape(VIBRATO_RATE + index as f32 * 0.1, Waveform::Sine);
+        let band_pass = |_| {
+            let mut filter = Biquad::new(FilterType::BPF);
+            filter.set_resonance_feedback(false);
+            filter
+        };
+        FormantVoice {
+            glottis: PolyBlepOscillator::new(),
+            noise,
+            formants: std::array::from_fn(band_pass),
+            resonances: [1.; 3],
+            fricative: band_pass(0),
+            envelope: Envelope::new(),
+            vibrato,
+            sample_rate: 0.,
+            note: None,
+            frequency_hz: 440.,
+            position: 0,
+            elapsed: 0.,
+            articulation: SILENCE,
+            control_counter: 0,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.glottis.init(sample_rate);
+        self.noise.init(sample_rate);
+        self.formants.iter_mut().for_each(|x| x.init(sample_rate));
+        self.fricative.init(sample_rate);
+        self.envelope.init(sample_rate);
+        self.vibrato.init(sample_rate);
+    }
+
+    fn note_on(&mut self, midi_note: u8, position: usize) {
+        self.note = Some(midi_note);
+        self.frequency_hz = midi_to_frequence(midi_note);
+        self.position = position;
+        self.elapsed = 0.;
+        self.envelope.note_on();
+        self.control_counter = 0;
+    }
+
+    fn note_off(&mut self) {
+        self.note = None;
+        self.control_counter = 0;
+    }
+
+    ///step through the phonemes and glide toward the current one
+    fn articulate(&mut self, phonemes: &[Phoneme], settings: &FormantSettings) {
+        let time_step = CONTROL_RATE as f32 / self.sample_rate;
+        self.elapsed += time_step;
+        let held = self.note.is_some();
+        let target = match phonemes.get(self.position) {
+            None => {
+                self.envelope.note_off();
+                SILENCE
+            }
+            Some(phoneme) => {
+                let length = match phoneme {
+                    Phoneme::Pause => duration(*phoneme),
+                    _ => duration(*phoneme).map(|length| length * settings.consonant_length),
+                };
+                let next_vowel = phonemes[self.position + 1..].iter().any(|x| x.is_vowel());
+                let advance = match length {
+                    //released, the next word is not sung
+                    _ if !held && *phoneme == Phoneme::Pause => false,
+                    Some(length) => self.elapsed >= length,
+                    //a vowel is left for the consonants ending the word once the note is released,
+                    //or for the next syllable at the speed of the syllables
+                    None if held => {
+                        settings.speed > 0. && self.elapsed >= 1. / settings.speed && next_vowel
+                    }
+                    None => is_coda(phonemes, self.position + 1),
+                };
+                //released, the voice fades out once the word is sung
+                if !held && !advance && !phoneme.is_consonant() {
+                    self.envelope.note_off();
+                }
+                let progress = length.map_or(0., |length| self.elapsed / length.max(time_step));
+                if advance {
+                    self.position += 1;
+                    self.elapsed = 0.;
+                }
+                articulation(*phoneme, progress)
+            }
+        };
+
+        //the formants glide, the levels follow faster so the consonants stay crisp
+        let glide = 1. - (-time_step / settings.glide.max(0.001)).exp();
+        let follow = 1. - (-time_step / 0.005_f32).exp();
+        let current = &mut self.articulation;
+        for k in 0..3 {
+            current.formants[k] += (target.formants[k] - current.formants[k]) * glide;
+            current.gains[k] += (target.gains[k] - current.gains[k]) * glide;
+        }
+        current.voicing += (target.voicing - current.voicing) * follow;
+        current.noise += (target.noise - current.noise) * follow;
+        current.noise_frequency += (target.noise_frequency - current.noise_frequency) * follow;
+
+        let highest = self.sample_rate * 0.45;
+        for (k, bandwidth) in FORMANT_BANDWIDTHS.iter().enumerate() {
+            let frequency = (current.formants[k] * settings.formant_shift).min(highest);
+            self.resonances[k] = (frequency / bandwidth).max(1.);
+            self.formants[k].set_frequence_and_resonance(frequency, self.resonances[k]);
+        }
+        self.fricative.set_frequence_and_resonance(current.noise_frequency.min(highest), 1.5);
+    }
+
+    fn process(&mut self, phonemes: &[Phoneme], settings: &FormantSettings) -> f32 {
+        if self.control_counter == 0 {
+            self.articulate(phonemes, settings);
+        }
+        self.control_counter = (self.control_counter + 1) % CONTROL_RATE;
+
+        let vibrato = self.vibrato.tick() * settings.vibrato;
+        self.glottis.frequency_hz = self.frequency_hz * 2_f32.powf(vibrato / 12.);
+        let noise = self.noise.process();
+        let articulation = &self.articulation;
+        let source = (self.glottis.process() + noise * settings.breath) * articulation.voicing;
+
+        let mut output = 0.;
+        for ((filter, gain), resonance) in self
+            .formants
+            .iter_mut()
+            .zip(articulation.gains.iter())
+            .zip(self.resonances.iter())
+        {
+            //the constant skirt band-pass peaks at Q
+            output += filter.process(source) * gain / resonance;
+        }
+        output += self.fricative.process(noise) * articulation.noise / 1.5;
+        //the formants only keep a few harmonics of the saw
+        output * 4. * self.envelope.process()
+    }
+}
+
+impl Voice for FormantVoice {
+    type Context = Lyrics;
+
+    ///each new note sings the next syllable
+    fn start(&mut self, midi_note: u8, _retrigger: bool, lyrics: &mut Lyrics) {
+        if self.note != Some(midi_note) {
+            self.note_on(midi_note, lyrics.syllable);
+            lyrics.syllable = next_syllable(lyrics.phonemes.as_slice(), lyrics.syllable);
+        }
+    }
+
+    fn release(&mut self, _: &Lyrics) {
+        self.note_off()
+    }
+
+    fn steal(&mut self, _: &Lyrics) {
+        self.note_off();
+        self.envelope.steal();
+    }
+
+    fn level(&self, _: &Lyrics) -> f32 {
+        self.envelope.value()
+    }
+
+    fn is_silent(&self, _: &Lyrics) -> bool {
+        self.envelope.status == envelope::Segment::Off
+    }
+}
+
+///the text sung by the voices
+struct Lyrics {
+    phonemes: PhonemeSequence,
+    //first phoneme of the syllable sung by the next note
+    syllable: usize,
+}
+
+pub struct FormantModel {
+    voices: VoiceManager<FormantVoice>,
+    reverb: Reverb,
+    lyrics: Lyrics,
+    settings: FormantSettings,
+    volume: f32,
+}
+
+impl HasDescription for FormantModel {
+    const NAME: &'static str = "Formant";
+    const DESCRIPTION: &'static str = "four voices singing the text typed or sent by ORCA, a syllable per note";
+}
+
+impl HasConstructor for FormantModel {
+    fn new() -> Self {
+        FormantModel {
+            voices: VoiceManager::new((0..NUMBER_OF_VOICES).map(FormantVoice::new).collect(), VoiceMode::Oldest),
+            reverb: Reverb::new(),
+            lyrics: Lyrics {
+                phonemes: text_to_phonemes(DEFAULT_TEXT),
+                syllable: 0,
+            },
+            settings: FormantSettings {
+                speed: 0.,
+                consonant_length: 1.,
+                formant_shift: 1.,
+                breath: 0.1,
+                vibrato: 0.2,
+                glide: GLIDE_TIME,
+            },
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.voices.init(sample_rate);
+        self.voices.iter_mut().for_each(|x| x.init(sample_rate));
+        self.reverb.init(sample_rate);
+    }
+}
+
+impl HasMidiInput for FormantModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut self.lyrics),
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut self.lyrics),
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for FormantModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.fill(0.);
+        for i in 0..self.voices.len() {
+            for sample in left.iter_mut() {
+                self.voices.update(i, &mut self.lyrics);
+                let voice = &mut self.voices[i];
+                if voice.envelope.status == envelope::Segment::Off {
+                    break;
+                }
+                *sample += voice.process(self.lyrics.phonemes.as_slice(), &self.settings);
+            }
+        }
+        for sample in left.iter_mut() {
+            //vca
+            *sample = self.reverb.process(*sample) * self.volume / NUMBER_OF_VOICES as f32;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for FormantModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = FormantModelParamID;
+        type P = ParameterCapsule;
+
+        let params = Parameters {
+            capsules: vec![
+                //text
+                P::new(ID::Speed as i32, "speed", 0, 's', 0., 8., 1.),
+                P::new(ID::ConsonantLength as i32, "consonant", 9, 'c', 0.1, 3.6, 1.),
+                P::new(ID::Glide as i32, "glide", 10, 'g', 0.005, 0.2, 2.),
+                //voice
+                P::new(ID::FormantShift as i32, "formant", 17, 'f', -17., 18., 1.),
+                P::new(ID::Breath as i32, "breath", 4, 'b', 0., 1., 1.),
+                P::new(ID::Vibrato as i32, "vibrato", 7, 'i', 0., 1., 1.),
+                //envelope
+                P::new(
+                    ID::EnvelopeAttack as i32,
+                    "env-atk",
+                    3,
+                    'a',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    ID::EnvelopeRelease as i32,
+                    "env-dcy",
+                    3,
+                    'd',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                //reverb
+                P::new(ID::ReverbDryWet as i32, "rvb-wet", 10, 'r', 0., 1., 1.),
+                P::new(ID::ReverbTime as i32, "rvb-time", 20, '9', 0., 0.99, 1.),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = FormantModelParamID;
+        let typed_id: FormantModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Speed => self.settings.speed = new_value,
+            ID::ConsonantLength => self.settings.consonant_length = new_value,
+            ID::Glide => self.settings.glide = new_value,
+            //in semitones
+            ID::FormantShift => self.settings.formant_shift = 2_f32.powf(new_value / 12.),
+            ID::Breath => self.settings.breath = new_value,
+            ID::Vibrato => self.settings.vibrato = new_value,
+            ID::EnvelopeAttack => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_attack(new_value)),
+            ID::EnvelopeRelease => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_release(new_value)),
+            //reverb
+            ID::ReverbDryWet => self.reverb.dry_wet = new_value,
+            ID::ReverbTime => self.reverb.set_reverb_time(new_value),
+        }
+    }
+}
+
+impl Synth for FormantModel {
+    ///the next note starts the new text from its first syllable
+    fn set_text(&mut self, phonemes: &PhonemeSequence) {
+        if phonemes.is_empty() {
+            return;
+        }
+        self.lyrics.phonemes = *phonemes;
+        self.lyrics.syllable = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{model, render};
+
+    #[test]
+    fn syllables() {
+        let laminaria = text_to_phonemes("laminaria");
+        let starts: Vec<usize> = (0..5)
+            .scan(0, |position, _| {
+                *position = next_syllable(laminaria.as_slice(), *position);
+                Some(*position)
+            })
+            .collect();
+        assert_eq!(starts, vec![2, 4, 6, 8, 0]);
+
+        //the consonants closing a word stay with it
+        let shall_sing = text_to_phonemes("shall sing");
+        assert_eq!(next_syllable(shall_sing.as_slice(), 0), 4);
+        assert_eq!(next_syllable(shall_sing.as_slice(), 4), 0);
+        assert!(is_coda(shall_sing.as_slice(), 2));
+        assert!(!is_coda(laminaria.as_slice(), 2));
+    }
+
+    #[test]
+    fn sing_a_syllable_per_note() {
+        let mut model: FormantModel = model();
+        model.set_text(&text_to_phonemes("shall sing"));
+        model.set_note(MidiMessage::NoteOn(60));
+        let sung = render(&mut model, 9600);
+        assert!(sung.iter().any(|sample| sample.abs() > 0.001));
+        assert!(sung.iter().all(|sample| sample.abs() < 1.));
+        //the vowel is held
+        assert_eq!(model.voices[0].position, 1);
+        assert!((model.voices[0].articulation.formants[0] - 800.).abs() < 10.);
+
+        //released, the word ends on its last consonant then fades out
+        model.set_note(MidiMessage::NoteOff(60));
+        render(&mut model, 4800);
+        //stopped on the pause before "sing"
+        assert_eq!(model.voices[0].position, 3);
+        render(&mut model, 4800);
+        assert!(model.voices[0].envelope.status == envelope::Segment::Off);
+
+        //the next note sings the next word
+        model.set_note(MidiMessage::NoteOn(62));
+        assert_eq!(model.voices[0].position, 4);
+    }
+}
@@ -0,0 +1,397 @@
+use crate::buffer::RingBuffer;
+use crate::envelope;
+use crate::envelope::Envelope;
+use crate::midi::MidiMessage;
+use crate::midibuffer::PolyMidiBuffer;
+use crate::oscillator::{BlepWaveform, PolyBlepOscillator};
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::sample::Sample;
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::HarmonicModel;
+use crate::ParameterUpdate;
+use num_derive::FromPrimitive;
+use std::sync::Arc;
+
+const NUMBER_OF_NOTES: usize = 4;
+const MAXIMUM_GRAINS: usize = 32;
+//in seconds
+const BUFFER_TIME: f32 = 4.;
+//in seconds, length of the harmonic model output captured when there is no sample
+const CAPTURE_TIME: f32 = 2.;
+//the note playing the grains at their recorded pitch
+const ROOT_NOTE: u8 = 60;
+
+const NB_SYNTH_PARAM: usize = 10;
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum GranularModelParamID {
+    Capture,
+    Density,
+    Size,
+    Position,
+    Spray,
+    Pitch,
+    Reverse,
+    EnvelopeAttack,
+    EnvelopeRelease,
+    Volume,
+}
+
+///a slice of the buffer read at its own speed through a hann window
+#[derive(Clone, Copy)]
+struct Grain {
+    //samples behind the write head of the buffer
+    delay: f32,
+    //negative when reversed
+    step: f32,
+    length: f32,
+    age: f32,
+}
+
+impl Grain {
+    fn is_over(&self) -> bool {
+        self.age >= self.length
+    }
+
+    fn process(&mut self, buffer: &RingBuffer, capturing: bool) -> f32 {
+        let window = 0.5 - 0.5 * (std::f32::consts::TAU * self.age / self.length).cos();
+        let sample = buffer.read_behind(self.delay) * window;
+        //the write head moves away from the grain while capturing
+        self.delay += capturing as u8 as f32 - self.step;
+        self.age += 1.;
+        sample
+    }
+}
+
+pub struct GranularModel {
+    sample: Option<Arc<Sample>>,
+    buffer: RingBuffer,
+    //samples written in the buffer, up to its capacity
+    recorded: f32,
+    grains: [Option<Grain>; MAXIMUM_GRAINS],
+    //samples until the next grain
+    countdown: f32,
+    //the held notes take turns to pitch the grains
+    next_note: usize,
+    random: PolyBlepOscillator,
+    envelope: Envelope,
+    midibuffer: PolyMidiBuffer,
+    sample_rate: f32,
+    //parameters
+    capturing: bool,
+    density: f32,
+    size: f32,
+    position: f32,
+    spray: f32,
+    pitch: f32,
+    reverse: f32,
+    volume: f32,
+}
+
+impl GranularModel {
+    ///grains of `sample` instead of the output of the harmonic model
+    pub fn with_sample(sample: Arc<Sample>) -> Self {
+        let mut model = Self::new();
+        model.sample = Some(sample);
+        model
+    }
+
+    fn record(&mut self, samples: &[f32]) {
+        for sample in samples.iter().take(self.buffer.capacity() - 1) {
+            self.buffer.write_sample(*sample);
+        }
+        self.recorded = samples.len().min(self.buffer.capacity() - 1) as f32;
+    }
+
+    ///between 0 and 1
+    fn random(&mut self) -> f32 {
+        self.random.process() * 0.5 + 0.5
+    }
+
+    fn spawn_grain(&mut self) {
+        let Some(free) = self.grains.iter().position(|grain| grain.is_none()) else {
+            return;
+        };
+        let notes = &self.midibuffer.notes;
+        let Some(midi_note) = notes.get(self.next_note % notes.len().max(1)) else {
+            return;
+        };
+        self.next_note += 1;
+
+        let semitones = *midi_note as f32 - ROOT_NOTE as f32 + self.pitch;
+        let mut step = 2_f32.powf(semitones / 12.);
+        if self.random() < self.reverse {
+            step = -step;
+        }
+        let length = self.size / 1000. * self.sample_rate;
+        let position = self.position + (self.random() * 2. - 1.) * self.spray;
+        //position 0 is the oldest recorded sample, 1 the newest
+        let delay = (1. - position.clamp(0., 1.)) * self.recorded;
+        //keep the whole grain inside the recording, ahead of the write head
+        let travel = length * (step.abs() + self.capturing as u8 as f32);
+        let (minimum, maximum) = if step > 0. {
+            (travel + 1., self.recorded - 1.)
+        } else {
+            (1., self.recorded - travel - 1.)
+        };
+        self.grains[free] = Some(Grain {
+            delay: delay.min(maximum).max(minimum.min(maximum)).max(0.),
+            step,
+            length,
+            age: 0.,
+        });
+    }
+}
+
+impl HasDescription for GranularModel {
+    const NAME: &'static str = "Granular";
+    const DESCRIPTION: &'static str = "grains of a sample or of the synth's own sound, pitched by the notes";
+}
+
+impl HasConstructor for GranularModel {
+    fn new() -> Self {
+        let mut random = PolyBlepOscillator::new();
+        random.waveform = BlepWaveform::Noise;
+        GranularModel {
+            sample: None,
+            buffer: RingBuffer::new(BUFFER_TIME),
+            recorded: 0.,
+            grains: [None; MAXIMUM_GRAINS],
+            countdown: 0.,
+            next_note: 0,
+            random,
+            envelope: Envelope::new(),
+            midibuffer: PolyMidiBuffer::new(NUMBER_OF_NOTES),
+            sample_rate: 0.,
+            capturing: false,
+            density: 20.,
+            size: 100.,
+            position: 0.5,
+            spray: 0.1,
+            pitch: 0.,
+            reverse: 0.,
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.buffer.init(sample_rate);
+        self.envelope.init(sample_rate);
+        let source = match &self.sample {
+            Some(sample) => sample.resample(sample_rate),
+            None => HarmonicModel::render_chord(sample_rate, CAPTURE_TIME),
+        };
+        self.record(&source);
+    }
+}
+
+impl HasMidiInput for GranularModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.midibuffer.remove_note(midi_note),
+            MidiMessage::NoteOn(midi_note) => self.midibuffer.add_note(midi_note),
+            _ => {}
+        }
+        //the grains keep their pitch, only the new ones follow the notes
+        if self.midibuffer.notes.is_empty() {
+            self.envelope.note_off();
+        } else {
+            self.envelope.note_on();
+        }
+    }
+}
+
+impl HasEngine for GranularModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        //overlapping grains add up, keep the level close to one grain
+        let overlap = (self.density * self.size / 1000.).max(1.);
+        let gain = self.volume / overlap.sqrt();
+
+        for sample in left.iter_mut() {
+            if !self.midibuffer.notes.is_empty() {
+                self.countdown -= 1.;
+                if self.countdown <= 0. {
+                    self.countdown += self.sample_rate / self.density;
+                    self.spawn_grain();
+                }
+            }
+
+            let mut output = 0.;
+            for slot in self.grains.iter_mut() {
+                if let Some(grain) = slot {
+                    output += grain.process(&self.buffer, self.capturing);
+                    if grain.is_over() {
+                        *slot = None;
+                    }
+                }
+            }
+            //the grains feed the buffer back, like the frozen delay.
+            //At the level of one grain and soft clipped, or each pass would read back louder
+            if self.capturing {
+                self.buffer.write_sample((output / overlap.sqrt()).tanh());
+                self.recorded = (self.recorded + 1.).min((self.buffer.capacity() - 1) as f32);
+            }
+            *sample = output * self.envelope.process() * gain;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for GranularModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = GranularModelParamID;
+        type P = ParameterCapsule;
+
+        let params = Parameters {
+            capsules: vec![
+                //source
+                P::new(ID::Capture as i32, "capture", 0, 'c', 0., 1., 1.),
+                //grains
+                P::new(ID::Density as i32, "density", 15, 'n', 1., 100., 2.),
+                P::new(ID::Size as i32, "size", 15, 's', 10., 500., 2.),
+                P::new(ID::Position as i32, "position", 17, 'p', 0., 1., 1.),
+                P::new(ID::Spray as i32, "spray", 5, 'y', 0., 1., 2.),
+                //semitones added to the note
+                P::new(ID::Pitch as i32, "pitch", 17, 't', -17., 18., 1.),
+                P::new(ID::Reverse as i32, "reverse", 0, 'r', 0., 1., 1.),
+                //envelope
+                P::new(
+                    ID::EnvelopeAttack as i32,
+                    "env-atk",
+                    3,
+                    'a',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    ID::EnvelopeRelease as i32,
+                    "env-rel",
+                    8,
+                    'd',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = GranularModelParamID;
+        let typed_id: GranularModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Capture => {
+                self.capturing = new_value > 0.5;
+                self.buffer.set_freezed(!self.capturing);
+            }
+            ID::Density => self.density = new_value,
+            ID::Size => self.size = new_value,
+            ID::Position => self.position = new_value,
+            ID::Spray => self.spray = new_value,
+            ID::Pitch => self.pitch = new_value.round(),
+            ID::Reverse => self.reverse = new_value,
+            //envelope
+            ID::EnvelopeAttack => self.envelope.set_attack(new_value),
+            ID::EnvelopeRelease => self.envelope.set_release(new_value),
+        }
+    }
+}
+
+impl Synth for GranularModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::with_defaults;
+
+    //a second of a 480Hz sine, its period is 100 samples
+    fn granular_model() -> GranularModel {
+        let sine = (0..48000)
+            .map(|i| (std::f32::consts::TAU * i as f32 / 100.).sin())
+            .collect();
+        with_defaults(GranularModel::with_sample(Arc::new(Sample::from_samples("sine", 48000., sine))))
+    }
+
+    fn grains(model: &GranularModel) -> Vec<Grain> {
+        model.grains.iter().flatten().copied().collect()
+    }
+
+    #[test]
+    fn notes_pitch_the_grains() {
+        let mut model = granular_model();
+        assert_eq!(model.recorded, 48000.);
+        let mut left = [0.; 512];
+        let mut right = [0.; 512];
+        model.process_block(&mut left, &mut right);
+        assert!(left.iter().all(|sample| *sample == 0.));
+        assert!(grains(&model).is_empty());
+
+        model.set_note(MidiMessage::NoteOn(72));
+        //past the attack
+        for _ in 0..10 {
+            model.process_block(&mut left, &mut right);
+        }
+        assert!(left.iter().any(|sample| sample.abs() > 0.01));
+        assert!(grains(&model).iter().all(|grain| grain.step == 2.));
+
+        model.set_parameter((GranularModelParamID::Reverse as i32, 1.));
+        model.set_parameter((GranularModelParamID::Pitch as i32, -12.));
+        for _ in 0..20 {
+            model.process_block(&mut left, &mut right);
+        }
+        assert!(grains(&model).iter().any(|grain| grain.step == -1.));
+        //grains never read outside of the recording
+        assert!(grains(&model)
+            .iter()
+            .all(|grain| grain.delay >= 0. && grain.delay < model.recorded));
+    }
+
+    #[test]
+    fn capture_own_output() {
+        let mut model = granular_model();
+        let newest = model.buffer.read_behind(0.);
+        model.set_note(MidiMessage::NoteOn(60));
+        let mut left = [0.; 512];
+        let mut right = [0.; 512];
+        model.process_block(&mut left, &mut right);
+        //frozen by default
+        assert_eq!(model.buffer.read_behind(0.), newest);
+
+        model.set_parameter((GranularModelParamID::Capture as i32, 1.));
+        model.process_block(&mut left, &mut right);
+        assert_ne!(model.buffer.read_behind(0.), newest);
+        //the old recording moved back by a block
+        assert_eq!(model.buffer.read_behind(512.), newest);
+    }
+
+    #[test]
+    fn capture_stays_bounded() {
+        let mut model = granular_model();
+        model.set_parameter((GranularModelParamID::Capture as i32, 1.));
+        model.set_parameter((GranularModelParamID::Density as i32, 100.));
+        model.set_parameter((GranularModelParamID::Size as i32, 500.));
+        model.set_note(MidiMessage::NoteOn(60));
+        let mut left = [0.; 512];
+        let mut right = [0.; 512];
+        //the whole buffer is read back several times
+        for _ in 0..4 * model.buffer.capacity() / 512 {
+            model.process_block(&mut left, &mut right);
+            assert!(left.iter().all(|sample| sample.is_finite() && sample.abs() < 4.));
+        }
+    }
+}
@@ -4,11 +4,12 @@ use crate::buffer::DelayMode;
 use crate::envelope;
 use crate::envelope::Envelope;
 use crate::midi::MidiMessage;
-use crate::midibuffer::PolyMidiBuffer;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode, DEFAULT_NUMBER_OF_VOICES};
 use crate::outils;
 use crate::parameters::ParameterCapsule;
 use crate::reverb::Reverb;
 use crate::synth::HasConstructor;
+use crate::synth::HasDescription;
 use crate::synth::HasEngine;
 use crate::synth::HasMidiInput;
 use crate::synth::HasParameters;
@@ -22,104 +23,229 @@ use crate::parameters::Parameters;
 use crate::ParameterUpdate;
 use num_derive::FromPrimitive;
 
-const NUMBER_OF_VOICES: usize = 4;
-const VOICE_ITERATOR: std::ops::Range<usize> = 0..NUMBER_OF_VOICES;
-
-const NB_SYNTH_PARAM: usize = 11;
+const NB_SYNTH_PARAM: usize = 19;
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
 
 #[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
 pub enum HarmonicModelParamID {
     OscHarmonicRatio,
     OscHarmonicGain,
+    OscPartials,
+    OscOddEven,
+    OscTilt,
+    OscStretch,
+    OscPhaseRandomize,
     EnvelopeAttack,
     EnvelopeRelease,
+    Glide,
+    Legato,
     FilterCutoff,
     DelayTime,
     DelayFeedback,
     DelayDryWet,
     ReverbTime,
     ReverbDryWet,
+    StereoWidth,
     Volume,
 }
 
+#[derive(Clone)]
+struct HarmonicVoice {
+    envelope: Envelope,
+    oscillator: HarmonicOscillator,
+}
+
+impl Voice for HarmonicVoice {
+    type Context = ();
+
+    fn start(&mut self, _midi_note: u8, retrigger: bool, _: &mut ()) {
+        if retrigger {
+            self.envelope.note_on();
+            self.oscillator.randomize_phases();
+        }
+    }
+
+    fn release(&mut self, _: &()) {
+        self.envelope.note_off();
+    }
+
+    fn steal(&mut self, _: &()) {
+        self.envelope.steal();
+    }
+
+    fn level(&self, _: &()) -> f32 {
+        self.envelope.value()
+    }
+
+    fn is_silent(&self, _: &()) -> bool {
+        self.envelope.status == envelope::Segment::Off
+    }
+
+    fn set_frequency(&mut self, frequency_hz: f32) {
+        self.oscillator.set_frequency(frequency_hz);
+    }
+}
+
 pub struct HarmonicModel {
-    envelopes: [Envelope; NUMBER_OF_VOICES],
-    oscillators: [HarmonicOscillator; NUMBER_OF_VOICES],
-    midibuffer: PolyMidiBuffer,
-    reverb: Reverb,
-    delay: DelayLine,
-    low_pass: Biquad,
+    voices: VoiceManager<HarmonicVoice>,
+    reverbs: [Reverb; 2],
+    delays: [DelayLine; 2],
+    low_passes: [Biquad; 2],
     //parameters
+    delay_time: f32,
     delay_dry_wet: f32,
+    stereo_width: f32,
     volume: f32,
 }
 
 
+impl HasDescription for HarmonicModel{
+    const NAME: &'static str = "Harmonic";
+    const DESCRIPTION: &'static str = "additive voices, low-pass, stereo delay and reverb";
+}
+
 impl HasConstructor for HarmonicModel{
      fn new() -> Self {
-        HarmonicModel {
-            reverb: Reverb::new(),
-            envelopes: [Envelope::new(); NUMBER_OF_VOICES],
-            oscillators: [HarmonicOscillator::new(500.); NUMBER_OF_VOICES],
-            midibuffer: PolyMidiBuffer::new(NUMBER_OF_VOICES),
-            low_pass: Biquad::new(crate::filter::FilterType::LPF),
-            delay: DelayLine::new(buffer::MAXIMUM_DELAY_TIME, DelayMode::Comb),
-            delay_dry_wet: 0.0,
-            volume: 0.5,
-        }
+        Self::with_voices(DEFAULT_NUMBER_OF_VOICES, VoiceMode::Oldest)
     }
 
     fn init(&mut self, sample_rate: f32){
-            self.reverb.init(sample_rate);
-            self.envelopes.iter_mut().for_each(|x|x.init(sample_rate));
-            self.oscillators.iter_mut().for_each(|x|x.init(sample_rate));
-            self.low_pass.init(sample_rate);
-            self.delay.init(sample_rate);
+            self.reverbs.iter_mut().for_each(|x|x.init(sample_rate));
+            self.voices.init(sample_rate);
+            for voice in self.voices.iter_mut() {
+                voice.envelope.init(sample_rate);
+                voice.oscillator.init(sample_rate);
+            }
+            self.low_passes.iter_mut().for_each(|x|x.init(sample_rate));
+            self.delays.iter_mut().for_each(|x|x.init(sample_rate));
     }
 }
 
 impl HasMidiInput for HarmonicModel {
     fn set_note(&mut self, message: MidiMessage) {
         match message {
-            MidiMessage::NoteOff(midi_note) => self.midibuffer.remove_note(midi_note),
-            MidiMessage::NoteOn(midi_note) => self.midibuffer.add_note(midi_note),
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut ()),
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut ()),
             _ => {}
         }
+    }
+}
+
+impl HarmonicModel {
+    ///`voices` notes at once, `mode` chooses the voice a new note steals
+    pub fn with_voices(voices: usize, mode: VoiceMode) -> Self {
+        let voice = HarmonicVoice {
+            envelope: Envelope::new(),
+            oscillator: HarmonicOscillator::new(500.),
+        };
+        HarmonicModel {
+            //slightly different allpass times on the right side to decorrelate the tails
+            reverbs: [Reverb::new(), Reverb::new_with_time_scale(1.13)],
+            voices: VoiceManager::new(vec![voice; voices.max(1)], mode),
+            low_passes: [
+                Biquad::new(crate::filter::FilterType::LPF),
+                Biquad::new(crate::filter::FilterType::LPF),
+            ],
+            delays: [
+                DelayLine::new(buffer::MAXIMUM_DELAY_TIME, DelayMode::Comb),
+                DelayLine::new(buffer::MAXIMUM_DELAY_TIME, DelayMode::Comb),
+            ],
+            delay_time: buffer::MAXIMUM_DELAY_TIME,
+            delay_dry_wet: 0.0,
+            stereo_width: 0.0,
+            volume: 0.5,
+        }
+    }
 
-        for i in VOICE_ITERATOR {
-            match self.midibuffer.notes.get(i) {
-                None => self.envelopes[i].note_off(),
-                Some(midi_note) => {
-                    self.envelopes[i].note_on();
-                    self.oscillators[i].set_note(*midi_note)
+    ///a chord and its last second of release, the synth's own sound used by the models reading a sample
+    pub fn render_chord(sample_rate: f32, length: f32) -> Vec<f32> {
+        let mut model = HarmonicModel::new();
+        model.init(sample_rate);
+        for capsule in model.get_parameters().capsules.iter() {
+            model.set_parameter((capsule.id, capsule.parameter.get_raw_value()));
+        }
+        for midi_note in [48, 55, 63, 70] {
+            model.set_note(MidiMessage::NoteOn(midi_note));
+        }
+        let mut left = vec![0.; (length * sample_rate) as usize];
+        let mut right = left.clone();
+        let release = ((length - 1.) * sample_rate) as usize;
+        for (i, (left, right)) in left.chunks_mut(256).zip(right.chunks_mut(256)).enumerate() {
+            if (i * 256..(i + 1) * 256).contains(&release) {
+                for midi_note in [48, 55, 63, 70] {
+                    model.set_note(MidiMessage::NoteOff(midi_note));
                 }
             }
+            model.process_block(left, right);
+        }
+        left
+    }
+
+    ///spread the voices evenly from left to right according to the stereo width
+    fn voice_pan(&self, voice_index: usize) -> f32 {
+        if self.voices.len() == 1 {
+            return 0.;
         }
+        let position = voice_index as f32 / (self.voices.len() - 1) as f32;
+        (position * 2. - 1.) * self.stereo_width
+    }
+
+    ///the right delay get longer with the width, so the echoes bounce between the sides
+    fn update_delay_times(&mut self) {
+        self.delays[LEFT].set_delay_time(self.delay_time);
+        let right_time = self.delay_time * (1. + 0.5 * self.stereo_width);
+        self.delays[RIGHT].set_delay_time(right_time.min(buffer::MAXIMUM_DELAY_TIME));
     }
 }
 
 impl HasEngine for HarmonicModel {
     fn process(&mut self) -> f32 {
-        let mut sample: f32 = 0.;
-        for i in VOICE_ITERATOR {
-            match self.envelopes[i].status {
-                envelope::Segment::Off => {}
-                _ => sample += self.oscillators[i].process() * self.envelopes[i].process(),
-            }
-        }
-        sample /= 4.;
-        sample = self.low_pass.process(sample);
-        // EFFECTS
-        let delay:f32 = self.delay.process(sample);
+        let (left, right) = self.process_stereo();
+        (left + right) * 0.5
+    }
 
-        sample = outils::equal_power_crossfade(sample, delay, self.delay_dry_wet);
+    fn process_stereo(&mut self) -> (f32, f32) {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        (left[0], right[0])
+    }
 
-        sample = self.reverb.process(sample);
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.fill(0.);
+        right.fill(0.);
+        for i in 0..self.voices.len() {
+            if self.voices[i].envelope.status == envelope::Segment::Off && self.voices.pending_note(i).is_none() {
+                continue;
+            }
+            //compensate the -3dB of the pan law at the center, and divide by the number of voices
+            let (left_gain, right_gain) = outils::equal_power_pan(
+                std::f32::consts::SQRT_2 / self.voices.len() as f32,
+                self.voice_pan(i),
+            );
+            for (left_sample, right_sample) in left.iter_mut().zip(right.iter_mut()) {
+                self.voices.update(i, &mut ());
+                let voice = &mut self.voices[i];
+                if voice.envelope.status == envelope::Segment::Off {
+                    break;
+                }
+                let voice = voice.oscillator.process() * voice.envelope.process();
+                *left_sample += voice * left_gain;
+                *right_sample += voice * right_gain;
+            }
+        }
+        for (side, buffer) in [(LEFT, left), (RIGHT, right)] {
+            self.low_passes[side].process_block(buffer);
+            for sample in buffer.iter_mut() {
+                // EFFECTS
+                let delay: f32 = self.delays[side].process(*sample);
 
-        //vca
-        sample *= self.volume;
+                let wet = outils::equal_power_crossfade(*sample, delay, self.delay_dry_wet);
 
-        return sample;
+                //vca
+                *sample = self.reverbs[side].process(wet) * self.volume;
+            }
+        }
     }
 }
 
@@ -148,6 +274,11 @@ impl HasParameters for HarmonicModel {
                     3.,
                     1.,
                 ),
+                P::new(ID::OscPartials as i32, "partials", 4, 'n', 1., 36., 1.),
+                P::new(ID::OscOddEven as i32, "odd-even", 17, 'o', -17., 18., 1.),
+                P::new(ID::OscTilt as i32, "tilt", 17, 'l', -17., 18., 1.),
+                P::new(ID::OscStretch as i32, "stretch", 10, 'k', -0.05, 0.125, 1.),
+                P::new(ID::OscPhaseRandomize as i32, "phase-rnd", 0, 'p', 0., 1., 1.),
                 //envelope
                 P::new(
                     ID::EnvelopeAttack as i32,
@@ -167,6 +298,9 @@ impl HasParameters for HarmonicModel {
                     envelope::MAXIMUM_ENVELOPE_TIME,
                     2.,
                 ),
+                //mono
+                P::new(ID::Glide as i32, "glide", 0, 'i', 0., 2000., 2.),
+                P::new(ID::Legato as i32, "legato", 0, 'e', 0., 1., 1.),
                 P::new(ID::FilterCutoff as i32, "cutoff", 36, 'c', 20., 20000., 4.),
                 //Delay
                 P::new(
@@ -183,6 +317,7 @@ impl HasParameters for HarmonicModel {
                 P::new(ID::ReverbDryWet as i32, "rvb-wet", 0, 'r', 0., 1., 1.),
                 P::new(ID::ReverbTime as i32, "rvb-time", 0, '9', 0., 0.99, 1.),
                 //global
+                P::new(ID::StereoWidth as i32, "width", 0, 's', 0., 1., 1.),
                 P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
             ],
             nb_param: NB_SYNTH_PARAM,
@@ -199,34 +334,73 @@ impl HasParameters for HarmonicModel {
         let typed_id: HarmonicModelParamID = num::FromPrimitive::from_i32(id).unwrap();
         match typed_id {
             ID::Volume => self.volume = new_value,
-            ID::ReverbDryWet => self.reverb.set_reverb_time(new_value),
-            ID::ReverbTime => self.reverb.dry_wet = new_value,
+            ID::ReverbDryWet => self
+                .reverbs
+                .iter_mut()
+                .for_each(|reverb| reverb.set_reverb_time(new_value)),
+            ID::ReverbTime => self
+                .reverbs
+                .iter_mut()
+                .for_each(|reverb| reverb.dry_wet = new_value),
+            ID::StereoWidth => {
+                self.stereo_width = new_value;
+                self.update_delay_times()
+            }
             //oscillator
             ID::OscHarmonicGain => self
-                .oscillators
+                .voices
                 .iter_mut()
-                .for_each(|osc| osc.harmonic_gain_exponent = new_value),
+                .for_each(|voice| voice.oscillator.set_harmonic_gain_exponent(new_value)),
             ID::OscHarmonicRatio => self
-                .oscillators
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.oscillator.set_harmonic_index_increment(new_value)),
+            ID::OscPartials => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.oscillator.set_partial_count(new_value.round() as usize)),
+            //in 17th, so the middle of the orca range is balanced
+            ID::OscOddEven => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.oscillator.set_odd_even(new_value / 17.)),
+            ID::OscTilt => self
+                .voices
                 .iter_mut()
-                .for_each(|osc| osc.harmonic_index_increment = new_value),
+                .for_each(|voice| voice.oscillator.set_tilt(new_value)),
+            ID::OscStretch => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.oscillator.set_stretch(new_value)),
+            ID::OscPhaseRandomize => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.oscillator.set_phase_randomize(new_value)),
             // envelope
             ID::EnvelopeAttack => self
-                .envelopes
+                .voices
                 .iter_mut()
-                .for_each(|env| env.set_attack(new_value)),
+                .for_each(|voice| voice.envelope.set_attack(new_value)),
             ID::EnvelopeRelease => self
-                .envelopes
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_release(new_value)),
+            ID::Glide => self.voices.set_glide_time(new_value),
+            ID::Legato => self.voices.set_legato(new_value > 0.5),
+            ID::FilterCutoff => self
+                .low_passes
                 .iter_mut()
-                .for_each(|env| env.set_release(new_value)),
-            ID::FilterCutoff => self.low_pass.set_frequency(new_value),
+                .for_each(|filter| filter.set_frequency(new_value)),
             //delay
             ID::DelayDryWet => self.delay_dry_wet = new_value,
-            ID::DelayTime => self.delay.set_delay_time(new_value),
-            ID::DelayFeedback => {
-                self.delay.set_freeze(new_value > 0.99);
-                self.delay.set_feedback(new_value)
+            ID::DelayTime => {
+                self.delay_time = new_value;
+                self.update_delay_times()
             }
+            ID::DelayFeedback => self.delays.iter_mut().for_each(|delay| {
+                delay.set_freeze(new_value > 0.99);
+                delay.set_feedback(new_value)
+            }),
         }
     }
 }
@@ -0,0 +1,92 @@
+//! Laminaria's synthesis core: the `Synth` traits, the models, and the DSP blocks they are made of
+//! (`Envelope`, `Biquad`, `DelayLine`, `Reverb`, `HarmonicOscillator`...).
+//!
+//! It has no audio, MIDI or terminal dependency, the `Laminaria` binary adds them.
+//! Its `AudioEngine` can be driven by any output giving interleaved buffers.
+//!
+//! Render a note offline :
+//!
+//! ```
+//! use laminaria::midi::MidiMessage;
+//! use laminaria::registry::ModelRegistry;
+//!
+//! let registry = ModelRegistry::with_builtin_models();
+//! let mut synth_model = registry.build_by_name("sine").unwrap();
+//! synth_model.init(48000.);
+//! synth_model.set_note(MidiMessage::NoteOn(60));
+//!
+//! let mut left = [0.; 256];
+//! let mut right = [0.; 256];
+//! synth_model.process_block(&mut left, &mut right);
+//! ```
+//!
+//! New models implement `Synth` and `HasDescription`, and are added to a `ModelRegistry`.
+
+pub mod buffer;
+pub mod chorus;
+pub mod drum_model;
+pub mod engine;
+pub mod envelope;
+pub mod filter;
+pub mod fm_model;
+pub mod formant_model;
+pub mod granular_model;
+pub mod harmonic_model;
+pub mod midi;
+pub mod midibuffer;
+pub mod modal_model;
+pub mod model_switch;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod null_backend;
+pub mod organ_model;
+pub mod oscillator;
+pub mod outils;
+pub mod parameters;
+pub mod pluck_model;
+pub mod queue;
+pub mod registry;
+pub mod render;
+pub mod reverb;
+pub mod rotary;
+pub mod sample;
+pub mod sampler_model;
+pub mod sine_model;
+pub mod spectral_model;
+pub mod synth;
+#[cfg(test)]
+mod test_utils;
+pub mod textparsing;
+pub mod va_model;
+pub mod wavetable;
+pub mod wavetable_model;
+extern crate num;
+extern crate num_derive;
+
+pub use buffer::{DelayLine, RingBuffer};
+pub use chorus::Chorus;
+pub use drum_model::DrumModel;
+pub use envelope::Envelope;
+pub use filter::Biquad;
+pub use fm_model::FmModel;
+pub use formant_model::FormantModel;
+pub use granular_model::GranularModel;
+pub use harmonic_model::HarmonicModel;
+pub use midibuffer::{Voice, VoiceAllocator, VoiceManager, VoiceMode};
+pub use modal_model::ModalModel;
+pub use organ_model::OrganModel;
+pub use oscillator::{HarmonicOscillator, Lfo, PolyBlepOscillator, SineWave};
+pub use pluck_model::PluckModel;
+pub use reverb::Reverb;
+pub use rotary::Rotary;
+pub use sample::Sample;
+pub use sampler_model::{SampleZone, SamplerModel};
+pub use sine_model::SineModel;
+pub use spectral_model::SpectralModel;
+pub use synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+pub use textparsing::{text_to_phonemes, Phoneme, PhonemeSequence, TextCharacteristic};
+pub use va_model::VaModel;
+pub use wavetable::Wavetable;
+pub use wavetable_model::WavetableModel;
+
+///parameter id and its new raw value
+pub type ParameterUpdate = (i32, f32);
@@ -1,48 +1,35 @@
 //PERSO
 mod audio;
-mod harmonic_model;
-mod midi;
-mod parameters;
-mod synth;
+mod midi_input;
+mod render_command;
+mod settings;
+mod udp_input;
 mod ui;
-pub use harmonic_model::HarmonicModel;
-mod sine_model;
-pub use crate::sine_model::SineModel;
 use crate::ui::clean_terminal;
 use crate::ui::init_terminal;
-mod oscillator;
-pub use oscillator::HarmonicOscillator;
-pub use oscillator::Lfo;
-mod filter;
-pub use filter::Biquad;
-mod buffer;
-pub use buffer::RingBuffer;
-mod chorus;
-mod outils;
-pub use chorus::Chorus;
-mod textparsing;
-pub use textparsing::TextCharacteristic;
-mod envelope;
-mod midibuffer;
-mod reverb;
-extern crate num;
-extern crate num_derive;
-
-type ParameterUpdate = (i32, f32);
+use laminaria::engine::EngineMessage;
+use laminaria::midibuffer::DEFAULT_NUMBER_OF_VOICES;
+use laminaria::registry::ModelEntry;
+use laminaria::synth::HasDescription;
+use laminaria::{
+    model_switch, queue, registry, GranularModel, HarmonicModel, Sample, SampleZone, SamplerModel, SineModel,
+    SpectralModel, VoiceMode, Wavetable, WavetableModel,
+};
+use std::path::PathBuf;
 
 //std and extern stuff
 use std::error::Error;
 extern crate anyhow;
 extern crate clap;
-use crate::clap::Parser;
+use crate::clap::{Parser, Subcommand};
 extern crate cpal;
 use crate::cpal::traits::StreamTrait;
-use crate::midi::MidiMessage;
-use crate::synth::HasConstructor;
-use crate::synth::Synth;
+use laminaria::midi::TimedMidiMessage;
+use laminaria::synth::Synth;
 use crate::ui::option_menu;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
 
 #[derive(Parser, Debug)]
@@ -50,6 +37,61 @@ use std::sync::{Arc, Mutex};
 struct Args {
     #[arg(short, long, default_value_t = 0)]
     channel: u8,
+    ///audio host, ex: ALSA or JACK
+    #[arg(long)]
+    host: Option<String>,
+    ///output device name, see --list-devices
+    #[arg(long)]
+    device: Option<String>,
+    #[arg(long)]
+    sample_rate: Option<u32>,
+    ///in frames
+    #[arg(long)]
+    buffer_size: Option<u32>,
+    ///choose the audio host, device, sample rate and buffer size in menus
+    #[arg(short, long)]
+    audio_menu: bool,
+    ///print the audio hosts and their output devices, then quit
+    #[arg(long)]
+    list_devices: bool,
+    ///start with this synth model instead of the menu, see --list-models
+    #[arg(short, long)]
+    model: Option<String>,
+    ///print the synth models, then quit
+    #[arg(long)]
+    list_models: bool,
+    ///wav file of single cycles added to the tables of the Wavetable model, can be repeated
+    #[arg(long = "wavetable", global = true)]
+    wavetables: Vec<PathBuf>,
+    ///length of one cycle in the wavetable files
+    #[arg(long, default_value_t = 2048, global = true)]
+    frame_size: usize,
+    ///wav or aiff file played by the Granular model instead of its own sound
+    #[arg(long, global = true)]
+    grain_sample: Option<PathBuf>,
+    ///wav or aiff file analysed by the Spectral model instead of its own sound
+    #[arg(long, global = true)]
+    spectral_sample: Option<PathBuf>,
+    ///wav or aiff file of the Sampler model as `<file>[@<lowest>-<highest>[@<root>]]`, can be repeated
+    #[arg(long = "sample", global = true)]
+    samples: Vec<String>,
+    ///notes played at once by the Harmonic and Sine models
+    #[arg(long, global = true)]
+    voices: Option<usize>,
+    ///voice a new note steals when they are all playing : oldest, quietest, lowest, highest or round-robin, or a single voice with mono, mono-low or mono-high
+    #[arg(long, global = true)]
+    voice_mode: Option<VoiceMode>,
+    ///listen to the text sent by ORCA on this udp port, 49160 is the one of its `;` operator
+    #[arg(long)]
+    udp_port: Option<u16>,
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Mode {
+    ///Render a patch and a note sequence to a wav file, without any audio device
+    Render(render_command::RenderArgs),
 }
 
 pub use crossterm::{
@@ -63,6 +105,93 @@ pub use crossterm::{
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
     let midi_channel: u8;
+    let mut registry = registry::ModelRegistry::with_builtin_models();
+    if !args.wavetables.is_empty() {
+        let mut tables = vec![];
+        for path in args.wavetables.iter() {
+            tables.push(Arc::new(Wavetable::from_wav(path, args.frame_size)?));
+        }
+        //the files are loaded once, every new model shares them
+        registry.register(ModelEntry::new(
+            WavetableModel::NAME,
+            WavetableModel::DESCRIPTION,
+            move || Box::new(WavetableModel::with_tables(tables.clone())),
+        ));
+    }
+
+    if let Some(path) = &args.grain_sample {
+        let sample = Arc::new(Sample::from_file(path)?);
+        registry.register(ModelEntry::new(
+            GranularModel::NAME,
+            GranularModel::DESCRIPTION,
+            move || Box::new(GranularModel::with_sample(sample.clone())),
+        ));
+    }
+
+    if let Some(path) = &args.spectral_sample {
+        let sample = Arc::new(Sample::from_file(path)?);
+        registry.register(ModelEntry::new(
+            SpectralModel::NAME,
+            SpectralModel::DESCRIPTION,
+            move || Box::new(SpectralModel::with_sample(sample.clone())),
+        ));
+    }
+
+    if args.voices.is_some() || args.voice_mode.is_some() {
+        let voices = args.voices.unwrap_or(DEFAULT_NUMBER_OF_VOICES);
+        let mode = args.voice_mode.unwrap_or_default();
+        registry.register(ModelEntry::new(
+            HarmonicModel::NAME,
+            HarmonicModel::DESCRIPTION,
+            move || Box::new(HarmonicModel::with_voices(voices, mode)),
+        ));
+        registry.register(ModelEntry::new(
+            SineModel::NAME,
+            SineModel::DESCRIPTION,
+            move || Box::new(SineModel::with_voices(voices, mode)),
+        ));
+    }
+
+    if !args.samples.is_empty() {
+        let mut zones = vec![];
+        for spec in args.samples.iter() {
+            zones.push(SampleZone::parse(spec)?);
+        }
+        registry.register(ModelEntry::new(
+            SamplerModel::NAME,
+            SamplerModel::DESCRIPTION,
+            move || Box::new(SamplerModel::with_zones(zones.clone())),
+        ));
+    }
+
+    if let Some(Mode::Render(render_args)) = args.mode {
+        render_command::run(render_args, &registry)?;
+        return Ok(());
+    }
+
+    if args.list_models {
+        print!("{}", registry.describe());
+        return Ok(());
+    }
+    //check the name before touching the terminal
+    let model_choice = match &args.model {
+        Some(name) => match registry.find(name) {
+            Some(index) => Some(index),
+            None => {
+                eprintln!("Unknown model '{}', available :\n{}", name, registry.describe());
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    if args.list_devices {
+        audio::list_devices()?;
+        return Ok(());
+    }
+
+    //the saved setup, overridden by the menu or the command line
+    let mut audio_settings = settings::AudioSettings::load();
 
     std::panic::set_hook(Box::new(|info| {
         ui::clean_terminal();
@@ -77,57 +206,90 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     init_terminal();
 
-    let options = vec![
-        "Harmonic".to_string(),
-        "Sine".to_string(),
-    ];
-    let synth_model: Box<dyn Synth> = match option_menu(options, "Select Synth Model".to_string()) {
-        0 => {Box::new(HarmonicModel::new())},
-        1 => {Box::new(SineModel::new())},
-        _ => {Box::new(SineModel::new())},
+    if args.audio_menu {
+        audio_settings = audio::select_audio_settings()?;
+    }
+    audio_settings.merge(settings::AudioSettings {
+        host: args.host,
+        device: args.device,
+        sample_rate: args.sample_rate,
+        buffer_size: args.buffer_size,
+    });
+
+    let model_index = match model_choice {
+        Some(index) => index,
+        None => option_menu(registry.names(), "Select Synth Model".to_string()),
+    };
+    let (model_index, synth_model): (usize, Box<dyn Synth>) = match registry.build(model_index) {
+        Some(synth_model) => (model_index, synth_model),
+        None => (0, registry.build(0).expect("the registry is empty")),
     };
 
-    // initialize channels
-    let (parameter_sender, parameter_receiver): (
-        Sender<ParameterUpdate>,
-        Receiver<ParameterUpdate>,
-    ) = channel();
+    // initialize channels, the ones read by the audio thread are lock free rings
+    let overflows = Arc::new(AtomicUsize::new(0));
+    let (mut parameter_sender, parameter_receiver) =
+        queue::bounded::<EngineMessage>(queue::QUEUE_CAPACITY, overflows.clone());
     let (ui_sender, ui_receiver): (Sender<ui::UiEvent>, Receiver<ui::UiEvent>) = channel();
-    let (midi_sender, midi_receiver): (Sender<MidiMessage>, Receiver<MidiMessage>) = channel();
+    //text from ORCA, parsed by the keyboard loop
+    let (text_sender, text_receiver): (Sender<String>, Receiver<String>) = channel();
+    if let Some(port) = args.udp_port {
+        udp_input::listen_udp(port, text_sender)?;
+    }
+    let (midi_sender, midi_receiver) =
+        queue::bounded::<TimedMidiMessage>(queue::QUEUE_CAPACITY, overflows.clone());
+    //old models come back from the audio thread to be dropped
+    let (retired_sender, retired_receiver) =
+        queue::bounded::<Box<model_switch::ModelSwap>>(queue::QUEUE_CAPACITY, overflows.clone());
 
     //initialize parameter system
     let parameters = synth_model.get_parameters();
-    let number_of_params = parameters.nb_param;
     let defaults = parameters.capsules.clone();
     let parameters_mutex = Arc::new(Mutex::new(parameters));
     let parameters_clone_ui = parameters_mutex.clone();
     let parameters_clone_interaction = parameters_mutex.clone();
 
     let ui_sender_interaction_thread = ui_sender.clone();
-    let parameter_sender_interaction_thread = parameter_sender.clone();
+
+    // set default values, waiting in the ring until the audio thread starts
+    for caps in defaults.iter() {
+        let _ = parameter_sender.send(EngineMessage::Parameter((
+            caps.id,
+            caps.parameter.get_raw_value(),
+        )));
+    }
 
     // INIT AUDIO THREAD
-    let stream = audio::stream_setup_for(parameter_receiver, midi_receiver, synth_model)?;
+    let (stream, audio_setup, sample_rate) = audio::stream_setup_for(
+        parameter_receiver,
+        midi_receiver,
+        retired_sender,
+        synth_model,
+        defaults,
+        &audio_settings,
+    )?;
     stream.play()?;
-
-    // set default values
-    for caps in defaults {
-        parameter_sender.send((caps.id, caps.parameter.get_raw_value()))?
+    //remember the setup once we know it works
+    if let Err(error) = audio_settings.save() {
+        println!("Can't save the audio settings : {}", error);
     }
+    ui_sender.send(ui::UiEvent::UpdateAudioSetup(audio_setup))?;
+    let model_switcher = model_switch::ModelSwitcher::new(registry, sample_rate, model_index, retired_receiver);
+    ui_sender.send(ui::UiEvent::UpdateModelName(model_switcher.current_name()))?;
 
     // INIT UI THREAD
     let _ui_thread = std::thread::Builder::new()
         .name("UI".to_string())
-        .spawn(move || ui::gui(parameters_clone_ui, ui_receiver, number_of_params));
+        .spawn(move || ui::gui(parameters_clone_ui, ui_receiver, overflows));
 
     // INIT INTERACTION THREAD
     ui::keyboard_input(
         parameters_clone_interaction,
-        parameter_sender_interaction_thread,
+        parameter_sender,
         ui_sender_interaction_thread,
         midi_sender,
         midi_channel,
-        number_of_params,
+        model_switcher,
+        text_receiver,
     );
 
     clean_terminal();
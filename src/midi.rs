@@ -1,11 +1,4 @@
-use crate::ui::option_menu;
-use crate::{outils, ParameterUpdate};
-use crossterm::{cursor, terminal};
-use midir::{Ignore, MidiInput, MidiInputConnection};
-use std::collections::HashMap;
-use std::error::Error;
-use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 const NOTE_OFF_MASK: u8 = 0b1000_0000;
 const NOTE_ON_MASK: u8 = 0b1001_0000;
@@ -30,21 +23,27 @@ const STOP_CODE: u8 = 0b1111_1100;
 const ACTIVE_SENSING_CODE: u8 = 0b1111_1110;
 const SYSTEM_RESET_CODE: u8 = 0b1111_1111;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MidiMessage {
     ///note number
     NoteOff(u8),
     ///note number
     NoteOn(u8),
     ControlChange(u8, u8),
+    ///program number
+    ProgramChange(u8),
     None,
 }
 
+///midi message and the time it was received at, so the audio thread can place it inside the buffer
+pub type TimedMidiMessage = (MidiMessage, Instant);
+
 //stole from https://github.com/chris-zen/kiro-synth/blob/master/kiro-midi-core/src/decoder.rs
 ///take message one and two, return the channel and the midiMessage
 /// * `status` - first midi message, contain the event type and the channel index
 /// * `note` - second midi message, contain the note number / cc index
 /// * `velocity` - third midi message, contain the velocity / cc value
-fn raw_midi_to_message(status: u8, note: u8, velocity: u8) -> (u8, MidiMessage) {
+pub fn raw_midi_to_message(status: u8, note: u8, velocity: u8) -> (u8, MidiMessage) {
     let channel = status & 0x0f;
     type MM = MidiMessage;
     match status & 0xf0 {
@@ -56,105 +55,12 @@ fn raw_midi_to_message(status: u8, note: u8, velocity: u8) -> (u8, MidiMessage)
             (channel, MM::NoteOn(note))
         }
         CONTROL_CHANGE_MASK => (channel, MM::ControlChange(note, velocity)),
+        PROGRAM_CHANGE_MASK => (channel, MM::ProgramChange(note)),
         _ => (channel, MM::None),
     }
 }
 
-use crate::parameters::Parameters;
-use crate::ui::UiEvent;
-
-pub fn connect_midi(
-    midi_sender: Sender<MidiMessage>,
-    parameter_clone: Arc<Mutex<Parameters>>,
-    parameter_sender: Sender<ParameterUpdate>,
-    gui_sender: Sender<UiEvent>,
-    channel_index: Arc<Mutex<u8>>,
-) -> Result<(MidiInputConnection<()>, String), Box<dyn Error>> {
-    let mut midicc_hash: HashMap<u8, i32> = HashMap::new();
-    for capsule in parameter_clone.lock().unwrap().capsules.iter() {
-        let id = capsule.id;
-        let cc = capsule.parameter.midicc;
-        midicc_hash.insert(outils::get_orca_integer(cc).unwrap_or(0), id);
-    }
-    let mut selection = 0;
-    let mut midi_in = MidiInput::new("midir reading input")?;
-    midi_in.ignore(Ignore::None);
-    println!("{}", terminal::Clear(terminal::ClearType::All));
-    println!("{}", cursor::MoveTo(0, 0));
-    // Get an input port (read from console if multiple are available)
-    let in_ports = midi_in.ports();
-    let number_of_option = in_ports.len();
-    let in_port = match number_of_option {
-        0 => return Err("no input port found".into()),
-        1 => {
-            //choose the only connection avaible
-            &in_ports[0]
-        }
-        _ => {
-            let mut options: Vec<String> = vec![];
-            for (i, p) in in_ports.iter().enumerate() {
-                options.push(midi_in.port_name(p).unwrap());
-            }
-            selection = option_menu(options, "Select MIDI Port".to_string());
-            in_ports
-                .get(selection)
-                .ok_or("invalid input port selected")?
-        }
-    };
-
-    println!("\nOpening connection");
-
-    let port_name = midi_in.port_name(&in_ports[selection as usize]).unwrap();
-
-    // _conn_in needs to be a named parameter, because it needs to be kept alive until the end of the scope
-    let _conn_in = (
-        midi_in.connect(
-            in_port,
-            "midir-read-input",
-            move |_stamp, message, _| {
-                let (channel, midi_message): (u8, MidiMessage);
-                if message.len() < 3 {
-                    (channel, midi_message) = raw_midi_to_message(message[0], 0, 0);
-                } else {
-                    (channel, midi_message) =
-                        raw_midi_to_message(message[0], message[1], message[2]);
-                }
-                //check if CC
-                // println!("{}: {:?}", _stamp, message);
-                if channel == *channel_index.lock().unwrap() {
-                    match midi_message {
-                        MidiMessage::ControlChange(cc, midi_value) => {
-                            //try to get parameter name from name table
-                            match midicc_hash.get(&cc) {
-                                Some(id) => {
-                                    //convert midi 127 to orca 36
-                                    let orca_value =
-                                        ((midi_value as f32 / 128.) * 36.).floor() as i32;
-                                    let parameter_binding =
-                                        &mut parameter_clone.lock().unwrap()[*id];
-                                    parameter_binding.value = orca_value;
-                                    let raw_value = parameter_binding.get_raw_value();
-                                    parameter_sender.send((*id, raw_value)).unwrap();
-                                    gui_sender.send(UiEvent::Refresh).unwrap();
-                                }
-                                //if cc is not bounded, do nothing
-                                None => {}
-                            }
-                        }
-                        MidiMessage::NoteOff(note) => {
-                            midi_sender.send(MidiMessage::NoteOff(note)).unwrap()
-                        }
-                        MidiMessage::NoteOn(note) => {
-                            midi_sender.send(MidiMessage::NoteOn(note)).unwrap()
-                        }
-                        MidiMessage::None => {}
-                    }
-                }
-            },
-            (),
-        )?,
-        port_name,
-    );
-
-    Ok(_conn_in)
+///convert midi 127 to orca 36
+pub fn cc_value_to_orca(midi_value: u8) -> i32 {
+    ((midi_value as f32 / 128.) * 36.).floor() as i32
 }
@@ -0,0 +1,100 @@
+use crate::ui::option_menu;
+use crate::ui::UiEvent;
+use crossterm::{cursor, terminal};
+use laminaria::midi::{raw_midi_to_message, MidiMessage, TimedMidiMessage};
+use laminaria::queue::QueueSender;
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use std::error::Error;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Instant;
+
+///connection to a midi port, holding the ring sender to the audio thread
+pub type MidiConnection = MidiInputConnection<QueueSender<TimedMidiMessage>>;
+
+///The callback never locks nor waits: notes and CC are pushed to the audio thread ring,
+///which map the CC to the parameters itself. The ring sender is the connection data,
+///`MidiInputConnection::close` give it back to open another port.
+pub fn connect_midi(
+    midi_sender: QueueSender<TimedMidiMessage>,
+    gui_sender: Sender<UiEvent>,
+    program_sender: Sender<u8>,
+    channel_index: Arc<AtomicU8>,
+) -> Result<(MidiConnection, String), Box<dyn Error>> {
+    let mut selection = 0;
+    let mut midi_in = MidiInput::new("midir reading input")?;
+    midi_in.ignore(Ignore::None);
+    println!("{}", terminal::Clear(terminal::ClearType::All));
+    println!("{}", cursor::MoveTo(0, 0));
+    // Get an input port (read from console if multiple are available)
+    let in_ports = midi_in.ports();
+    let number_of_option = in_ports.len();
+    let in_port = match number_of_option {
+        0 => return Err("no input port found".into()),
+        1 => {
+            //choose the only connection avaible
+            &in_ports[0]
+        }
+        _ => {
+            let mut options: Vec<String> = vec![];
+            for (i, p) in in_ports.iter().enumerate() {
+                options.push(midi_in.port_name(p).unwrap());
+            }
+            selection = option_menu(options, "Select MIDI Port".to_string());
+            in_ports
+                .get(selection)
+                .ok_or("invalid input port selected")?
+        }
+    };
+
+    println!("\nOpening connection");
+
+    let port_name = midi_in.port_name(&in_ports[selection as usize]).unwrap();
+
+    // _conn_in needs to be a named parameter, because it needs to be kept alive until the end of the scope
+    let _conn_in = (
+        midi_in.connect(
+            in_port,
+            "midir-read-input",
+            move |_stamp, message, midi_sender| {
+                //midir stamps don't share an origin between backends (host time on CoreMIDI),
+                //the arrival time is close enough and always comparable to the audio clock
+                let time = Instant::now();
+                let (channel, midi_message): (u8, MidiMessage);
+                if message.len() < 3 {
+                    //program change only have two bytes
+                    let data = message.get(1).copied().unwrap_or(0);
+                    (channel, midi_message) = raw_midi_to_message(message[0], data, 0);
+                } else {
+                    (channel, midi_message) =
+                        raw_midi_to_message(message[0], message[1], message[2]);
+                }
+                // println!("{}: {:?}", _stamp, message);
+                if channel == channel_index.load(Ordering::Relaxed) {
+                    match midi_message {
+                        MidiMessage::None => {}
+                        //models are built outside of the audio thread
+                        MidiMessage::ProgramChange(program) => {
+                            program_sender.send(program).unwrap();
+                        }
+                        //a full ring drop the message, the overflow is shown in the UI
+                        _ => {
+                            let _ = midi_sender.send((midi_message, time));
+                        }
+                    }
+                    //the UI keep its own copy of the parameter values
+                    if let MidiMessage::ControlChange(cc, midi_value) = midi_message {
+                        gui_sender
+                            .send(UiEvent::ControlChange(cc, midi_value))
+                            .unwrap();
+                    }
+                }
+            },
+            midi_sender,
+        )?,
+        port_name,
+    );
+
+    Ok(_conn_in)
+}
@@ -1,5 +1,17 @@
-use std::{collections::VecDeque};
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    str::FromStr,
+};
 
+use crate::oscillator::Glide;
+
+///voices of the models that let you choose them at startup
+pub const DEFAULT_NUMBER_OF_VOICES: usize = 4;
+//in samples, a glide retunes the voices at this rate, some are costly to retune (the partials)
+const GLIDE_CONTROL_PERIOD: usize = 32;
+
+///The notes currently held, oldest first, to give each one a voice
 pub struct PolyMidiBuffer {
     pub notes: VecDeque<u8>,
     max_size: usize,
@@ -39,12 +51,365 @@ impl PolyMidiBuffer {
     }
 }
 
+///Which voice is stolen by a new note when they are all playing
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub enum VoiceMode {
+    ///the note played first
+    #[default]
+    Oldest,
+    ///the voice with the lowest envelope
+    Quietest,
+    ///the low notes have priority, the highest one is stolen by a lower note
+    Lowest,
+    ///the high notes have priority, the lowest one is stolen by a higher note
+    Highest,
+    ///the notes take the voices one after the other
+    RoundRobin,
+    ///a single voice playing the last held note
+    Mono,
+    ///a single voice playing the lowest held note
+    MonoLow,
+    ///a single voice playing the highest held note
+    MonoHigh,
+}
+
+impl VoiceMode {
+    pub fn is_mono(&self) -> bool {
+        matches!(self, VoiceMode::Mono | VoiceMode::MonoLow | VoiceMode::MonoHigh)
+    }
+}
+
+impl FromStr for VoiceMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "oldest" => Ok(VoiceMode::Oldest),
+            "quietest" => Ok(VoiceMode::Quietest),
+            "lowest" => Ok(VoiceMode::Lowest),
+            "highest" => Ok(VoiceMode::Highest),
+            "round-robin" | "roundrobin" => Ok(VoiceMode::RoundRobin),
+            "mono" | "mono-last" => Ok(VoiceMode::Mono),
+            "mono-low" => Ok(VoiceMode::MonoLow),
+            "mono-high" => Ok(VoiceMode::MonoHigh),
+            _ => Err(format!(
+                "unknown voice mode {}, try oldest, quietest, lowest, highest, round-robin, mono, mono-low or mono-high",
+                name
+            )),
+        }
+    }
+}
+
+///the voice given to a note, and the note it played before if any
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Allocation {
+    pub voice: usize,
+    pub previous: Option<u8>,
+    ///the voice was still holding another note, a legato voice goes on without a new attack
+    pub tied: bool,
+}
+
+///A note for each voice, kept until it is released or stolen, so the other voices don't move
+pub struct VoiceAllocator {
+    ///note held by each voice
+    pub notes: Vec<Option<u8>>,
+    //last note of each voice, still there while it releases
+    last_notes: Vec<Option<u8>>,
+    //note on order, to find the oldest
+    ages: Vec<u64>,
+    age: u64,
+    next_voice: usize,
+    mode: VoiceMode,
+    //keys down in mono, in the order they were pressed
+    held: Vec<u8>,
+}
+
+impl VoiceAllocator {
+    pub fn new(voices: usize, mode: VoiceMode) -> Self {
+        let voices = if mode.is_mono() { 1 } else { voices.max(1) };
+        VoiceAllocator {
+            notes: vec![None; voices],
+            last_notes: vec![None; voices],
+            ages: vec![0; voices],
+            age: 0,
+            next_voice: 0,
+            mode,
+            //every midi note, so it never grows on the audio thread
+            held: Vec::with_capacity(128),
+        }
+    }
+
+    pub fn is_mono(&self) -> bool {
+        self.mode.is_mono()
+    }
+
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    ///no key is held, the voices are released or silent
+    pub fn all_released(&self) -> bool {
+        self.notes.iter().all(Option::is_none)
+    }
+
+    pub fn voice_of(&self, midi_note: u8) -> Option<usize> {
+        self.notes.iter().position(|note| *note == Some(midi_note))
+    }
+
+    ///`levels` tells how much a voice still sounds, 0 once silent, ex: its envelope.
+    ///None if the note has less priority than all the held ones
+    pub fn note_on(&mut self, midi_note: u8, levels: impl Fn(usize) -> f32) -> Option<Allocation> {
+        if self.is_mono() {
+            return self.mono_note_on(midi_note);
+        }
+        self.age += 1;
+        let voice = match self.voice_of(midi_note) {
+            //played again, on the same voice
+            Some(voice) => voice,
+            None => match self.free_voice(&levels) {
+                Some(voice) => voice,
+                None => self.stolen_voice(midi_note, &levels)?,
+            },
+        };
+        let allocation = self.allocation(voice, midi_note);
+        self.ages[voice] = self.age;
+        self.next_voice = (voice + 1) % self.len();
+        Some(allocation)
+    }
+
+    ///the voice that was playing the note.
+    ///In mono it can go back to a key still held, check `notes` before releasing it
+    pub fn note_off(&mut self, midi_note: u8) -> Option<usize> {
+        if self.is_mono() {
+            let position = self.held.iter().position(|note| *note == midi_note)?;
+            self.held.remove(position);
+            if self.notes[0] != Some(midi_note) {
+                return None;
+            }
+            self.notes[0] = self.mono_note();
+            if self.notes[0].is_some() {
+                self.last_notes[0] = self.notes[0];
+            }
+            return Some(0);
+        }
+        let voice = self.voice_of(midi_note)?;
+        self.notes[voice] = None;
+        Some(voice)
+    }
+
+    pub fn kill_all(&mut self) {
+        self.notes.fill(None);
+        self.held.clear();
+    }
+
+    fn allocation(&mut self, voice: usize, midi_note: u8) -> Allocation {
+        let allocation = Allocation {
+            voice,
+            previous: self.last_notes[voice],
+            tied: self.notes[voice].is_some_and(|note| note != midi_note),
+        };
+        self.notes[voice] = Some(midi_note);
+        self.last_notes[voice] = Some(midi_note);
+        allocation
+    }
+
+    ///None if a held key has the priority
+    fn mono_note_on(&mut self, midi_note: u8) -> Option<Allocation> {
+        self.held.retain(|note| *note != midi_note);
+        self.held.push(midi_note);
+        if self.mono_note() != Some(midi_note) {
+            return None;
+        }
+        Some(self.allocation(0, midi_note))
+    }
+
+    //the held key the mono voice plays
+    fn mono_note(&self) -> Option<u8> {
+        match self.mode {
+            VoiceMode::MonoLow => self.held.iter().min().copied(),
+            VoiceMode::MonoHigh => self.held.iter().max().copied(),
+            _ => self.held.last().copied(),
+        }
+    }
+
+    ///a silent voice first, so the released ones ring until their end, else the quietest released one
+    fn free_voice(&self, levels: &impl Fn(usize) -> f32) -> Option<usize> {
+        let start = match self.mode {
+            VoiceMode::RoundRobin => self.next_voice,
+            _ => 0,
+        };
+        (0..self.len())
+            .map(|i| (start + i) % self.len())
+            .filter(|voice| self.notes[*voice].is_none())
+            .min_by(|a, b| levels(*a).max(0.).total_cmp(&levels(*b).max(0.)))
+    }
+
+    fn stolen_voice(&self, midi_note: u8, levels: &impl Fn(usize) -> f32) -> Option<usize> {
+        let held = (0..self.len()).filter_map(|voice| self.notes[voice].map(|note| (voice, note)));
+        match self.mode {
+            VoiceMode::Oldest => (0..self.len()).min_by_key(|voice| self.ages[*voice]),
+            VoiceMode::Quietest => (0..self.len()).min_by(|a, b| levels(*a).total_cmp(&levels(*b))),
+            VoiceMode::Lowest => held
+                .max_by_key(|(_, note)| *note)
+                .filter(|(_, note)| *note > midi_note)
+                .map(|(voice, _)| voice),
+            VoiceMode::Highest => held
+                .min_by_key(|(_, note)| *note)
+                .filter(|(_, note)| *note < midi_note)
+                .map(|(voice, _)| voice),
+            VoiceMode::RoundRobin => Some(self.next_voice),
+            //a single voice, mono notes don't get here
+            VoiceMode::Mono | VoiceMode::MonoLow | VoiceMode::MonoHigh => Some(0),
+        }
+    }
+}
+
+
+///What a model does with one of its voices, the `VoiceManager` decides when
+pub trait Voice {
+    ///what the voices of a model share to play, `()` if nothing
+    type Context;
+    ///`retrigger` is false for a legato note, only the pitch moves
+    fn start(&mut self, midi_note: u8, retrigger: bool, context: &mut Self::Context);
+    fn release(&mut self, context: &Self::Context);
+    ///quick fade out, the voice plays its next note once silent
+    fn steal(&mut self, context: &Self::Context);
+    ///how much it still sounds, to pick the voice a note takes
+    fn level(&self, context: &Self::Context) -> f32;
+    fn is_silent(&self, context: &Self::Context) -> bool;
+    ///the pitch while it glides, the voices tuned by their note ignore it
+    fn set_frequency(&mut self, _frequency_hz: f32) {}
+}
+
+///The voices of a model and who plays what : a stolen voice fades out before its next note,
+///mono voices go back to the keys still held, and the pitch glides between the notes.
+///Derefs to the voices.
+pub struct VoiceManager<V> {
+    voices: Vec<V>,
+    allocator: VoiceAllocator,
+    //note waiting for its stolen voice to fade out
+    pending_notes: Vec<Option<u8>>,
+    glides: Vec<Glide>,
+    legato: bool,
+}
+
+impl<V> Deref for VoiceManager<V> {
+    type Target = [V];
+
+    fn deref(&self) -> &[V] {
+        &self.voices
+    }
+}
+
+impl<V> DerefMut for VoiceManager<V> {
+    fn deref_mut(&mut self) -> &mut [V] {
+        &mut self.voices
+    }
+}
+
+impl<V: Voice> VoiceManager<V> {
+    ///a mono `mode` only keeps the first voice
+    pub fn new(mut voices: Vec<V>, mode: VoiceMode) -> Self {
+        let allocator = VoiceAllocator::new(voices.len(), mode);
+        voices.truncate(allocator.len());
+        VoiceManager {
+            pending_notes: vec![None; voices.len()],
+            glides: vec![Glide::new(); voices.len()],
+            voices,
+            allocator,
+            legato: false,
+        }
+    }
+
+    pub fn init(&mut self, sample_rate: f32) {
+        self.glides.iter_mut().for_each(|x| x.init(sample_rate));
+    }
+
+    ///in ms, 0 jumps to the notes
+    pub fn set_glide_time(&mut self, time: f32) {
+        self.glides.iter_mut().for_each(|glide| glide.set_time(time));
+    }
+
+    ///mono only, a note played over a held one doesn't start again
+    pub fn set_legato(&mut self, legato: bool) {
+        self.legato = legato;
+    }
+
+    pub fn allocator(&self) -> &VoiceAllocator {
+        &self.allocator
+    }
+
+    pub fn pending_note(&self, voice: usize) -> Option<u8> {
+        self.pending_notes[voice]
+    }
+
+    pub fn note_on(&mut self, midi_note: u8, context: &mut V::Context) {
+        let voices = &self.voices;
+        let Some(allocation) = self.allocator.note_on(midi_note, |i| voices[i].level(context)) else {
+            return;
+        };
+        let voice = allocation.voice;
+        if self.allocator.is_mono() {
+            //the single voice slides to the note, legato doesn't attack again over a held one
+            self.start(voice, midi_note, !(self.legato && allocation.tied), context);
+        } else if allocation.previous != Some(midi_note) && !self.voices[voice].is_silent(context) {
+            //a voice still sounding another note fades out first, instead of jumping pitch
+            self.voices[voice].steal(context);
+            self.pending_notes[voice] = Some(midi_note);
+        } else {
+            self.start(voice, midi_note, true, context);
+        }
+    }
+
+    pub fn note_off(&mut self, midi_note: u8, context: &mut V::Context) {
+        let Some(voice) = self.allocator.note_off(midi_note) else {
+            return;
+        };
+        match self.allocator.notes[voice] {
+            //mono, back to the key still held
+            Some(held_note) => self.start(voice, held_note, !self.legato, context),
+            None => {
+                self.voices[voice].release(context);
+                //released before its stolen voice was free
+                self.pending_notes[voice] = None;
+            }
+        }
+    }
+
+    ///before each sample of a voice : its pending note starts once it is silent, and its pitch glides
+    pub fn update(&mut self, voice: usize, context: &mut V::Context) {
+        if let Some(midi_note) = self.pending_notes[voice] {
+            if self.voices[voice].is_silent(context) {
+                self.start(voice, midi_note, true, context);
+            }
+        }
+        let glide = &mut self.glides[voice];
+        if glide.is_gliding() {
+            let frequency = glide.process();
+            if glide.remaining().is_multiple_of(GLIDE_CONTROL_PERIOD) {
+                self.voices[voice].set_frequency(frequency);
+            }
+        }
+    }
+
+    fn start(&mut self, voice: usize, midi_note: u8, retrigger: bool, context: &mut V::Context) {
+        self.pending_notes[voice] = None;
+        self.glides[voice].set_note(midi_note);
+        self.voices[voice].start(midi_note, retrigger, context);
+        self.voices[voice].set_frequency(self.glides[voice].frequency());
+    }
+}
+
 
 
 
 #[cfg(test)]
 mod test {
-    use super::PolyMidiBuffer;
+    use super::{PolyMidiBuffer, VoiceAllocator, VoiceMode};
 
     #[test]
     fn basics() {
@@ -99,5 +464,79 @@ mod test {
 
     }
 
+    #[test]
+    fn voice_modes() {
+        let fill = |mode| {
+            let mut voices = VoiceAllocator::new(3, mode);
+            for note in [60, 48, 72] {
+                voices.note_on(note, |_| 1.);
+            }
+            voices
+        };
+        //the voices keep their notes when another one is released
+        let mut voices = fill(VoiceMode::Oldest);
+        assert_eq!(voices.note_off(48), Some(1));
+        assert_eq!(voices.notes, vec![Some(60), None, Some(72)]);
+        assert_eq!(voices.note_on(50, |_| 1.).unwrap().voice, 1);
+        assert_eq!(voices.note_on(55, |_| 1.).unwrap().voice, 0);
+
+        //the release of the first note overlaps the next one
+        voices.note_off(50);
+        voices.note_off(72);
+        assert_eq!(voices.note_on(62, |voice| [1., 0.5, 0.][voice]).unwrap().voice, 2);
+
+        let mut voices = fill(VoiceMode::Quietest);
+        assert_eq!(voices.note_on(55, |voice| [1., 1., 0.5][voice]).unwrap().voice, 2);
 
-}
\ No newline at end of file
+        //a higher note doesn't steal the low ones
+        let mut voices = fill(VoiceMode::Lowest);
+        assert_eq!(voices.note_on(84, |_| 1.), None);
+        assert_eq!(voices.note_on(55, |_| 1.).unwrap().voice, 2);
+
+        let mut voices = fill(VoiceMode::Highest);
+        assert_eq!(voices.note_on(36, |_| 1.), None);
+        assert_eq!(voices.note_on(55, |_| 1.).unwrap().voice, 1);
+
+        //a released voice waits for its turn
+        let mut voices = fill(VoiceMode::RoundRobin);
+        voices.note_off(60);
+        let allocation = voices.note_on(55, |_| 1.).unwrap();
+        assert_eq!((allocation.voice, allocation.previous), (0, Some(60)));
+        voices.note_off(48);
+        voices.note_off(72);
+        assert_eq!(voices.note_on(50, |_| 1.).unwrap().voice, 1);
+        assert_eq!(voices.note_on(52, |_| 1.).unwrap().voice, 2);
+    }
+
+    #[test]
+    fn mono_priorities() {
+        //the last key wins, and the voice goes back to the previous one on release
+        let mut voice = VoiceAllocator::new(4, VoiceMode::Mono);
+        assert_eq!(voice.len(), 1);
+        let allocation = voice.note_on(60, |_| 1.).unwrap();
+        assert!(!allocation.tied);
+        let allocation = voice.note_on(64, |_| 1.).unwrap();
+        assert_eq!((allocation.previous, allocation.tied), (Some(60), true));
+        assert_eq!(voice.note_off(64), Some(0));
+        assert_eq!(voice.notes[0], Some(60));
+        assert_eq!(voice.note_off(60), Some(0));
+        assert_eq!(voice.notes[0], None);
+
+        //a higher key waits under the low one
+        let mut voice = VoiceAllocator::new(1, VoiceMode::MonoLow);
+        voice.note_on(60, |_| 1.);
+        assert_eq!(voice.note_on(67, |_| 1.), None);
+        assert!(voice.note_on(55, |_| 1.).unwrap().tied);
+        //releasing a key that doesn't play changes nothing
+        assert_eq!(voice.note_off(67), None);
+        voice.note_off(55);
+        assert_eq!(voice.notes[0], Some(60));
+
+        let mut voice = VoiceAllocator::new(1, VoiceMode::MonoHigh);
+        voice.note_on(60, |_| 1.);
+        assert_eq!(voice.note_on(55, |_| 1.), None);
+        voice.note_on(67, |_| 1.);
+        voice.note_off(67);
+        assert_eq!(voice.notes[0], Some(60));
+    }
+}
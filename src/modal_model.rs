@@ -0,0 +1,485 @@
+use crate::envelope::StealFade;
+use crate::filter::FilterType;
+use crate::midi::MidiMessage;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode};
+use crate::oscillator::{BlepWaveform, PolyBlepOscillator};
+use crate::outils::midi_to_frequence;
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::reverb::Reverb;
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::Biquad;
+use crate::ParameterUpdate;
+use num_derive::FromPrimitive;
+
+const NUMBER_OF_VOICES: usize = 4;
+pub const NUMBER_OF_MODES: usize = 8;
+
+const NB_SYNTH_PARAM: usize = 11;
+//in seconds, length of the noise burst exciter
+const BURST_TIME: f32 = 0.005;
+//modes above this part of nyquist are muted
+const HIGHEST_MODE: f32 = 0.45;
+
+///Partials of a struck material, as frequency ratios to the fundamental and amplitudes
+pub struct ModalPreset {
+    pub name: &'static str,
+    pub ratios: [f32; NUMBER_OF_MODES],
+    pub amplitudes: [f32; NUMBER_OF_MODES],
+}
+
+pub const PRESETS: [ModalPreset; 4] = [
+    //a tuned bar, the overtones are two octaves and a third above
+    ModalPreset {
+        name: "marimba",
+        ratios: [1., 3.99, 10.65, 20.26, 32.8, 48.2, 66.4, 87.6],
+        amplitudes: [1., 0.5, 0.25, 0.12, 0.06, 0.03, 0.02, 0.01],
+    },
+    //hum, prime, tierce, quint and nominal of a church bell
+    ModalPreset {
+        name: "bell",
+        ratios: [0.5, 1., 1.183, 1.506, 2., 2.514, 2.662, 3.011],
+        amplitudes: [0.6, 1., 0.8, 0.4, 0.7, 0.3, 0.25, 0.2],
+    },
+    ModalPreset {
+        name: "glass",
+        ratios: [1., 2.32, 4.25, 6.63, 9.38, 12.4, 15.9, 19.7],
+        amplitudes: [1., 0.7, 0.5, 0.35, 0.25, 0.18, 0.12, 0.08],
+    },
+    //closely spaced modes of a thin rectangular plate
+    ModalPreset {
+        name: "plate",
+        ratios: [1., 1.59, 2.14, 2.3, 2.65, 2.92, 3.16, 3.5],
+        amplitudes: [1., 0.9, 0.8, 0.8, 0.7, 0.7, 0.6, 0.6],
+    },
+];
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum ModalModelParamID {
+    Preset,
+    Exciter,
+    Noise,
+    Brightness,
+    Decay,
+    Damping,
+    Inharmonicity,
+    StrikePosition,
+    ReverbDryWet,
+    ReverbTime,
+    Volume,
+}
+
+///what strikes the resonators
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)]
+pub enum Exciter {
+    Impulse,
+    ///a few milliseconds of noise, softer
+    Burst,
+}
+
+#[derive(Clone, Copy)]
+struct ModalSettings {
+    preset: usize,
+    exciter: Exciter,
+    noise: f32,
+    brightness: f32,
+    //in seconds
+    decay: f32,
+    damping: f32,
+    inharmonicity: f32,
+    strike_position: f32,
+}
+
+///a bank of band-pass filters, one per mode
+struct ModalVoice {
+    modes: [Biquad; NUMBER_OF_MODES],
+    gains: [f32; NUMBER_OF_MODES],
+    noise: PolyBlepOscillator,
+    sample_rate: f32,
+    note: Option<u8>,
+    frequency_hz: f32,
+    excitation_left: usize,
+    excitation: f32,
+    //samples until the longest mode is under -60dB
+    remaining: usize,
+    fade: StealFade,
+}
+
+impl ModalVoice {
+    fn new(index: usize) -> Self {
+        let mut noise = PolyBlepOscillator::new();
+        noise.waveform = BlepWaveform::Noise;
+        noise.set_seed(index as u32);
+        ModalVoice {
+            modes: std::array::from_fn(|_| {
+                let mut mode = Biquad::new(FilterType::BPF);
+                mode.set_resonance_feedback(false);
+                mode
+            }),
+            gains: [0.; NUMBER_OF_MODES],
+            noise,
+            sample_rate: 0.,
+            note: None,
+            frequency_hz: 440.,
+            excitation_left: 0,
+            excitation: 0.,
+            remaining: 0,
+            fade: StealFade::new(),
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.modes.iter_mut().for_each(|x| x.init(sample_rate));
+        self.noise.init(sample_rate);
+        self.fade.init(sample_rate);
+    }
+
+    ///frequency, resonance and gain of every mode
+    fn tune(&mut self, settings: &ModalSettings) {
+        let preset = &PRESETS[settings.preset];
+        for (k, mode) in self.modes.iter_mut().enumerate() {
+            let ratio = preset.ratios[k];
+            //stiffness pushes the upper modes up, like the strings of a piano
+            let ratio = ratio * (1. + settings.inharmonicity * 0.01 * ratio * ratio).sqrt();
+            let frequency = self.frequency_hz * ratio;
+            if frequency > self.sample_rate * HIGHEST_MODE {
+                self.gains[k] = 0.;
+                continue;
+            }
+            //the higher modes die first when damped
+            let decay = settings.decay / (1. + settings.damping * 4. * (ratio - 1.).max(0.));
+            //Q of a band-pass ringing for `decay` seconds until -60dB
+            let resonance = (std::f32::consts::PI * frequency * decay / 6.91).max(0.7);
+            mode.set_frequence_and_resonance(frequency, resonance);
+            //struck at a node a mode is silent
+            let position = (std::f32::consts::PI * (k + 1) as f32 * settings.strike_position).sin();
+            //the band-pass gain grows with the frequency, compensate it
+            let omega = std::f32::consts::TAU * frequency / self.sample_rate;
+            self.gains[k] = preset.amplitudes[k] * position.abs() / omega.sin();
+        }
+    }
+
+    fn strike(&mut self, midi_note: u8, settings: &ModalSettings) {
+        self.note = Some(midi_note);
+        self.frequency_hz = midi_to_frequence(midi_note);
+        self.tune(settings);
+        self.excitation_left = match settings.exciter {
+            Exciter::Impulse => 1,
+            Exciter::Burst => (BURST_TIME * self.sample_rate) as usize,
+        };
+        self.remaining = (settings.decay * self.sample_rate) as usize;
+        //the modes still ring with the stolen note, the new one starts from silence
+        if self.fade.is_stolen() {
+            self.modes.iter_mut().for_each(|mode| mode.reset());
+            self.excitation = 0.;
+        }
+        self.fade.reset();
+    }
+
+    fn process(&mut self, settings: &ModalSettings) -> f32 {
+        let mut input = 0.;
+        if self.excitation_left > 0 {
+            input = match settings.exciter {
+                Exciter::Impulse => 1.,
+                Exciter::Burst => self.noise.process() * 0.3,
+            };
+            self.excitation_left -= 1;
+        }
+        //held notes keep blowing noise in the resonators
+        if self.note.is_some() && settings.noise > 0. {
+            input += self.noise.process() * settings.noise * 0.02;
+            self.remaining = self
+                .remaining
+                .max((settings.decay * self.sample_rate) as usize);
+        }
+        //a darker strike excites less the upper modes
+        let smoothing = (settings.brightness * settings.brightness).max(0.01);
+        self.excitation += (input - self.excitation) * smoothing;
+
+        self.remaining = self.remaining.saturating_sub(1);
+        let excitation = self.excitation;
+        self.modes
+            .iter_mut()
+            .zip(self.gains.iter())
+            .filter(|(_, gain)| **gain > 0.)
+            .map(|(mode, gain)| mode.process(excitation) * gain)
+            .sum::<f32>()
+            * self.fade.process()
+    }
+}
+
+impl Voice for ModalVoice {
+    type Context = ModalSettings;
+
+    fn start(&mut self, midi_note: u8, _retrigger: bool, settings: &mut ModalSettings) {
+        if self.note != Some(midi_note) {
+            self.strike(midi_note, settings)
+        }
+    }
+
+    //released, it rings until its end
+    fn release(&mut self, _: &ModalSettings) {
+        self.note = None
+    }
+
+    fn steal(&mut self, _: &ModalSettings) {
+        self.note = None;
+        self.excitation_left = 0;
+        self.remaining = self.remaining.min(self.fade.start());
+    }
+
+    fn level(&self, _: &ModalSettings) -> f32 {
+        self.remaining as f32
+    }
+
+    fn is_silent(&self, _: &ModalSettings) -> bool {
+        self.remaining == 0
+    }
+}
+
+pub struct ModalModel {
+    voices: VoiceManager<ModalVoice>,
+    reverb: Reverb,
+    settings: ModalSettings,
+    volume: f32,
+}
+
+impl ModalModel {
+    fn retune(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.tune(&self.settings);
+        }
+    }
+}
+
+impl HasDescription for ModalModel {
+    const NAME: &'static str = "Modal";
+    const DESCRIPTION: &'static str =
+        "struck resonator banks, marimba, bell, glass and plate, with reverb";
+}
+
+impl HasConstructor for ModalModel {
+    fn new() -> Self {
+        ModalModel {
+            voices: VoiceManager::new((0..NUMBER_OF_VOICES).map(ModalVoice::new).collect(), VoiceMode::Oldest),
+            reverb: Reverb::new(),
+            settings: ModalSettings {
+                preset: 0,
+                exciter: Exciter::Impulse,
+                noise: 0.,
+                brightness: 0.7,
+                decay: 2.,
+                damping: 0.3,
+                inharmonicity: 0.,
+                strike_position: 0.2,
+            },
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.voices.init(sample_rate);
+        self.voices.iter_mut().for_each(|x| x.init(sample_rate));
+        self.reverb.init(sample_rate);
+    }
+}
+
+impl HasMidiInput for ModalModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut self.settings),
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut self.settings),
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for ModalModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.fill(0.);
+        for i in 0..self.voices.len() {
+            for sample in left.iter_mut() {
+                self.voices.update(i, &mut self.settings);
+                let voice = &mut self.voices[i];
+                if voice.remaining == 0 {
+                    break;
+                }
+                *sample += voice.process(&self.settings);
+            }
+        }
+        for sample in left.iter_mut() {
+            //vca
+            *sample = self.reverb.process(*sample) * self.volume / NUMBER_OF_VOICES as f32;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for ModalModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = ModalModelParamID;
+        type P = ParameterCapsule;
+
+        let params = Parameters {
+            capsules: vec![
+                //resonators
+                P::new(
+                    ID::Preset as i32,
+                    "preset",
+                    0,
+                    'm',
+                    0.,
+                    (PRESETS.len() - 1) as f32,
+                    1.,
+                ),
+                P::new(ID::Decay as i32, "decay", 10, 't', 0.05, 20., 2.),
+                P::new(ID::Damping as i32, "damping", 10, 'd', 0., 1., 1.),
+                P::new(ID::Inharmonicity as i32, "inharm", 0, 'i', 0., 1., 1.),
+                //exciter
+                P::new(ID::Exciter as i32, "exciter", 0, 'x', 0., 1., 1.),
+                P::new(
+                    ID::StrikePosition as i32,
+                    "strike-pos",
+                    14,
+                    's',
+                    0.,
+                    0.5,
+                    1.,
+                ),
+                P::new(ID::Brightness as i32, "bright", 25, 'b', 0., 1., 1.),
+                P::new(ID::Noise as i32, "noise", 0, 'n', 0., 1., 2.),
+                //reverb
+                P::new(ID::ReverbDryWet as i32, "rvb-wet", 10, 'r', 0., 1., 1.),
+                P::new(ID::ReverbTime as i32, "rvb-time", 20, '9', 0., 0.99, 1.),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = ModalModelParamID;
+        let typed_id: ModalModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Preset => {
+                self.settings.preset = (new_value.round() as usize).min(PRESETS.len() - 1);
+                self.retune();
+            }
+            ID::Decay => {
+                self.settings.decay = new_value;
+                self.retune();
+            }
+            ID::Damping => {
+                self.settings.damping = new_value;
+                self.retune();
+            }
+            ID::Inharmonicity => {
+                self.settings.inharmonicity = new_value;
+                self.retune();
+            }
+            ID::StrikePosition => {
+                self.settings.strike_position = new_value;
+                self.retune();
+            }
+            ID::Exciter => {
+                self.settings.exciter =
+                    num::FromPrimitive::from_i32(new_value.round() as i32).unwrap_or(Exciter::Burst)
+            }
+            ID::Brightness => self.settings.brightness = new_value,
+            ID::Noise => self.settings.noise = new_value,
+            //reverb
+            ID::ReverbDryWet => self.reverb.dry_wet = new_value,
+            ID::ReverbTime => self.reverb.set_reverb_time(new_value),
+        }
+    }
+}
+
+impl Synth for ModalModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{model, peak, render};
+
+    fn modal_model() -> ModalModel {
+        let mut model: ModalModel = model();
+        model.set_parameter((ModalModelParamID::ReverbDryWet as i32, 0.));
+        model
+    }
+
+    #[test]
+    fn presets_ring_and_decay() {
+        for (index, preset) in PRESETS.iter().enumerate() {
+            let mut model = modal_model();
+            model.set_parameter((ModalModelParamID::Preset as i32, index as f32));
+            model.set_note(MidiMessage::NoteOn(60));
+            model.set_note(MidiMessage::NoteOff(60));
+            let attack = peak(&render(&mut model, 4800));
+            assert!(
+                attack > 0.01 && attack < 1.,
+                "{} peaks at {}",
+                preset.name,
+                attack
+            );
+            //2 seconds of decay, -60dB
+            render(&mut model, 48000);
+            assert!(peak(&render(&mut model, 4800)) < attack * 0.01);
+        }
+    }
+
+    #[test]
+    fn modes_follow_the_parameters() {
+        let mut model = modal_model();
+        model.set_note(MidiMessage::NoteOn(100));
+        //the highest marimba modes are above nyquist
+        assert!(model.voices[0].gains[7] == 0.);
+        assert!(model.voices[0].gains[0] > 0.);
+
+        //struck in its middle, the second mode is silent
+        model.set_parameter((ModalModelParamID::StrikePosition as i32, 0.5));
+        assert!(model.voices[0].gains[1] < 0.001);
+
+        //held notes blown with noise keep ringing
+        model.set_parameter((ModalModelParamID::Noise as i32, 1.));
+        render(&mut model, 48000 * 3);
+        assert!(peak(&render(&mut model, 4800)) > 0.001);
+        model.set_note(MidiMessage::NoteOff(100));
+        render(&mut model, 48000 * 3);
+        assert_eq!(model.voices[0].remaining, 0);
+    }
+
+    #[test]
+    fn stolen_modes_restart_from_silence() {
+        let model = modal_model();
+        let settings = model.settings;
+        let mut stolen = ModalVoice::new(0);
+        let mut fresh = ModalVoice::new(0);
+        stolen.init(48000.);
+        fresh.init(48000.);
+        stolen.strike(60, &settings);
+        for _ in 0..4800 {
+            stolen.process(&settings);
+        }
+        stolen.steal(&settings);
+        while stolen.remaining > 0 {
+            stolen.process(&settings);
+        }
+        //struck again, it rings like a voice that never played
+        stolen.strike(72, &settings);
+        fresh.strike(72, &settings);
+        for _ in 0..480 {
+            assert_eq!(stolen.process(&settings), fresh.process(&settings));
+        }
+    }
+}
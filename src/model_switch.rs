@@ -0,0 +1,87 @@
+use crate::engine::EngineMessage;
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::queue::{QueueReceiver, QueueSender};
+use crate::registry::ModelRegistry;
+use crate::synth::Synth;
+use std::sync::{Arc, Mutex};
+
+///A model ready to play, and its parameters so the audio thread can map the CC
+pub struct ModelSwap {
+    pub synth_model: Box<dyn Synth>,
+    pub capsules: Vec<ParameterCapsule>,
+}
+
+///Build, init and send new models to the audio thread, and drop the ones it gives back.
+///All the allocation happen here, never in the audio thread.
+pub struct ModelSwitcher {
+    registry: ModelRegistry,
+    sample_rate: f32,
+    current: usize,
+    retired_receiver: QueueReceiver<Box<ModelSwap>>,
+}
+
+impl ModelSwitcher {
+    ///`retired_receiver` get the old models once the audio thread faded them out
+    pub fn new(
+        registry: ModelRegistry,
+        sample_rate: f32,
+        current: usize,
+        retired_receiver: QueueReceiver<Box<ModelSwap>>,
+    ) -> Self {
+        ModelSwitcher {
+            registry,
+            sample_rate,
+            current,
+            retired_receiver,
+        }
+    }
+
+    pub fn current_name(&self) -> String {
+        self.registry.name(self.current).unwrap_or_default().to_string()
+    }
+
+    ///index wrap around the model list
+    pub fn next(&self) -> usize {
+        (self.current + 1) % self.registry.len().max(1)
+    }
+
+    pub fn previous(&self) -> usize {
+        let len = self.registry.len().max(1);
+        (self.current + len - 1) % len
+    }
+
+    ///Replace the UI parameters by the ones of the new model and send it to the audio thread.
+    ///Return false if there is no model at this index or the ring is full.
+    pub fn switch(
+        &mut self,
+        index: usize,
+        engine_sender: &mut QueueSender<EngineMessage>,
+        parameters: &Arc<Mutex<Parameters>>,
+    ) -> bool {
+        self.drop_retired();
+        let Some(mut synth_model) = self.registry.build(index) else {
+            return false;
+        };
+        synth_model.init(self.sample_rate);
+        let new_parameters = synth_model.get_parameters();
+        //default values are set here, so the model start with the right sound
+        for capsule in new_parameters.capsules.iter() {
+            synth_model.set_parameter((capsule.id, capsule.parameter.get_raw_value()));
+        }
+        let swap = Box::new(ModelSwap {
+            synth_model,
+            capsules: new_parameters.capsules.clone(),
+        });
+        if engine_sender.send(EngineMessage::SwapModel(swap)).is_err() {
+            return false;
+        }
+        *parameters.lock().unwrap() = new_parameters;
+        self.current = index;
+        true
+    }
+
+    ///free the models the audio thread is done with
+    pub fn drop_retired(&mut self) {
+        while self.retired_receiver.try_recv().is_some() {}
+    }
+}
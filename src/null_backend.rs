@@ -0,0 +1,254 @@
+use crate::engine::{AudioEngine, EngineMessage};
+use crate::midi::TimedMidiMessage;
+use crate::model_switch::ModelSwap;
+use crate::queue::{self, QueueReceiver, QueueSender};
+use crate::synth::Synth;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+///Fake audio output: pull buffers from an `AudioEngine` like a device callback would,
+///with a fake clock advancing one buffer at each pull, so no sound card is needed.
+pub struct NullBackend {
+    engine: AudioEngine,
+    output: Vec<f32>,
+    num_channels: usize,
+    clock: Instant,
+    buffer_duration: Duration,
+}
+
+impl NullBackend {
+    ///`sample_rate` is the one the engine was built with
+    pub fn new(
+        engine: AudioEngine,
+        sample_rate: f32,
+        buffer_size: usize,
+        num_channels: usize,
+    ) -> Self {
+        let buffer_duration = Duration::from_secs_f64(buffer_size as f64 / sample_rate as f64);
+        NullBackend {
+            engine,
+            output: vec![0.; buffer_size * num_channels],
+            num_channels,
+            clock: Instant::now(),
+            buffer_duration,
+        }
+    }
+
+    ///time of the fake clock, to timestamp the midi messages
+    pub fn now(&self) -> Instant {
+        self.clock
+    }
+
+    pub fn buffer_duration(&self) -> Duration {
+        self.buffer_duration
+    }
+
+    ///advance the clock by one buffer and fill it, interleaved
+    pub fn pull(&mut self) -> &[f32] {
+        self.clock += self.buffer_duration;
+        self.engine
+            .process(&mut self.output, self.num_channels, self.clock);
+        &self.output
+    }
+}
+
+///The same plumbing as `main`, with a null output instead of the cpal stream
+pub struct NullPipeline {
+    pub parameter_sender: QueueSender<EngineMessage>,
+    pub midi_sender: QueueSender<TimedMidiMessage>,
+    pub retired_receiver: QueueReceiver<Box<ModelSwap>>,
+    pub overflows: Arc<AtomicUsize>,
+    pub backend: NullBackend,
+}
+
+impl NullPipeline {
+    ///queue the default parameter values, then build the engine
+    pub fn new(
+        synth_model: Box<dyn Synth>,
+        sample_rate: f32,
+        buffer_size: usize,
+        num_channels: usize,
+    ) -> Self {
+        let overflows = Arc::new(AtomicUsize::new(0));
+        let (mut parameter_sender, parameter_receiver) =
+            queue::bounded::<EngineMessage>(queue::QUEUE_CAPACITY, overflows.clone());
+        let (midi_sender, midi_receiver) =
+            queue::bounded::<TimedMidiMessage>(queue::QUEUE_CAPACITY, overflows.clone());
+        let (retired_sender, retired_receiver) =
+            queue::bounded::<Box<ModelSwap>>(queue::QUEUE_CAPACITY, overflows.clone());
+
+        let defaults = synth_model.get_parameters().capsules;
+        for caps in defaults.iter() {
+            let _ = parameter_sender.send(EngineMessage::Parameter((
+                caps.id,
+                caps.parameter.get_raw_value(),
+            )));
+        }
+        let engine = AudioEngine::new(
+            synth_model,
+            sample_rate,
+            parameter_receiver,
+            midi_receiver,
+            retired_sender,
+            defaults,
+        );
+        NullPipeline {
+            parameter_sender,
+            midi_sender,
+            retired_receiver,
+            overflows,
+            backend: NullBackend::new(engine, sample_rate, buffer_size, num_channels),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::midi::MidiMessage;
+    use crate::sine_model::SineModelParamID;
+    use crate::synth::HasConstructor;
+    use crate::SineModel;
+
+    const BUFFER_SIZE: usize = 100;
+
+    fn pipeline() -> NullPipeline {
+        let mut pipeline = NullPipeline::new(Box::new(SineModel::new()), 1000., BUFFER_SIZE, 2);
+        //fastest attack
+        assert!(pipeline
+            .parameter_sender
+            .send(EngineMessage::Parameter((
+                SineModelParamID::EnvelopeAttack as i32,
+                0.,
+            )))
+            .is_ok());
+        pipeline
+    }
+
+    #[test]
+    fn silence_without_note() {
+        let mut pipeline = pipeline();
+        for _ in 0..4 {
+            assert!(pipeline.backend.pull().iter().all(|sample| *sample == 0.));
+        }
+    }
+
+    #[test]
+    fn note_lands_at_its_offset() {
+        let mut pipeline = pipeline();
+        pipeline.backend.pull();
+        //half a buffer after the last pull, so in the middle of the next one
+        let time = pipeline.backend.now() + pipeline.backend.buffer_duration() / 2;
+        pipeline
+            .midi_sender
+            .send((MidiMessage::NoteOn(69), time))
+            .unwrap();
+        let output = pipeline.backend.pull();
+        let (first_half, second_half) = output.split_at(BUFFER_SIZE);
+        assert!(first_half.iter().all(|sample| *sample == 0.));
+        assert!(second_half.iter().any(|sample| sample.abs() > 0.01));
+        //stereo device get the same sample on both channels for a mono model
+        assert!(output.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn control_change_sets_parameter() {
+        let mut pipeline = pipeline();
+        let now = pipeline.backend.now();
+        pipeline
+            .midi_sender
+            .send((MidiMessage::NoteOn(69), now))
+            .unwrap();
+        assert!(pipeline
+            .backend
+            .pull()
+            .iter()
+            .any(|sample| sample.abs() > 0.01));
+
+        //volume is on cc `v`, 31
+        let now = pipeline.backend.now();
+        pipeline
+            .midi_sender
+            .send((MidiMessage::ControlChange(31, 0), now))
+            .unwrap();
+        assert!(pipeline.backend.pull().iter().all(|sample| *sample == 0.));
+        assert_eq!(
+            pipeline
+                .overflows
+                .load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[test]
+    fn swap_model_fades_out_and_retires() {
+        let mut pipeline = pipeline();
+        let now = pipeline.backend.now();
+        pipeline
+            .midi_sender
+            .send((MidiMessage::NoteOn(69), now))
+            .unwrap();
+        pipeline.backend.pull();
+
+        //the new model has no note playing, so only the fading one is heard
+        let mut synth_model: Box<dyn Synth> = Box::new(SineModel::new());
+        synth_model.init(1000.);
+        let capsules = synth_model.get_parameters().capsules;
+        let swap = Box::new(ModelSwap {
+            synth_model,
+            capsules,
+        });
+        assert!(pipeline
+            .parameter_sender
+            .send(EngineMessage::SwapModel(swap))
+            .is_ok());
+        let output = pipeline.backend.pull();
+        assert!(output[..10].iter().any(|sample| sample.abs() > 0.01));
+        //20ms at 1000Hz, so the fade is over after 20 frames
+        assert!(output[40..].iter().all(|sample| *sample == 0.));
+        assert!(pipeline.retired_receiver.try_recv().is_some());
+    }
+
+    #[test]
+    fn swaps_wait_for_the_fade() {
+        //blocks shorter than the 20ms fade, so the next swap comes in the middle of it
+        let mut pipeline = NullPipeline::new(Box::new(SineModel::new()), 48000., 64, 1);
+        let now = pipeline.backend.now();
+        pipeline
+            .midi_sender
+            .send((MidiMessage::NoteOn(69), now))
+            .unwrap();
+        let mut output = vec![];
+        for _ in 0..10 {
+            output.extend_from_slice(pipeline.backend.pull());
+        }
+
+        let swap = || {
+            let mut synth_model: Box<dyn Synth> = Box::new(SineModel::new());
+            synth_model.init(48000.);
+            let capsules = synth_model.get_parameters().capsules;
+            Box::new(ModelSwap {
+                synth_model,
+                capsules,
+            })
+        };
+        assert!(pipeline.parameter_sender.send(EngineMessage::SwapModel(swap())).is_ok());
+        output.extend_from_slice(pipeline.backend.pull());
+        //two more during the fade, the first one never plays and the last one waits
+        for _ in 0..2 {
+            assert!(pipeline.parameter_sender.send(EngineMessage::SwapModel(swap())).is_ok());
+            output.extend_from_slice(pipeline.backend.pull());
+        }
+        assert!(pipeline.retired_receiver.try_recv().is_some());
+        assert!(pipeline.retired_receiver.try_recv().is_none());
+        for _ in 0..40 {
+            output.extend_from_slice(pipeline.backend.pull());
+        }
+        //the note fades out without a jump, then the two fades retire their model
+        assert!(output.windows(2).all(|pair| (pair[0] - pair[1]).abs() < 0.05));
+        assert!(pipeline.retired_receiver.try_recv().is_some());
+        assert!(pipeline.retired_receiver.try_recv().is_some());
+        assert!(pipeline.retired_receiver.try_recv().is_none());
+    }
+}
@@ -0,0 +1,404 @@
+use crate::envelope;
+use crate::envelope::Envelope;
+use crate::midi::MidiMessage;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode};
+use crate::oscillator::{BlepWaveform, PolyBlepOscillator};
+use crate::outils::midi_to_frequence;
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::rotary::Rotary;
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::ParameterUpdate;
+use crate::SineWave;
+use num_derive::FromPrimitive;
+
+const NUMBER_OF_VOICES: usize = 4;
+pub const NUMBER_OF_DRAWBARS: usize = 9;
+///the footages 16', 5 1/3', 8', 4', 2 2/3', 2', 1 3/5', 1 1/3' and 1', as ratios to the note
+pub const DRAWBAR_RATIOS: [f32; NUMBER_OF_DRAWBARS] = [0.5, 1.5, 1., 2., 3., 4., 5., 6., 8.];
+//in hertz, like the tonewheels the partials above fold back an octave
+const HIGHEST_WHEEL: f32 = 5920.;
+//in seconds
+const CLICK_TIME: f32 = 0.003;
+
+const NB_SYNTH_PARAM: usize = 18;
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum OrganModelParamID {
+    Drawbar1,
+    Drawbar2,
+    Drawbar3,
+    Drawbar4,
+    Drawbar5,
+    Drawbar6,
+    Drawbar7,
+    Drawbar8,
+    Drawbar9,
+    KeyClick,
+    Percussion,
+    PercussionHarmonic,
+    PercussionDecay,
+    RotaryDryWet,
+    RotarySpeed,
+    EnvelopeAttack,
+    EnvelopeRelease,
+    Volume,
+}
+
+#[derive(Clone, Copy)]
+struct OrganSettings {
+    //between 0 and 1, the drawbar pulled from 0 to 8
+    levels: [f32; NUMBER_OF_DRAWBARS],
+    click: f32,
+    percussion: f32,
+    //the drawbar sounding the percussion, 4' (second) or 2 2/3' (third)
+    percussion_drawbar: usize,
+    //gain multiplied at each sample
+    percussion_decay: f32,
+    //single trigger, the percussion waits for all the keys to be released
+    percussion_trigger: bool,
+}
+
+///nine sines, a key click and the percussion
+struct OrganVoice {
+    partials: [SineWave; NUMBER_OF_DRAWBARS],
+    envelope: Envelope,
+    click: PolyBlepOscillator,
+    click_left: usize,
+    click_length: usize,
+    percussion: f32,
+    note: Option<u8>,
+}
+
+impl OrganVoice {
+    fn new(index: usize) -> Self {
+        let mut click = PolyBlepOscillator::new();
+        click.waveform = BlepWaveform::Noise;
+        click.set_seed(index as u32);
+        OrganVoice {
+            partials: [SineWave::new(); NUMBER_OF_DRAWBARS],
+            envelope: Envelope::new(),
+            click,
+            click_left: 0,
+            click_length: 1,
+            percussion: 0.,
+            note: None,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.partials.iter_mut().for_each(|x| x.init(sample_rate));
+        self.envelope.init(sample_rate);
+        self.click.init(sample_rate);
+        self.click_length = (CLICK_TIME * sample_rate) as usize;
+    }
+
+    fn note_on(&mut self, midi_note: u8, percussion: bool) {
+        let frequency = midi_to_frequence(midi_note);
+        for (partial, ratio) in self.partials.iter_mut().zip(DRAWBAR_RATIOS.iter()) {
+            partial.frequency_hz = frequency * ratio;
+            while partial.frequency_hz > HIGHEST_WHEEL {
+                partial.frequency_hz /= 2.;
+            }
+        }
+        if percussion {
+            self.percussion = 1.;
+        }
+        self.note = Some(midi_note);
+        self.envelope.note_on();
+        self.click_left = self.click_length;
+    }
+
+    //the key contacts click again when released
+    fn note_off(&mut self) {
+        if self.note.take().is_some() {
+            self.click_left = self.click_length;
+        }
+        self.envelope.note_off();
+    }
+
+    fn process(&mut self, settings: &OrganSettings) -> f32 {
+        let mut output = 0.;
+        for (k, partial) in self.partials.iter_mut().enumerate() {
+            let sine = partial.process();
+            output += sine * settings.levels[k];
+            if k == settings.percussion_drawbar {
+                output += sine * self.percussion * settings.percussion;
+            }
+        }
+        self.percussion *= settings.percussion_decay;
+        output *= self.envelope.process();
+        if self.click_left > 0 {
+            output += self.click.process() * settings.click * self.click_left as f32
+                / self.click_length as f32;
+            self.click_left -= 1;
+        }
+        output * 0.25
+    }
+}
+
+impl Voice for OrganVoice {
+    type Context = OrganSettings;
+
+    fn start(&mut self, midi_note: u8, _retrigger: bool, settings: &mut OrganSettings) {
+        if self.note != Some(midi_note) {
+            self.note_on(midi_note, settings.percussion_trigger)
+        }
+    }
+
+    fn release(&mut self, _: &OrganSettings) {
+        self.note_off()
+    }
+
+    fn steal(&mut self, _: &OrganSettings) {
+        self.note = None;
+        self.envelope.steal();
+    }
+
+    fn level(&self, _: &OrganSettings) -> f32 {
+        self.envelope.value()
+    }
+
+    fn is_silent(&self, _: &OrganSettings) -> bool {
+        self.envelope.status == envelope::Segment::Off && self.click_left == 0
+    }
+}
+
+pub struct OrganModel {
+    voices: VoiceManager<OrganVoice>,
+    rotary: Rotary,
+    settings: OrganSettings,
+    sample_rate: f32,
+    volume: f32,
+}
+
+impl HasDescription for OrganModel {
+    const NAME: &'static str = "Organ";
+    const DESCRIPTION: &'static str = "drawbar organ, key click, percussion and rotary speaker";
+}
+
+impl HasConstructor for OrganModel {
+    fn new() -> Self {
+        OrganModel {
+            voices: VoiceManager::new((0..NUMBER_OF_VOICES).map(OrganVoice::new).collect(), VoiceMode::Oldest),
+            rotary: Rotary::new(),
+            settings: OrganSettings {
+                levels: [1., 1., 1., 0., 0., 0., 0., 0., 0.],
+                click: 0.3,
+                percussion: 0.,
+                percussion_drawbar: 3,
+                percussion_decay: 0.9999,
+                percussion_trigger: true,
+            },
+            sample_rate: 0.,
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.voices.init(sample_rate);
+        self.voices.iter_mut().for_each(|x| x.init(sample_rate));
+        self.rotary.init(sample_rate);
+    }
+}
+
+impl HasMidiInput for OrganModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        self.settings.percussion_trigger = self.voices.allocator().all_released();
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut self.settings),
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut self.settings),
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for OrganModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.fill(0.);
+        for i in 0..self.voices.len() {
+            for sample in left.iter_mut() {
+                self.voices.update(i, &mut self.settings);
+                let voice = &mut self.voices[i];
+                if voice.envelope.status == envelope::Segment::Off && voice.click_left == 0 {
+                    break;
+                }
+                *sample += voice.process(&self.settings);
+            }
+        }
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            let (rotary_left, rotary_right) = self.rotary.process(*left);
+            //vca
+            *left = rotary_left * self.volume;
+            *right = rotary_right * self.volume;
+        }
+    }
+}
+
+impl HasParameters for OrganModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = OrganModelParamID;
+        type P = ParameterCapsule;
+
+        let params = Parameters {
+            capsules: vec![
+                //drawbars, from 0 to 8
+                P::new(ID::Drawbar1 as i32, "16'", 35, '1', 0., 8., 1.),
+                P::new(ID::Drawbar2 as i32, "5 1/3'", 35, '2', 0., 8., 1.),
+                P::new(ID::Drawbar3 as i32, "8'", 35, '3', 0., 8., 1.),
+                P::new(ID::Drawbar4 as i32, "4'", 0, '4', 0., 8., 1.),
+                P::new(ID::Drawbar5 as i32, "2 2/3'", 0, '5', 0., 8., 1.),
+                P::new(ID::Drawbar6 as i32, "2'", 0, '6', 0., 8., 1.),
+                P::new(ID::Drawbar7 as i32, "1 3/5'", 0, '7', 0., 8., 1.),
+                P::new(ID::Drawbar8 as i32, "1 1/3'", 0, '8', 0., 8., 1.),
+                P::new(ID::Drawbar9 as i32, "1'", 0, '9', 0., 8., 1.),
+                P::new(ID::KeyClick as i32, "click", 10, 'c', 0., 1., 1.),
+                //percussion
+                P::new(ID::Percussion as i32, "perc", 0, 'p', 0., 1., 1.),
+                P::new(ID::PercussionHarmonic as i32, "perc-harm", 0, 'h', 0., 1., 1.),
+                //in seconds, until -60dB
+                P::new(ID::PercussionDecay as i32, "perc-decay", 10, 'k', 0.1, 2., 2.),
+                //rotary speaker
+                P::new(ID::RotaryDryWet as i32, "rotary", 20, 'r', 0., 1., 1.),
+                P::new(ID::RotarySpeed as i32, "rot-fast", 0, 's', 0., 1., 1.),
+                //envelope
+                P::new(
+                    ID::EnvelopeAttack as i32,
+                    "env-atk",
+                    0,
+                    'a',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    ID::EnvelopeRelease as i32,
+                    "env-rel",
+                    0,
+                    'd',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = OrganModelParamID;
+        let typed_id: OrganModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Drawbar1
+            | ID::Drawbar2
+            | ID::Drawbar3
+            | ID::Drawbar4
+            | ID::Drawbar5
+            | ID::Drawbar6
+            | ID::Drawbar7
+            | ID::Drawbar8
+            | ID::Drawbar9 => {
+                self.settings.levels[typed_id as usize - ID::Drawbar1 as usize] =
+                    new_value.round() / 8.
+            }
+            ID::KeyClick => self.settings.click = new_value,
+            ID::Percussion => self.settings.percussion = new_value,
+            ID::PercussionHarmonic => {
+                self.settings.percussion_drawbar = if new_value > 0.5 { 4 } else { 3 }
+            }
+            ID::PercussionDecay => {
+                self.settings.percussion_decay = 0.001_f32.powf(1. / (new_value * self.sample_rate))
+            }
+            ID::RotaryDryWet => self.rotary.dry_wet = new_value,
+            ID::RotarySpeed => self.rotary.set_fast(new_value > 0.5),
+            //envelope
+            ID::EnvelopeAttack => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_attack(new_value)),
+            ID::EnvelopeRelease => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_release(new_value)),
+        }
+    }
+}
+
+impl Synth for OrganModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{model, peak, render, render_stereo};
+
+    fn organ_model() -> OrganModel {
+        let mut model: OrganModel = model();
+        model.set_parameter((OrganModelParamID::RotaryDryWet as i32, 0.));
+        model.set_parameter((OrganModelParamID::KeyClick as i32, 0.));
+        model
+    }
+
+    #[test]
+    fn drawbars_set_the_partials() {
+        let mut model = organ_model();
+        //only the 8', a sine on the note
+        for id in OrganModelParamID::Drawbar1 as i32..=OrganModelParamID::Drawbar9 as i32 {
+            model.set_parameter((id, 0.));
+        }
+        model.set_parameter((OrganModelParamID::Drawbar3 as i32, 8.));
+        model.set_note(MidiMessage::NoteOn(69));
+        let (left, right) = render_stereo(&mut model, 48000);
+        let crossings = left.windows(2).filter(|pair| pair[0] < 0. && pair[1] >= 0.).count();
+        assert!((crossings as i32 - 440).abs() <= 1, "{}", crossings);
+        assert_eq!(left, right);
+
+        //the 16' alone is an octave below
+        model.set_parameter((OrganModelParamID::Drawbar3 as i32, 0.));
+        model.set_parameter((OrganModelParamID::Drawbar1 as i32, 8.));
+        let left = render(&mut model, 48000);
+        let crossings = left.windows(2).filter(|pair| pair[0] < 0. && pair[1] >= 0.).count();
+        assert!((crossings as i32 - 220).abs() <= 1, "{}", crossings);
+
+        //the rotary spreads it
+        model.set_parameter((OrganModelParamID::RotaryDryWet as i32, 1.));
+        let (left, right) = render_stereo(&mut model, 48000);
+        assert!(peak(&left) > 0.01);
+        assert_ne!(left, right);
+    }
+
+    #[test]
+    fn percussion_single_trigger() {
+        let mut model = organ_model();
+        model.set_parameter((OrganModelParamID::Drawbar1 as i32, 0.));
+        model.set_parameter((OrganModelParamID::Drawbar2 as i32, 0.));
+        model.set_parameter((OrganModelParamID::Percussion as i32, 1.));
+        model.set_parameter((OrganModelParamID::PercussionDecay as i32, 0.5));
+        model.set_note(MidiMessage::NoteOn(60));
+        assert_eq!(model.voices[0].percussion, 1.);
+        let left = render(&mut model, 48000);
+        assert!(peak(&left[..4800]) > peak(&left[43200..]) * 1.3);
+
+        //legato, no percussion until every key is released
+        model.set_note(MidiMessage::NoteOn(64));
+        assert_eq!(model.voices[1].percussion, 0.);
+        render(&mut model, 4800);
+        model.set_note(MidiMessage::NoteOff(60));
+        model.set_note(MidiMessage::NoteOff(64));
+        model.set_note(MidiMessage::NoteOn(67));
+        //on a silent voice, the released ones keep ringing
+        assert_eq!(model.voices.allocator().voice_of(67), Some(2));
+        assert_eq!(model.voices[2].percussion, 1.);
+    }
+}
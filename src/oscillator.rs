@@ -1,4 +1,5 @@
-use crate::outils::midi_to_frequence;
+use crate::outils::{convert_ms_to_sample, midi_to_frequence};
+use num_derive::FromPrimitive;
 
 pub enum Waveform {
     Sine,
@@ -7,6 +8,7 @@ pub enum Waveform {
     Triangle,
 }
 
+///Low frequency oscillator, output between -1 and 1
 pub struct Lfo {
     pub frequence: f32,
     waveform: Waveform,
@@ -85,6 +87,85 @@ impl Lfo {
     }
 }
 
+///Portamento : slides a frequency to the next note, every interval in the same time
+#[derive(Clone, Copy)]
+pub struct Glide {
+    frequency_hz: f32,
+    target_hz: f32,
+    //multiplied at each sample, so it glides evenly in pitch
+    ratio: f32,
+    remaining: usize,
+    //in milliseconds
+    time: f32,
+    sample_rate: f32,
+}
+
+impl Default for Glide {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Glide {
+    pub fn new() -> Self {
+        Glide {
+            frequency_hz: 0.,
+            target_hz: 0.,
+            ratio: 1.,
+            remaining: 0,
+            time: 0.,
+            sample_rate: 0.,
+        }
+    }
+
+    pub fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    ///time in ms, 0 jumps to the notes
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    ///a voice that never played starts right on its note
+    pub fn set_note(&mut self, midi_note: u8) {
+        self.target_hz = midi_to_frequence(midi_note);
+        let samples = convert_ms_to_sample(self.time, self.sample_rate) as usize;
+        if samples == 0 || self.frequency_hz == 0. {
+            self.frequency_hz = self.target_hz;
+            self.remaining = 0;
+            return;
+        }
+        self.ratio = (self.target_hz / self.frequency_hz).powf(1. / samples as f32);
+        self.remaining = samples;
+    }
+
+    pub fn is_gliding(&self) -> bool {
+        self.remaining > 0
+    }
+
+    ///samples left to the target
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency_hz
+    }
+
+    pub fn process(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.frequency_hz *= self.ratio;
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.frequency_hz = self.target_hz;
+            }
+        }
+        self.frequency_hz
+    }
+}
+
+///Sine oscillator driven by a frequency in hertz
 #[derive(Clone, Copy)]
 pub struct SineWave {
     pub frequency_hz: f32,
@@ -92,6 +173,12 @@ pub struct SineWave {
     pub phasor: f32,
 }
 
+impl Default for SineWave {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SineWave {
     pub fn new() -> Self {
         Self {
@@ -122,19 +209,42 @@ impl SineWave {
         self.increment_phasor();
         self.sine()
     }
+
+    ///phase modulation, `phase_offset` in cycles is added to the phasor for this sample only
+    pub fn process_phase_modulated(&mut self, phase_offset: f32) -> f32 {
+        self.increment_phasor();
+        ((self.phasor + phase_offset) * std::f32::consts::TAU).sin()
+    }
 }
 
+pub const MAXIMUM_PARTIALS: usize = 64;
+
+///Bank of sine partials, their spacing and gain slope give the waveform.
+///Partials above nyquist are culled, so high notes don't alias.
 #[derive(Clone, Copy)]
 pub struct HarmonicOscillator {
     //Parameter
-    pub frequency_hz: f32,
+    frequency_hz: f32,
+    partial_count: usize,
+    //waveform description, sqr is 2 / 1, tri is 2 / 2
+    harmonic_index_increment: f32,
+    harmonic_gain_exponent: f32,
+    //-1 keeps the even partials, 1 the odd ones, the fundamental stays
+    odd_even: f32,
+    //in dB per octave
+    tilt: f32,
+    //0 is harmonic, above the partials spread like a stiff string
+    stretch: f32,
+    //0 keeps the phases running, 1 scatter them on the whole period at each note
+    phase_randomize: f32,
     //computation variable
     sample_rate: f32,
-    sine_bank: [SineWave; 5],
+    sine_bank: [SineWave; MAXIMUM_PARTIALS],
+    gains: [f32; MAXIMUM_PARTIALS],
+    //partials under nyquist
+    active_partials: usize,
+    random_state: u32,
     pub current_sample_index: f32,
-    //waveform description, sqr is 2 / 1, tri is 2 / 2
-    pub harmonic_index_increment: f32,
-    pub harmonic_gain_exponent: f32,
 }
 
 impl HarmonicOscillator {
@@ -142,33 +252,144 @@ impl HarmonicOscillator {
         HarmonicOscillator {
             sample_rate: 0.0,
             frequency_hz,
-            sine_bank: [SineWave::new(); 5],
+            partial_count: 5,
+            sine_bank: [SineWave::new(); MAXIMUM_PARTIALS],
+            gains: [0.; MAXIMUM_PARTIALS],
+            active_partials: 0,
+            random_state: 0x9E37_79B9,
             current_sample_index: 0.0,
             harmonic_gain_exponent: 1.0,
             harmonic_index_increment: 1.0,
+            odd_even: 0.,
+            tilt: 0.,
+            stretch: 0.,
+            phase_randomize: 0.,
         }
     }
 
     pub fn init(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate;
         self.sine_bank.iter_mut().for_each(|x| x.init(sample_rate));
+        self.update_partials();
     }
 
     pub fn set_note(&mut self, midi_note: u8) {
-        self.frequency_hz = midi_to_frequence(midi_note);
+        self.set_frequency(midi_to_frequence(midi_note));
     }
 
-    pub fn process(&mut self) -> f32 {
-        let mut output = 0.0;
-        let mut i = 1.;
+    pub fn set_frequency(&mut self, frequency_hz: f32) {
+        if self.frequency_hz == frequency_hz {
+            return;
+        }
+        self.frequency_hz = frequency_hz;
+        self.update_partials();
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency_hz
+    }
 
+    pub fn set_partial_count(&mut self, partial_count: usize) {
+        self.partial_count = partial_count.clamp(1, MAXIMUM_PARTIALS);
+        self.update_partials();
+    }
+
+    pub fn set_harmonic_index_increment(&mut self, increment: f32) {
+        self.harmonic_index_increment = increment;
+        self.update_partials();
+    }
+
+    pub fn set_harmonic_gain_exponent(&mut self, exponent: f32) {
+        self.harmonic_gain_exponent = exponent;
+        self.update_partials();
+    }
+
+    pub fn set_odd_even(&mut self, odd_even: f32) {
+        self.odd_even = odd_even.clamp(-1., 1.);
+        self.update_partials();
+    }
+
+    pub fn set_tilt(&mut self, tilt: f32) {
+        self.tilt = tilt;
+        self.update_partials();
+    }
+
+    pub fn set_stretch(&mut self, stretch: f32) {
+        self.stretch = stretch;
+        self.update_partials();
+    }
+
+    pub fn set_phase_randomize(&mut self, amount: f32) {
+        self.phase_randomize = amount.clamp(0., 1.);
+    }
+
+    ///partials under nyquist, the ones actually summed
+    pub fn active_partials(&self) -> usize {
+        self.active_partials
+    }
+
+    ///called on note on, scatter the phase of each partial by the phase randomize amount
+    pub fn randomize_phases(&mut self) {
+        if self.phase_randomize == 0. {
+            return;
+        }
+        let mut state = self.random_state;
         for sine in self.sine_bank.iter_mut() {
-            let gain = 1.0 / (i as f32).powf(self.harmonic_gain_exponent);
-            sine.frequency_hz = self.frequency_hz * i;
+            //xorshift, cheap and good enough for audio
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            sine.phasor = state as f32 / u32::MAX as f32 * self.phase_randomize;
+        }
+        self.random_state = state;
+    }
+
+    ///frequencies and gains are computed when a parameter change, not for every sample
+    fn update_partials(&mut self) {
+        let nyquist = self.sample_rate / 2.;
+        let mut gain_sum = 0.;
+        self.active_partials = 0;
+        for i in 0..self.partial_count {
+            let ratio = 1. + i as f32 * self.harmonic_index_increment;
+            let ratio = ratio.powf(1. + self.stretch);
+            let frequency = self.frequency_hz * ratio;
+            //the ratios only grow, every next partial is above too
+            if frequency >= nyquist {
+                break;
+            }
+            let mut gain = 1.0 / ratio.powf(self.harmonic_gain_exponent);
+            //+6dB per octave is a gain proportional to the ratio
+            gain *= ratio.powf(self.tilt / 6.02);
+            let partial_number = i + 1;
+            if partial_number > 1 {
+                if partial_number % 2 == 0 {
+                    gain *= 1. - self.odd_even.max(0.);
+                } else {
+                    gain *= 1. + self.odd_even.min(0.);
+                }
+            }
+            self.sine_bank[i].frequency_hz = frequency;
+            self.gains[i] = gain;
+            gain_sum += gain;
+            self.active_partials += 1;
+        }
+        //the sum of the gains keeps the peak level when partials are added or culled
+        if gain_sum > 0. {
+            let normalization = 0.5 / gain_sum;
+            self.gains[..self.active_partials]
+                .iter_mut()
+                .for_each(|gain| *gain *= normalization);
+        }
+    }
+
+    pub fn process(&mut self) -> f32 {
+        let mut output = 0.0;
+        for (sine, gain) in self.sine_bank[..self.active_partials]
+            .iter_mut()
+            .zip(self.gains.iter())
+        {
             output += sine.process() * gain;
-            i += self.harmonic_index_increment;
         }
-        output /= 5.0;
 
         //volume adjustement
         // output *= ((self.harmonic_gain_exponent-0.01)/3.0).powf(1.2) * 100.;
@@ -187,6 +408,12 @@ pub struct Oscillator {
     pub gain_exponent: f32,
 }
 
+impl Default for Oscillator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Oscillator {
     pub fn new() -> Self {
         Oscillator {
@@ -220,7 +447,7 @@ impl Oscillator {
     }
 
     fn is_multiple_of_freq_above_nyquist(&self, multiple: f32) -> bool {
-        false
+        self.frequency_hz * multiple >= self.sample_rate / 2.
     }
 
     fn sine_wave(&mut self) -> f32 {
@@ -235,7 +462,7 @@ impl Oscillator {
         while !self.is_multiple_of_freq_above_nyquist(i) {
             let gain = 1.0 / (i as f32).powf(gain_exponent);
             output += gain * self.calculate_sine_output_from_freq(self.frequency_hz * i);
-            i += self.harmonic_index_increment;
+            i += harmonic_index_increment;
         }
         output
     }
@@ -244,3 +471,192 @@ impl Oscillator {
         self.process2(1.73, 2.67)
     }
 }
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)]
+pub enum BlepWaveform {
+    Saw,
+    Pulse,
+    Triangle,
+    Noise,
+}
+
+///correction around a discontinuity of the phasor `t`, `dt` being the phase increment
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.
+    } else if t > 1. - dt {
+        let t = (t - 1.) / dt;
+        t * t + t + t + 1.
+    } else {
+        0.
+    }
+}
+
+///Audio rate saw, pulse and triangle, their steps smoothed with PolyBLEP so they alias far less
+///than the naive `Lfo` shapes. Output between -1 and 1.
+#[derive(Clone, Copy)]
+pub struct PolyBlepOscillator {
+    pub frequency_hz: f32,
+    pub waveform: BlepWaveform,
+    ///part of the period the pulse is high, from 0 to 1
+    pub pulse_width: f32,
+    sample_rate: f32,
+    phasor: f32,
+    //the triangle is an integrated square
+    integrator: f32,
+    noise_state: u32,
+}
+
+impl Default for PolyBlepOscillator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PolyBlepOscillator {
+    pub fn new() -> Self {
+        PolyBlepOscillator {
+            frequency_hz: 440.,
+            waveform: BlepWaveform::Saw,
+            pulse_width: 0.5,
+            sample_rate: 0.,
+            phasor: 0.,
+            integrator: -1.,
+            noise_state: 0x9E37_79B9,
+        }
+    }
+
+    pub fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn set_note(&mut self, midi_note: u8) {
+        self.frequency_hz = midi_to_frequence(midi_note);
+    }
+
+    ///different seeds keep the noise of several oscillators uncorrelated
+    pub fn set_seed(&mut self, seed: u32) {
+        self.noise_state = 0x9E37_79B9 ^ seed.wrapping_mul(0x85EB_CA6B) | 1;
+    }
+
+    //xorshift, cheap and good enough for audio
+    fn noise(&mut self) -> f32 {
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 17;
+        self.noise_state ^= self.noise_state << 5;
+        self.noise_state as f32 / u32::MAX as f32 * 2. - 1.
+    }
+
+    fn pulse(&self, width: f32, dt: f32) -> f32 {
+        let t = self.phasor;
+        let naive = if t < width { 1. } else { -1. };
+        naive + poly_blep(t, dt) - poly_blep((t + 1. - width) % 1., dt)
+    }
+
+    pub fn process(&mut self) -> f32 {
+        let dt = (self.frequency_hz / self.sample_rate).min(0.5);
+        let output = match self.waveform {
+            BlepWaveform::Saw => 2. * self.phasor - 1. - poly_blep(self.phasor, dt),
+            BlepWaveform::Pulse => self.pulse(self.pulse_width.clamp(0.01, 0.99), dt),
+            BlepWaveform::Triangle => {
+                //a little leak so the rounding errors don't pile up
+                self.integrator =
+                    self.integrator * (1. - 0.01 * dt) + 4. * dt * self.pulse(0.5, dt);
+                self.integrator
+            }
+            BlepWaveform::Noise => self.noise(),
+        };
+        self.phasor = (self.phasor + dt) % 1.;
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(waveform: BlepWaveform, frequency_hz: f32, pulse_width: f32) -> Vec<f32> {
+        let mut oscillator = PolyBlepOscillator::new();
+        oscillator.init(48000.);
+        oscillator.waveform = waveform;
+        oscillator.frequency_hz = frequency_hz;
+        oscillator.pulse_width = pulse_width;
+        (0..48000).map(|_| oscillator.process()).collect()
+    }
+
+    fn mean(samples: &[f32]) -> f32 {
+        samples.iter().sum::<f32>() / samples.len() as f32
+    }
+
+    #[test]
+    fn blep_shapes() {
+        let saw = render(BlepWaveform::Saw, 100., 0.5);
+        assert!(mean(&saw).abs() < 0.01);
+        assert!(saw.iter().all(|sample| sample.abs() <= 1.01));
+
+        //high a quarter of the time
+        let pulse = render(BlepWaveform::Pulse, 100., 0.25);
+        assert!((mean(&pulse) + 0.5).abs() < 0.01);
+
+        let triangle = render(BlepWaveform::Triangle, 100., 0.5);
+        let peak = triangle.iter().fold(0., |peak: f32, sample| peak.max(sample.abs()));
+        assert!(peak > 0.9 && peak < 1.1);
+
+        let noise = render(BlepWaveform::Noise, 100., 0.5);
+        assert!(mean(&noise).abs() < 0.05);
+        assert!(noise.iter().all(|sample| sample.abs() <= 1.));
+    }
+
+    #[test]
+    fn smoother_than_naive() {
+        //at a high note the naive saw jumps by 2 at each period, the blep one is spread over two samples
+        let saw = render(BlepWaveform::Saw, 5000., 0.5);
+        let biggest_step = saw
+            .windows(2)
+            .fold(0., |step: f32, pair| step.max((pair[1] - pair[0]).abs()));
+        assert!(biggest_step < 1.9);
+    }
+
+    #[test]
+    fn partials_culled_above_nyquist() {
+        let mut oscillator = HarmonicOscillator::new(100.);
+        oscillator.init(48000.);
+        oscillator.set_partial_count(MAXIMUM_PARTIALS);
+        assert_eq!(oscillator.active_partials(), MAXIMUM_PARTIALS);
+        //only the 2 first partials of 10kHz stay under 24kHz
+        oscillator.set_frequency(10000.);
+        assert_eq!(oscillator.active_partials(), 2);
+        assert!((0..48000).all(|_| oscillator.process().abs() <= 0.5));
+
+        let mut naive = Oscillator::new();
+        naive.init(48000.);
+        naive.frequency_hz = 10000.;
+        assert!(!naive.is_multiple_of_freq_above_nyquist(2.));
+        assert!(naive.is_multiple_of_freq_above_nyquist(3.));
+    }
+
+    #[test]
+    fn spectrum_controls() {
+        let mut oscillator = HarmonicOscillator::new(100.);
+        oscillator.init(48000.);
+        oscillator.set_partial_count(8);
+        //only odd partials, the even ones are muted
+        oscillator.set_odd_even(1.);
+        assert!(oscillator.gains[1] == 0. && oscillator.gains[2] > 0.);
+        oscillator.set_odd_even(0.);
+
+        //a tilt of +6dB per octave cancels the 1/n slope
+        oscillator.set_tilt(6.02);
+        assert!((oscillator.gains[3] - oscillator.gains[0]).abs() < 0.001);
+
+        //stretched partials go sharp
+        oscillator.set_stretch(0.1);
+        assert!(oscillator.sine_bank[1].frequency_hz > 200.);
+
+        oscillator.set_phase_randomize(1.);
+        oscillator.randomize_phases();
+        let phases: Vec<f32> = oscillator.sine_bank.iter().map(|sine| sine.phasor).collect();
+        assert!(phases.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+}
@@ -77,6 +77,13 @@ pub fn equal_power_crossfade(dry: f32, wet: f32, mut parameter: f32) -> f32 {
     return (dry * volumes_dry) + (wet * volumes_wet);
 }
 
+/// -1 -> left
+/// 1 -> right
+pub fn equal_power_pan(sample: f32, pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1., 1.) + 1.) * std::f32::consts::FRAC_PI_4;
+    return (sample * angle.cos(), sample * angle.sin());
+}
+
 pub fn get_orca_character(value: i32)->Option<char>{
     return ORCA_CHARACTERS.get(value as usize).copied();
 }
@@ -1,6 +1,7 @@
 use crate::outils;
 use std::ops::{Index, IndexMut};
 
+///A value set with an ORCA character (0 to z) and mapped between min and max
 #[derive(Clone)]
 pub struct Parameter {
     pub display_name: String,
@@ -90,6 +91,7 @@ impl Parameter {
     }
 }
 
+///A parameter and the id the model knows it by
 #[derive(Clone)]
 pub struct ParameterCapsule {
     pub id: i32,
@@ -120,6 +122,7 @@ impl ParameterCapsule {
     }
 }
 
+///All the parameters of a model, in display order
 pub struct Parameters {
     pub capsules: Vec<ParameterCapsule>,
     pub nb_param: usize,
@@ -0,0 +1,449 @@
+use crate::buffer::{DelayLine, DelayMode};
+use crate::envelope::StealFade;
+use crate::midi::MidiMessage;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode};
+use crate::oscillator::{BlepWaveform, PolyBlepOscillator};
+use crate::outils::{midi_to_frequence, rt60_to_gain};
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::ParameterUpdate;
+use num_derive::FromPrimitive;
+
+const NUMBER_OF_VOICES: usize = 4;
+
+const NB_SYNTH_PARAM: usize = 6;
+//in seconds, long enough for one period of the lowest midi note
+const MAXIMUM_PERIOD: f32 = 0.13;
+//in seconds, how fast a released string is muted
+const MUTE_TIME: f32 = 0.15;
+//in seconds, length of the burst exciter
+const BURST_TIME: f32 = 0.003;
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum PluckModelParamID {
+    Exciter,
+    Brightness,
+    PickPosition,
+    Damping,
+    Decay,
+    Volume,
+}
+
+///what is sent in the string when it is plucked
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)]
+pub enum Exciter {
+    ///one period of noise
+    Noise,
+    ///a few milliseconds of fading noise, harder
+    Burst,
+}
+
+#[derive(Clone, Copy)]
+struct PluckSettings {
+    exciter: Exciter,
+    brightness: f32,
+    pick_position: f32,
+    damping: f32,
+    decay: f32,
+}
+
+///phase delay in samples of `(1 - coefficient) + coefficient * z^-1` at `omega` radians per sample
+fn one_zero_phase_delay(coefficient: f32, omega: f32) -> f32 {
+    (coefficient * omega.sin()).atan2(1. - coefficient + coefficient * omega.cos()) / omega
+}
+
+///a string : a delay line one period long, looped through a low-pass
+struct PluckVoice {
+    string: DelayLine,
+    //comb filter on the excitation, the notch of the pick position
+    pick: DelayLine,
+    noise: PolyBlepOscillator,
+    sample_rate: f32,
+    note: Option<u8>,
+    frequency_hz: f32,
+    //in samples
+    period: f32,
+    loop_gain: f32,
+    muted: bool,
+    excitation_left: usize,
+    excitation_length: usize,
+    excitation: f32,
+    //two last outputs of the string, for its loop filter
+    previous: [f32; 2],
+    //samples until the string is under -60dB
+    remaining: usize,
+    fade: StealFade,
+}
+
+impl PluckVoice {
+    fn new(index: usize) -> Self {
+        let mut noise = PolyBlepOscillator::new();
+        noise.waveform = BlepWaveform::Noise;
+        noise.set_seed(index as u32);
+        PluckVoice {
+            string: DelayLine::new(MAXIMUM_PERIOD, DelayMode::Comb),
+            pick: DelayLine::new(MAXIMUM_PERIOD / 2., DelayMode::Comb),
+            noise,
+            sample_rate: 0.,
+            note: None,
+            frequency_hz: 440.,
+            period: 1.,
+            loop_gain: 0.,
+            muted: false,
+            excitation_left: 0,
+            excitation_length: 1,
+            excitation: 0.,
+            previous: [0.; 2],
+            remaining: 0,
+            fade: StealFade::new(),
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.string.init(sample_rate);
+        self.string.set_feedback(0.);
+        self.pick.init(sample_rate);
+        self.pick.set_feedback(0.);
+        self.noise.init(sample_rate);
+        self.fade.init(sample_rate);
+    }
+
+    ///the loop is the delay line, the low-pass and one sample to feed the output back,
+    ///the line is shortened by the two others so the whole loop lasts exactly one period
+    fn tune(&mut self, settings: &PluckSettings) {
+        self.period = self.sample_rate / self.frequency_hz;
+        let omega = std::f32::consts::TAU / self.period;
+        let loop_filter = one_zero_phase_delay(settings.damping * 0.5, omega);
+        let target = self.period - 1. - loop_filter;
+        //the linear interpolation of the line is a one-zero filter too, its delay depends on the fraction
+        let mut delay = target;
+        for _ in 0..3 {
+            let fraction = delay - delay.floor();
+            delay += target - (delay.floor() + one_zero_phase_delay(fraction, omega));
+        }
+        self.string.set_delay_in_samples(delay);
+        self.pick
+            .set_delay_in_samples((self.period * settings.pick_position).max(1.));
+
+        let decay = if self.muted {
+            MUTE_TIME.min(settings.decay)
+        } else {
+            settings.decay
+        };
+        self.loop_gain = rt60_to_gain(decay, self.period / self.sample_rate);
+    }
+
+    fn pluck(&mut self, midi_note: u8, settings: &PluckSettings) {
+        self.note = Some(midi_note);
+        self.frequency_hz = midi_to_frequence(midi_note);
+        self.muted = false;
+        self.tune(settings);
+        self.excitation_length = match settings.exciter {
+            Exciter::Noise => self.period as usize,
+            Exciter::Burst => (BURST_TIME * self.sample_rate) as usize,
+        }
+        .max(1);
+        self.excitation_left = self.excitation_length;
+        self.remaining = self.excitation_length + (settings.decay * self.sample_rate) as usize;
+        //the lines still hold the stolen note, the new one starts from silence
+        if self.fade.is_stolen() {
+            self.string.clear();
+            self.pick.clear();
+            self.previous = [0.; 2];
+            self.excitation = 0.;
+        }
+        self.fade.reset();
+    }
+
+    fn mute(&mut self, settings: &PluckSettings) {
+        if self.note.take().is_none() {
+            return;
+        }
+        self.muted = true;
+        self.tune(settings);
+        self.remaining = self.remaining.min((MUTE_TIME * self.sample_rate) as usize);
+    }
+
+    fn process(&mut self, settings: &PluckSettings) -> f32 {
+        let mut noise = 0.;
+        if self.excitation_left > 0 {
+            let envelope = match settings.exciter {
+                Exciter::Noise => 1.,
+                Exciter::Burst => self.excitation_left as f32 / self.excitation_length as f32,
+            };
+            noise = self.noise.process() * envelope * 0.5;
+            self.excitation_left -= 1;
+        }
+        //a darker excitation for a softer pluck
+        let smoothing = settings.brightness * settings.brightness;
+        self.excitation += (noise - self.excitation) * smoothing.max(0.01);
+        let excitation = self.excitation - self.pick.process(self.excitation);
+
+        let damping = settings.damping * 0.5;
+        let feedback = (1. - damping) * self.previous[0] + damping * self.previous[1];
+        let output = self.string.process(excitation + feedback * self.loop_gain);
+        self.previous = [output, self.previous[0]];
+        self.remaining = self.remaining.saturating_sub(1);
+        output * self.fade.process()
+    }
+}
+
+impl Voice for PluckVoice {
+    type Context = PluckSettings;
+
+    fn start(&mut self, midi_note: u8, _retrigger: bool, settings: &mut PluckSettings) {
+        //a held string is not plucked again
+        if self.note != Some(midi_note) {
+            self.pluck(midi_note, settings)
+        }
+    }
+
+    fn release(&mut self, settings: &PluckSettings) {
+        self.mute(settings)
+    }
+
+    fn steal(&mut self, _: &PluckSettings) {
+        self.note = None;
+        self.excitation_left = 0;
+        self.remaining = self.remaining.min(self.fade.start());
+    }
+
+    fn level(&self, _: &PluckSettings) -> f32 {
+        self.remaining as f32
+    }
+
+    fn is_silent(&self, _: &PluckSettings) -> bool {
+        self.remaining == 0
+    }
+}
+
+pub struct PluckModel {
+    voices: VoiceManager<PluckVoice>,
+    settings: PluckSettings,
+    volume: f32,
+}
+
+impl PluckModel {
+    fn retune(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.tune(&self.settings);
+        }
+    }
+}
+
+impl HasDescription for PluckModel {
+    const NAME: &'static str = "Pluck";
+    const DESCRIPTION: &'static str = "Karplus-Strong plucked strings, damping, brightness and pick position";
+}
+
+impl HasConstructor for PluckModel {
+    fn new() -> Self {
+        PluckModel {
+            voices: VoiceManager::new((0..NUMBER_OF_VOICES).map(PluckVoice::new).collect(), VoiceMode::Oldest),
+            settings: PluckSettings {
+                exciter: Exciter::Noise,
+                brightness: 1.,
+                pick_position: 0.13,
+                damping: 0.3,
+                decay: 4.,
+            },
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.voices.init(sample_rate);
+        self.voices.iter_mut().for_each(|x| x.init(sample_rate));
+    }
+}
+
+impl HasMidiInput for PluckModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut self.settings),
+            //the strings still ringing are the last ones taken
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut self.settings),
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for PluckModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.fill(0.);
+        for i in 0..self.voices.len() {
+            for sample in left.iter_mut() {
+                self.voices.update(i, &mut self.settings);
+                let voice = &mut self.voices[i];
+                if voice.remaining == 0 {
+                    break;
+                }
+                *sample += voice.process(&self.settings);
+            }
+        }
+        for sample in left.iter_mut() {
+            //vca
+            *sample *= self.volume / NUMBER_OF_VOICES as f32;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for PluckModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = PluckModelParamID;
+        type P = ParameterCapsule;
+
+        let params = Parameters {
+            capsules: vec![
+                //excitation
+                P::new(ID::Exciter as i32, "exciter", 0, 'x', 0., 1., 1.),
+                P::new(ID::Brightness as i32, "bright", 25, 'b', 0., 1., 1.),
+                P::new(ID::PickPosition as i32, "pick-pos", 8, 'p', 0.02, 0.5, 1.),
+                //string
+                P::new(ID::Damping as i32, "damping", 10, 'd', 0., 1., 1.),
+                P::new(ID::Decay as i32, "decay", 15, 't', 0.1, 20., 2.),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = PluckModelParamID;
+        let typed_id: PluckModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Exciter => {
+                self.settings.exciter =
+                    num::FromPrimitive::from_i32(new_value.round() as i32).unwrap_or(Exciter::Burst)
+            }
+            ID::Brightness => self.settings.brightness = new_value,
+            //the others change the tuning or the loop gain
+            ID::PickPosition => {
+                self.settings.pick_position = new_value;
+                self.retune();
+            }
+            ID::Damping => {
+                self.settings.damping = new_value;
+                self.retune();
+            }
+            ID::Decay => {
+                self.settings.decay = new_value;
+                self.retune();
+            }
+        }
+    }
+}
+
+impl Synth for PluckModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{model, render};
+
+    ///the loudest frequency around `expected`, in cents from it
+    fn pitch_error(samples: &[f32], expected: f32) -> f32 {
+        let magnitude = |frequency: f32| {
+            let omega = std::f32::consts::TAU * frequency / 48000.;
+            let (re, im) = samples
+                .iter()
+                .enumerate()
+                .fold((0., 0.), |(re, im), (n, sample)| {
+                    let phase = omega * n as f32;
+                    (re + sample * phase.cos(), im - sample * phase.sin())
+                });
+            re * re + im * im
+        };
+        (-40..=40)
+            .map(|cents| cents as f32 * 0.5)
+            .max_by(|a, b| {
+                let frequency = |cents: f32| expected * 2_f32.powf(cents / 1200.);
+                magnitude(frequency(*a)).total_cmp(&magnitude(frequency(*b)))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn tuned_across_the_range() {
+        for midi_note in [40, 69, 100] {
+            let mut model: PluckModel = model();
+            model.set_parameter((PluckModelParamID::Damping as i32, 1.));
+            model.set_note(MidiMessage::NoteOn(midi_note));
+            let samples = render(&mut model, 12000);
+            let error = pitch_error(&samples, midi_to_frequence(midi_note));
+            assert!(error.abs() <= 2., "note {} is {} cents off", midi_note, error);
+        }
+    }
+
+    #[test]
+    fn released_strings_are_muted() {
+        let mut model: PluckModel = model();
+        assert_eq!(model.get_parameters().capsules.len(), NB_SYNTH_PARAM);
+        assert!(render(&mut model, 256).iter().all(|sample| *sample == 0.));
+
+        model.set_note(MidiMessage::NoteOn(60));
+        let energy = |samples: Vec<f32>| samples.iter().map(|sample| sample * sample).sum::<f32>();
+        let plucked = energy(render(&mut model, 4800));
+        assert!(plucked > 0.);
+
+        //a second note doesn't pluck the first string again
+        let excitation_left = model.voices[0].excitation_left;
+        model.set_note(MidiMessage::NoteOn(64));
+        assert_eq!(model.voices[0].excitation_left, excitation_left);
+
+        model.set_note(MidiMessage::NoteOff(60));
+        model.set_note(MidiMessage::NoteOff(64));
+        render(&mut model, 4800);
+        assert!(energy(render(&mut model, 4800)) < plucked * 0.01);
+        render(&mut model, 48000);
+        assert!(model.voices.iter().all(|voice| voice.remaining == 0));
+    }
+
+    #[test]
+    fn stolen_strings_fade_before_the_next_pluck() {
+        let mut model: PluckModel = model();
+        for midi_note in 60..60 + NUMBER_OF_VOICES as u8 {
+            model.set_note(MidiMessage::NoteOn(midi_note));
+        }
+        render(&mut model, 4800);
+        //the oldest string is stolen, the new note waits for its fade
+        model.set_note(MidiMessage::NoteOn(72));
+        assert_eq!(model.voices.pending_note(0), Some(72));
+        assert!(model.voices[0].remaining > 0);
+        render(&mut model, 480);
+        assert_eq!(model.voices.pending_note(0), None);
+        assert_eq!(model.voices[0].note, Some(72));
+    }
+
+    #[test]
+    fn stolen_strings_restart_from_silence() {
+        let model: PluckModel = model();
+        let settings = model.settings;
+        let mut voice = PluckVoice::new(0);
+        voice.init(48000.);
+        voice.pluck(60, &settings);
+        for _ in 0..4800 {
+            voice.process(&settings);
+        }
+        voice.steal(&settings);
+        while voice.remaining > 0 {
+            voice.process(&settings);
+        }
+        //nothing comes out before the new excitation went through the line
+        voice.pluck(72, &settings);
+        assert!((0..50).all(|_| voice.process(&settings) == 0.));
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+///Number of messages the ring can hold before the sender starts dropping them
+pub const QUEUE_CAPACITY: usize = 1024;
+
+///Sending half of a bounded single producer single consumer ring.
+///Never blocks nor allocates, when the ring is full the message is dropped and counted.
+pub struct QueueSender<T> {
+    producer: rtrb::Producer<T>,
+    overflows: Arc<AtomicUsize>,
+}
+
+///Receiving half, safe to use in the audio thread
+pub struct QueueReceiver<T> {
+    consumer: rtrb::Consumer<T>,
+}
+
+///the overflow counter can be shared by several queues, to report them all in one place
+pub fn bounded<T>(capacity: usize, overflows: Arc<AtomicUsize>) -> (QueueSender<T>, QueueReceiver<T>) {
+    let (producer, consumer) = rtrb::RingBuffer::new(capacity);
+    (
+        QueueSender {
+            producer,
+            overflows,
+        },
+        QueueReceiver { consumer },
+    )
+}
+
+impl<T> QueueSender<T> {
+    ///give the message back if the ring is full
+    pub fn send(&mut self, message: T) -> Result<(), T> {
+        self.producer.push(message).map_err(|rtrb::PushError::Full(message)| {
+            self.overflows.fetch_add(1, Ordering::Relaxed);
+            message
+        })
+    }
+}
+
+impl<T> QueueReceiver<T> {
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.consumer.pop().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overflow() {
+        let overflows = Arc::new(AtomicUsize::new(0));
+        let (mut sender, mut receiver) = bounded(2, overflows.clone());
+
+        assert!(sender.send(1).is_ok());
+        assert!(sender.send(2).is_ok());
+        //full, the message is given back and counted
+        assert_eq!(sender.send(3), Err(3));
+        assert_eq!(overflows.load(Ordering::Relaxed), 1);
+
+        assert_eq!(receiver.try_recv(), Some(1));
+        assert!(sender.send(4).is_ok());
+        assert_eq!(receiver.try_recv(), Some(2));
+        assert_eq!(receiver.try_recv(), Some(4));
+        assert_eq!(receiver.try_recv(), None);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::synth::{HasDescription, Synth};
+use crate::{DrumModel, FmModel, FormantModel, GranularModel, HarmonicModel, ModalModel, OrganModel, PluckModel, SamplerModel, SineModel, SpectralModel, VaModel, WavetableModel};
+use std::sync::Arc;
+
+///build a new model, called outside of the audio thread
+pub type ModelConstructor = Arc<dyn Fn() -> Box<dyn Synth> + Send + Sync>;
+
+///What the registry knows about a model, enough to list it and build it
+#[derive(Clone)]
+pub struct ModelEntry {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub constructor: ModelConstructor,
+}
+
+impl ModelEntry {
+    ///the constructor can capture what the model needs, ex: wavetables loaded from files
+    pub fn new(
+        name: &'static str,
+        description: &'static str,
+        constructor: impl Fn() -> Box<dyn Synth> + Send + Sync + 'static,
+    ) -> Self {
+        ModelEntry {
+            name,
+            description,
+            constructor: Arc::new(constructor),
+        }
+    }
+
+    ///entry of a model implementing `HasDescription`, built with `HasConstructor::new`
+    pub fn of<M: Synth + HasDescription + 'static>() -> Self {
+        Self::new(M::NAME, M::DESCRIPTION, || Box::new(M::new()))
+    }
+}
+
+///The list of the models, in the order of the selection menu and of the midi program changes
+#[derive(Clone, Default)]
+pub struct ModelRegistry {
+    entries: Vec<ModelEntry>,
+}
+
+impl ModelRegistry {
+    ///an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///the models shipped with Laminaria
+    pub fn with_builtin_models() -> Self {
+        let mut registry = Self::new();
+        registry.register_model::<HarmonicModel>();
+        registry.register_model::<SineModel>();
+        registry.register_model::<WavetableModel>();
+        registry.register_model::<FmModel>();
+        registry.register_model::<VaModel>();
+        registry.register_model::<PluckModel>();
+        registry.register_model::<GranularModel>();
+        registry.register_model::<SamplerModel>();
+        registry.register_model::<DrumModel>();
+        registry.register_model::<ModalModel>();
+        registry.register_model::<FormantModel>();
+        registry.register_model::<SpectralModel>();
+        registry.register_model::<OrganModel>();
+        registry
+    }
+
+    ///add a model at the end of the list, or replace the one with the same name
+    pub fn register(&mut self, entry: ModelEntry) -> &mut Self {
+        match self.find(entry.name) {
+            Some(index) => self.entries[index] = entry,
+            None => self.entries.push(entry),
+        }
+        self
+    }
+
+    pub fn register_model<M: Synth + HasDescription + 'static>(&mut self) -> &mut Self {
+        self.register(ModelEntry::of::<M>())
+    }
+
+    pub fn entries(&self) -> &[ModelEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| entry.name.to_string())
+            .collect()
+    }
+
+    ///index of a model, the case is ignored
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self, index: usize) -> Option<&'static str> {
+        self.entries.get(index).map(|entry| entry.name)
+    }
+
+    ///a new model, not initialized
+    pub fn build(&self, index: usize) -> Option<Box<dyn Synth>> {
+        self.entries.get(index).map(|entry| (entry.constructor)())
+    }
+
+    pub fn build_by_name(&self, name: &str) -> Option<Box<dyn Synth>> {
+        self.build(self.find(name)?)
+    }
+
+    ///one `name - description` line per model
+    pub fn describe(&self) -> String {
+        let width = self
+            .entries()
+            .iter()
+            .map(|entry| entry.name.len())
+            .max()
+            .unwrap_or(0);
+        self.entries()
+            .iter()
+            .map(|entry| format!("{:width$} - {}\n", entry.name, entry.description))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::synth::HasConstructor;
+
+    #[test]
+    fn register_and_find() {
+        let mut registry = ModelRegistry::with_builtin_models();
+        assert_eq!(registry.names(), vec!["Harmonic", "Sine", "Wavetable", "FM", "VA", "Pluck", "Granular", "Sampler", "Drums", "Modal", "Formant", "Spectral", "Organ"]);
+        assert_eq!(registry.find("sine"), Some(1));
+        assert!(registry.build_by_name("nope").is_none());
+
+        //a third party model, it replaces the builtin one with the same name
+        registry.register(ModelEntry::new("sine", "another sine", || {
+            Box::new(SineModel::new())
+        }));
+        registry.register(ModelEntry::new("Other", "yet another sine", || {
+            Box::new(SineModel::new())
+        }));
+        assert_eq!(registry.len(), 14);
+        assert_eq!(registry.entries()[1].description, "another sine");
+        assert!(registry.build(13).is_some());
+        assert!(registry.describe().contains("Other     - yet another sine"));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::midi::MidiMessage;
+use crate::synth::{Synth, MAXIMUM_BLOCK_SIZE};
+use anyhow::{anyhow, Context};
+use std::path::Path;
+
+///a midi message and the time it should be played at, in seconds
+pub struct TimedEvent {
+    pub time: f32,
+    pub message: MidiMessage,
+}
+
+//nan or infinite times would never be reached
+fn is_time(seconds: f32) -> bool {
+    seconds.is_finite() && seconds >= 0.
+}
+
+///parse `<midi note>:<start>:<length>` into a note on and a note off
+pub fn parse_note(spec: &str) -> Result<[TimedEvent; 2], anyhow::Error> {
+    let fields: Vec<&str> = spec.split(':').collect();
+    if fields.len() != 3 {
+        return Err(anyhow!("note '{spec}' should be <midi note>:<start>:<length>"));
+    }
+    let note: u8 = fields[0].trim().parse().context(format!("invalid note in '{spec}'"))?;
+    let start: f32 = fields[1].trim().parse().context(format!("invalid start in '{spec}'"))?;
+    let length: f32 = fields[2].trim().parse().context(format!("invalid length in '{spec}'"))?;
+    if !is_time(start) || !is_time(length) {
+        return Err(anyhow!("start and length in '{spec}' should be positive seconds"));
+    }
+    Ok([
+        TimedEvent {
+            time: start,
+            message: MidiMessage::NoteOn(note),
+        },
+        TimedEvent {
+            time: start + length,
+            message: MidiMessage::NoteOff(note),
+        },
+    ])
+}
+
+///parse an event file, empty lines and lines starting with `#` are skipped
+pub fn parse_events(text: &str) -> Result<Vec<TimedEvent>, anyhow::Error> {
+    let mut events = vec![];
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            return Err(anyhow!("line {}: expected <seconds> <on|off> <midi note>", line_index + 1));
+        }
+        let time: f32 = fields[0]
+            .parse()
+            .context(format!("line {}: invalid time", line_index + 1))?;
+        if !is_time(time) {
+            return Err(anyhow!("line {}: time should be positive seconds", line_index + 1));
+        }
+        let note: u8 = fields[2]
+            .parse()
+            .context(format!("line {}: invalid note", line_index + 1))?;
+        let message = match fields[1] {
+            "on" => MidiMessage::NoteOn(note),
+            "off" => MidiMessage::NoteOff(note),
+            other => return Err(anyhow!("line {}: unknown event '{other}'", line_index + 1)),
+        };
+        events.push(TimedEvent { time, message });
+    }
+    Ok(events)
+}
+
+///set every parameter to its default, then apply the `<cc>=<orca value>` overrides
+pub fn apply_parameters(
+    synth_model: &mut Box<dyn Synth>,
+    overrides: &[String],
+) -> Result<(), anyhow::Error> {
+    let mut parameters = synth_model.get_parameters();
+    for spec in overrides {
+        let (cc, value) = spec
+            .split_once('=')
+            .ok_or_else(|| anyhow!("parameter '{spec}' should be <cc>=<orca value>"))?;
+        let (Some(cc), Some(value)) = (cc.chars().next(), value.chars().next()) else {
+            return Err(anyhow!("parameter '{spec}' should be <cc>=<orca value>"));
+        };
+        let capsule = parameters
+            .capsules
+            .iter_mut()
+            .find(|capsule| capsule.parameter.midicc == cc)
+            .ok_or_else(|| anyhow!("no parameter on cc '{cc}'"))?;
+        capsule.parameter.set_value(value);
+    }
+    for capsule in parameters.capsules.iter() {
+        synth_model.set_parameter((capsule.id, capsule.parameter.get_raw_value()));
+    }
+    Ok(())
+}
+
+pub const RENDER_CHANNELS: u16 = 2;
+
+///play the events through the model and return the rendered stereo samples, interleaved
+pub fn render(
+    synth_model: &mut Box<dyn Synth>,
+    sample_rate: f32,
+    mut events: Vec<TimedEvent>,
+    length: usize,
+) -> Vec<f32> {
+    //note off first when two events share the same time, so a retriggered note is not cut
+    events.sort_by(|a, b| {
+        a.time.total_cmp(&b.time).then_with(|| {
+            let a_on = matches!(a.message, MidiMessage::NoteOn(_));
+            let b_on = matches!(b.message, MidiMessage::NoteOn(_));
+            a_on.cmp(&b_on)
+        })
+    });
+    let mut events = events.into_iter().peekable();
+    let mut output = Vec::with_capacity(length * RENDER_CHANNELS as usize);
+    let mut left_block = [0.; MAXIMUM_BLOCK_SIZE];
+    let mut right_block = [0.; MAXIMUM_BLOCK_SIZE];
+    let mut position = 0;
+    while position < length {
+        while let Some(event) = events.next_if(|event| event.time * sample_rate <= position as f32) {
+            synth_model.set_note(event.message);
+        }
+        //process up to the next event, so it lands on its exact sample
+        let next_event = events
+            .peek()
+            .map_or(length, |event| (event.time * sample_rate).ceil() as usize);
+        let block_size = (next_event.min(length) - position).clamp(1, MAXIMUM_BLOCK_SIZE);
+        let (left, right) = (&mut left_block[..block_size], &mut right_block[..block_size]);
+        synth_model.process_block(left, right);
+        for (left_sample, right_sample) in left.iter().zip(right.iter()) {
+            output.push(*left_sample);
+            output.push(*right_sample);
+        }
+        position += block_size;
+    }
+    output
+}
+
+pub fn write_wav(path: &Path, samples: &[f32], sample_rate: u32) -> Result<(), anyhow::Error> {
+    let spec = hound::WavSpec {
+        channels: RENDER_CHANNELS,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for sample in samples {
+        let value = (sample.clamp(-1., 1.) * i16::MAX as f32) as i16;
+        writer.write_sample(value)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::ModelRegistry;
+
+    fn build_model(name: &str) -> Option<Box<dyn Synth>> {
+        ModelRegistry::with_builtin_models().build_by_name(name)
+    }
+
+    #[test]
+    fn parsing() {
+        let [on, off] = parse_note("60:0.5:1").unwrap();
+        assert_eq!(on.message, MidiMessage::NoteOn(60));
+        assert_eq!(on.time, 0.5);
+        assert_eq!(off.message, MidiMessage::NoteOff(60));
+        assert_eq!(off.time, 1.5);
+        assert!(parse_note("60:0.5").is_err());
+        assert!(parse_note("60:nan:1").is_err());
+        assert!(parse_note("60:0:inf").is_err());
+        assert!(parse_note("60:-1:1").is_err());
+
+        let events = parse_events("# a chord\n0 on 60\n0 on 64\n\n1 off 60\n").unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].message, MidiMessage::NoteOff(60));
+        assert!(parse_events("0 maybe 60").is_err());
+        assert!(parse_events("NaN on 60").is_err());
+    }
+
+    #[test]
+    fn render_sine() {
+        let sample_rate = 8000.;
+        let mut synth_model = build_model("sine").unwrap();
+        synth_model.init(sample_rate);
+        apply_parameters(&mut synth_model, &["a=0".to_string()]).unwrap();
+        let events = parse_note("69:0.1:0.1").unwrap().into_iter().collect();
+        let samples = render(&mut synth_model, sample_rate, events, 4000);
+
+        assert_eq!(samples.len(), 8000);
+        //silent before the note on
+        assert!(samples[..1600].iter().all(|sample| *sample == 0.));
+        //sound during the note, the same on both sides for a mono model
+        assert!(samples[1600..3200].iter().any(|sample| sample.abs() > 0.01));
+        assert!(samples.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn block_matches_sample_by_sample() {
+        let sample_rate = 8000.;
+        let mut by_block = build_model("harmonic").unwrap();
+        let mut by_sample = build_model("harmonic").unwrap();
+        for synth_model in [&mut by_block, &mut by_sample] {
+            synth_model.init(sample_rate);
+            apply_parameters(synth_model, &["s=k".to_string(), "w=k".to_string()]).unwrap();
+            synth_model.set_note(MidiMessage::NoteOn(60));
+            synth_model.set_note(MidiMessage::NoteOn(67));
+        }
+        let mut left = [0.; 300];
+        let mut right = [0.; 300];
+        by_block.process_block(&mut left, &mut right);
+        for i in 0..300 {
+            assert_eq!(by_sample.process_stereo(), (left[i], right[i]));
+        }
+    }
+}
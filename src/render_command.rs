@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Context};
+use laminaria::registry::ModelRegistry;
+use laminaria::text_to_phonemes;
+use laminaria::render::{apply_parameters, parse_events, parse_note, render, write_wav, TimedEvent};
+use std::path::PathBuf;
+
+///Render a patch and a note sequence to a wav file, without any audio device
+#[derive(clap::Args, Debug)]
+pub struct RenderArgs {
+    ///synth model to render, see --list-models
+    #[arg(short, long, default_value = "harmonic")]
+    pub model: String,
+    ///wav file to write
+    #[arg(short, long, default_value = "laminaria.wav")]
+    pub output: PathBuf,
+    #[arg(short, long, default_value_t = 48000)]
+    pub sample_rate: u32,
+    ///length of the render in seconds, default to the last event plus the tail
+    #[arg(short, long)]
+    pub duration: Option<f32>,
+    ///seconds rendered after the last event to let the release and the effects ring
+    #[arg(short, long, default_value_t = 2.)]
+    pub tail: f32,
+    ///parameter value as `<cc>=<orca value>`, ex: `c=k`
+    #[arg(short, long = "param")]
+    pub params: Vec<String>,
+    ///note as `<midi note>:<start>:<length>` in seconds, ex: `60:0:1.5`
+    #[arg(short, long = "note")]
+    pub notes: Vec<String>,
+    ///text file with one `<seconds> <on|off> <midi note>` event per line
+    #[arg(short, long)]
+    pub events: Option<PathBuf>,
+    ///text sung by the models reading text, ex: `--text laminaria`
+    #[arg(long)]
+    pub text: Option<String>,
+}
+
+pub fn run(args: RenderArgs, registry: &ModelRegistry) -> Result<(), anyhow::Error> {
+    let mut synth_model = registry.build_by_name(&args.model).ok_or_else(|| {
+        anyhow!(
+            "unknown model '{}', available : {}",
+            args.model,
+            registry.names().join(", ")
+        )
+    })?;
+    let sample_rate = args.sample_rate as f32;
+    synth_model.init(sample_rate);
+    apply_parameters(&mut synth_model, &args.params)?;
+    if let Some(text) = &args.text {
+        synth_model.set_text(&text_to_phonemes(text));
+    }
+
+    let mut events: Vec<TimedEvent> = vec![];
+    for spec in args.notes.iter() {
+        events.extend(parse_note(spec)?);
+    }
+    if let Some(path) = &args.events {
+        let text = std::fs::read_to_string(path).context(format!("can't read {:?}", path))?;
+        events.extend(parse_events(&text)?);
+    }
+
+    let last_event = events.iter().map(|event| event.time).fold(0., f32::max);
+    let duration = args.duration.unwrap_or(last_event + args.tail);
+    let length = (duration.max(0.) * sample_rate) as usize;
+
+    let samples = render(&mut synth_model, sample_rate, events, length);
+    write_wav(&args.output, &samples, args.sample_rate)?;
+    println!("Rendered {:.2}s of {} to {:?}", duration, args.model, args.output);
+    Ok(())
+}
@@ -2,14 +2,27 @@ use crate::{buffer::DelayLine, outils};
 
 
 const NUMBER_OF_ALLPASS:usize = 6;
+///Allpass delays in series
 pub struct Reverb{
     allpasses: Vec<DelayLine>,
     pub dry_wet: f32,
+    //stretch the allpass times, so two reverbs can be decorrelated for stereo
+    time_scale: f32,
+}
+
+impl Default for Reverb{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Reverb{
 
     pub fn new()->Self{
+        Self::new_with_time_scale(1.)
+    }
+
+    pub fn new_with_time_scale(time_scale: f32)->Self{
         let mut allpasses: Vec<DelayLine> = vec![];
         for i in 0..NUMBER_OF_ALLPASS{
             allpasses.push(DelayLine::new(0.100, crate::buffer::DelayMode::Allpass));
@@ -17,6 +30,7 @@ impl Reverb{
         Reverb{
         allpasses: allpasses,
         dry_wet: 0.5,
+        time_scale,
         }
     }
 
@@ -25,7 +39,7 @@ impl Reverb{
 
           for (i, delayline) in self.allpasses.iter_mut().enumerate() {
             delayline.init(sample_rate);
-            delayline.set_delay_time(time[i]);
+            delayline.set_delay_time(time[i] * self.time_scale);
     }
     }
 
@@ -0,0 +1,118 @@
+use crate::filter::FilterType;
+use crate::oscillator::Waveform;
+use crate::Biquad;
+use crate::Lfo;
+use crate::RingBuffer;
+
+//in hertz, the slow (chorale) and fast (tremolo) speeds
+const HORN_SPEEDS: (f32, f32) = (0.8, 6.7);
+const DRUM_SPEEDS: (f32, f32) = (0.7, 5.8);
+//in seconds, the light horn gets to its speed before the heavy drum
+const HORN_INERTIA: f32 = 0.6;
+const DRUM_INERTIA: f32 = 3.;
+const CROSSOVER: f32 = 800.;
+
+///One turning speaker, the delay of a `RingBuffer` moved by an `Lfo` makes the doppler
+struct Rotor {
+    lfo: Lfo,
+    buffer: RingBuffer,
+    speeds: (f32, f32),
+    inertia: f32,
+    //in seconds, half of the delay swing
+    depth: f32,
+    depth_in_samples: f32,
+    tremolo: f32,
+    sample_rate: f32,
+}
+
+impl Rotor {
+    fn new(speeds: (f32, f32), inertia: f32, depth: f32, tremolo: f32) -> Self {
+        let mut lfo = Lfo::new(speeds.0);
+        lfo.set_freq_and_shape(speeds.0, Waveform::Sine);
+        Rotor {
+            lfo,
+            buffer: RingBuffer::new(0.01),
+            speeds,
+            inertia,
+            depth,
+            depth_in_samples: 0.,
+            tremolo,
+            sample_rate: 0.,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.lfo.init(sample_rate);
+        self.buffer.init(sample_rate);
+        self.depth_in_samples = self.depth * sample_rate;
+    }
+
+    fn process(&mut self, sample: f32, fast: bool) -> (f32, f32) {
+        let target = if fast { self.speeds.1 } else { self.speeds.0 };
+        let frequence = self.lfo.frequence;
+        self.lfo
+            .set_frequence(frequence + (target - frequence) / (self.inertia * self.sample_rate));
+        let position = self.lfo.tick();
+        self.buffer.write_sample(sample);
+        //the two microphones face each other, one hears the rotor coming while the other hears it going
+        let left = self.buffer.read_behind(self.depth_in_samples * (1. + position))
+            * (1. + self.tremolo * position);
+        let right = self.buffer.read_behind(self.depth_in_samples * (1. - position))
+            * (1. - self.tremolo * position);
+        (left, right)
+    }
+}
+
+///Rotating speaker : the highs go to a fast horn, the lows to a slower drum, both speed up or slow down with some inertia
+pub struct Rotary {
+    horn: Rotor,
+    drum: Rotor,
+    low_pass: Biquad,
+    fast: bool,
+    pub dry_wet: f32,
+}
+
+impl Default for Rotary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rotary {
+    pub fn new() -> Self {
+        let mut low_pass = Biquad::new(FilterType::LPF);
+        low_pass.set_resonance_feedback(false);
+        low_pass.set_frequence_and_resonance(CROSSOVER, 0.7);
+        Rotary {
+            horn: Rotor::new(HORN_SPEEDS, HORN_INERTIA, 0.0003, 0.4),
+            drum: Rotor::new(DRUM_SPEEDS, DRUM_INERTIA, 0.0006, 0.25),
+            low_pass,
+            fast: false,
+            dry_wet: 0.5,
+        }
+    }
+
+    pub fn init(&mut self, sample_rate: f32) {
+        self.horn.init(sample_rate);
+        self.drum.init(sample_rate);
+        self.low_pass.init(sample_rate);
+    }
+
+    ///chorale when slow, tremolo when fast
+    pub fn set_fast(&mut self, fast: bool) {
+        self.fast = fast;
+    }
+
+    pub fn process(&mut self, sample: f32) -> (f32, f32) {
+        let low = self.low_pass.process(sample);
+        //what the drum doesn't take, so the two add up to the input
+        let (horn_left, horn_right) = self.horn.process(sample - low, self.fast);
+        let (drum_left, drum_right) = self.drum.process(low, self.fast);
+        let dry = sample * (1. - self.dry_wet);
+        (
+            dry + (horn_left + drum_left) * self.dry_wet,
+            dry + (horn_right + drum_right) * self.dry_wet,
+        )
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{anyhow, Context};
+use std::path::Path;
+
+///Mono audio loaded from a file, the material of the granular and sampler models
+pub struct Sample {
+    pub name: String,
+    pub sample_rate: f32,
+    pub samples: Vec<f32>,
+}
+
+impl Sample {
+    pub fn from_samples(name: &str, sample_rate: f32, samples: Vec<f32>) -> Self {
+        Sample {
+            name: name.to_string(),
+            sample_rate,
+            samples,
+        }
+    }
+
+    ///multichannel files are mixed down
+    pub fn from_wav(path: &Path) -> Result<Self, anyhow::Error> {
+        let mut reader =
+            hound::WavReader::open(path).context(format!("can't open {:?}", path))?;
+        let spec = reader.spec();
+        let interleaved: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 / scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        let channels = spec.channels.max(1) as usize;
+        let samples: Vec<f32> = interleaved
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+        if samples.is_empty() {
+            return Err(anyhow!("{:?} is empty", path));
+        }
+
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(Self::from_samples(&name, spec.sample_rate as f32, samples))
+    }
+
+    ///wav or aiff, chosen from the extension
+    pub fn from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "aif" | "aiff" | "aifc" => Self::from_aiff(path),
+            _ => Self::from_wav(path),
+        }
+    }
+
+    ///uncompressed aiff or aifc, multichannel files are mixed down
+    pub fn from_aiff(path: &Path) -> Result<Self, anyhow::Error> {
+        let bytes = std::fs::read(path).context(format!("can't open {:?}", path))?;
+        if bytes.len() < 12
+            || &bytes[0..4] != b"FORM"
+            || !matches!(&bytes[8..12], b"AIFF" | b"AIFC")
+        {
+            return Err(anyhow!("{:?} is not an aiff file", path));
+        }
+
+        //channels, bits per sample, sample rate, then the sound data
+        let mut format: Option<(usize, usize, f32)> = None;
+        let mut little_endian = false;
+        let mut data: &[u8] = &[];
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size = u32::from_be_bytes(bytes[offset + 4..offset + 8].try_into()?) as usize;
+            let chunk = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+            match id {
+                b"COMM" if chunk.len() >= 18 => {
+                    let channels = u16::from_be_bytes([chunk[0], chunk[1]]) as usize;
+                    let bits = u16::from_be_bytes([chunk[6], chunk[7]]) as usize;
+                    let sample_rate = extended_to_f32(chunk[8..18].try_into()?);
+                    if chunk.len() >= 22 {
+                        match &chunk[18..22] {
+                            b"NONE" => {}
+                            b"sowt" => little_endian = true,
+                            other => {
+                                return Err(anyhow!(
+                                    "{:?} is compressed ({}), not supported",
+                                    path,
+                                    String::from_utf8_lossy(other)
+                                ))
+                            }
+                        }
+                    }
+                    format = Some((channels.max(1), bits, sample_rate));
+                }
+                //the sound data starts after its offset and block size
+                b"SSND" if chunk.len() >= 8 => {
+                    let start = 8 + u32::from_be_bytes(chunk[0..4].try_into()?) as usize;
+                    data = &chunk[start.min(chunk.len())..];
+                }
+                _ => {}
+            }
+            //chunks are padded to an even size
+            offset += 8 + size + size % 2;
+        }
+
+        let Some((channels, bits, sample_rate)) = format else {
+            return Err(anyhow!("{:?} has no COMM chunk", path));
+        };
+        if !(1..=32).contains(&bits) {
+            return Err(anyhow!("{:?} has {} bits per sample", path, bits));
+        }
+        let width = bits.div_ceil(8);
+        let scale = (1_i64 << (width * 8 - 1)) as f32;
+        let interleaved: Vec<f32> = data
+            .chunks_exact(width)
+            .map(|sample| {
+                //most significant byte first, then the sign is extended to 32 bits
+                let mut value: i32 = 0;
+                for i in 0..width {
+                    let byte = if little_endian { sample[width - 1 - i] } else { sample[i] };
+                    value = (value << 8) | byte as i32;
+                }
+                let unused = 32 - width * 8;
+                ((value << unused) >> unused) as f32 / scale
+            })
+            .collect();
+        let samples: Vec<f32> = interleaved
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+        if samples.is_empty() {
+            return Err(anyhow!("{:?} is empty", path));
+        }
+
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(Self::from_samples(&name, sample_rate, samples))
+    }
+
+    ///linear interpolation to another sample rate, outside of the audio thread
+    pub fn resample(&self, sample_rate: f32) -> Vec<f32> {
+        if self.sample_rate == sample_rate || self.samples.is_empty() {
+            return self.samples.clone();
+        }
+        let ratio = self.sample_rate / sample_rate;
+        let length = (self.samples.len() as f32 / ratio) as usize;
+        (0..length)
+            .map(|i| {
+                let position = i as f32 * ratio;
+                let index = position.floor() as usize;
+                let next = (index + 1).min(self.samples.len() - 1);
+                let frac = position - index as f32;
+                self.samples[index] * (1. - frac) + self.samples[next] * frac
+            })
+            .collect()
+    }
+}
+
+///80 bits extended float of the aiff sample rate
+fn extended_to_f32(bytes: [u8; 10]) -> f32 {
+    let exponent = (u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7FFF) as i32;
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+    if exponent == 0 && mantissa == 0 {
+        return 0.;
+    }
+    (mantissa as f64 * 2_f64.powi(exponent - 16383 - 63)) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resample() {
+        let sample = Sample::from_samples("ramp", 24000., vec![0., 1., 2., 3.]);
+        assert_eq!(sample.resample(48000.), vec![0., 0.5, 1., 1.5, 2., 2.5, 3., 3.]);
+        assert_eq!(sample.resample(24000.), sample.samples);
+    }
+
+    #[test]
+    fn load_aiff() {
+        //stereo 16 bits at 44100Hz, two frames
+        let mut comm = vec![0, 2, 0, 0, 0, 2, 0, 16];
+        comm.extend([0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        let mut ssnd = vec![0; 8];
+        for value in [16384_i16, 16384, -32768, 0] {
+            ssnd.extend(value.to_be_bytes());
+        }
+        let mut body = b"AIFF".to_vec();
+        for (id, chunk) in [(b"COMM", comm), (b"SSND", ssnd)] {
+            body.extend(id);
+            body.extend((chunk.len() as u32).to_be_bytes());
+            body.extend(chunk);
+        }
+        let mut bytes = b"FORM".to_vec();
+        bytes.extend((body.len() as u32).to_be_bytes());
+        bytes.extend(body);
+
+        let path = std::env::temp_dir().join("laminaria_sample_test.aiff");
+        std::fs::write(&path, bytes).unwrap();
+        let sample = Sample::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sample.sample_rate, 44100.);
+        assert_eq!(sample.samples, vec![0.5, -0.5]);
+        assert_eq!(sample.name, "laminaria_sample_test");
+    }
+}
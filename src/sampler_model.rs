@@ -0,0 +1,480 @@
+use crate::buffer::interpolate;
+use crate::envelope;
+use crate::envelope::Envelope;
+use crate::midi::MidiMessage;
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::render::{render, TimedEvent, RENDER_CHANNELS};
+use crate::sample::Sample;
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::ParameterUpdate;
+use crate::PluckModel;
+use anyhow::{anyhow, Context};
+use num_derive::FromPrimitive;
+use std::path::Path;
+use std::sync::Arc;
+
+const NUMBER_OF_VOICES: usize = 8;
+//in seconds, the pluck played when no sample is loaded
+const BUILTIN_SAMPLE_TIME: f32 = 1.5;
+const DEFAULT_ROOT_NOTE: u8 = 60;
+
+const NB_SYNTH_PARAM: usize = 9;
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum SamplerModelParamID {
+    Start,
+    End,
+    Loop,
+    LoopStart,
+    OneShot,
+    Tune,
+    EnvelopeAttack,
+    EnvelopeRelease,
+    Volume,
+}
+
+///A sample played by the notes from `lowest_note` to `highest_note`,
+///at its recorded pitch on `root_note`
+#[derive(Clone)]
+pub struct SampleZone {
+    pub sample: Arc<Sample>,
+    pub lowest_note: u8,
+    pub highest_note: u8,
+    pub root_note: u8,
+}
+
+impl SampleZone {
+    ///the whole keyboard, recorded pitch on C4
+    pub fn new(sample: Arc<Sample>) -> Self {
+        SampleZone {
+            sample,
+            lowest_note: 0,
+            highest_note: 127,
+            root_note: DEFAULT_ROOT_NOTE,
+        }
+    }
+
+    ///`<file>`, `<file>@<note>` for a single key, or `<file>@<lowest>-<highest>[@<root>]`,
+    ///the root is the lowest note by default. The file is wav or aiff.
+    pub fn parse(spec: &str) -> Result<Self, anyhow::Error> {
+        //from the right, the path can hold '@' too: only the fields made of note numbers are notes
+        let is_notes = |field: &str| {
+            !field.trim().is_empty() && field.chars().all(|c| c.is_ascii_digit() || c == '-' || c == ' ')
+        };
+        let mut fields: Vec<&str> = spec.rsplitn(3, '@').collect();
+        let mut notes = vec![];
+        while fields.len() > 1 && is_notes(fields[0]) {
+            notes.push(fields.remove(0));
+        }
+        let path = fields.into_iter().rev().collect::<Vec<&str>>().join("@");
+        let (range, root) = match notes[..] {
+            [root, range] => (Some(range), Some(root)),
+            [range] => (Some(range), None),
+            _ => (None, None),
+        };
+        let note = |text: &str| -> Result<u8, anyhow::Error> {
+            let note: u8 = text
+                .trim()
+                .parse()
+                .context(format!("invalid note '{text}' in '{spec}'"))?;
+            if note > 127 {
+                return Err(anyhow!("note {note} is above 127 in '{spec}'"));
+            }
+            Ok(note)
+        };
+
+        let sample = Arc::new(Sample::from_file(Path::new(&path))?);
+        let mut zone = Self::new(sample);
+        if let Some(range) = range {
+            let (lowest, highest) = match range.split_once('-') {
+                Some((lowest, highest)) => (note(lowest)?, note(highest)?),
+                None => (note(range)?, note(range)?),
+            };
+            zone.lowest_note = lowest.min(highest);
+            zone.highest_note = lowest.max(highest);
+            zone.root_note = zone.lowest_note;
+        }
+        if let Some(root) = root {
+            zone.root_note = note(root)?;
+        }
+        Ok(zone)
+    }
+
+    fn contains(&self, midi_note: u8) -> bool {
+        (self.lowest_note..=self.highest_note).contains(&midi_note)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SamplerSettings {
+    start: f32,
+    end: f32,
+    looping: bool,
+    loop_start: f32,
+    one_shot: bool,
+    tune: f32,
+}
+
+#[derive(Clone, Copy)]
+struct SamplerVoice {
+    zone: usize,
+    note: Option<u8>,
+    //in samples of the zone
+    position: f32,
+    step: f32,
+    envelope: Envelope,
+    playing: bool,
+    //to steal the oldest voice
+    started: u64,
+}
+
+impl SamplerVoice {
+    fn new() -> Self {
+        SamplerVoice {
+            zone: 0,
+            note: None,
+            position: 0.,
+            step: 1.,
+            envelope: Envelope::new(),
+            playing: false,
+            started: 0,
+        }
+    }
+
+    fn process(&mut self, samples: &[f32], settings: &SamplerSettings) -> f32 {
+        //the last sample is not read, so the interpolation never wraps
+        let length = (samples.len() - 1) as f32;
+        let end = (settings.end * length).max(settings.start * length + 1.).min(length);
+        if self.position >= end {
+            //one shots always play to the end
+            if settings.looping && !settings.one_shot && self.note.is_some() {
+                let loop_start =
+                    (settings.start + (settings.end - settings.start) * settings.loop_start) * length;
+                let loop_length = (end - loop_start).max(1.);
+                while self.position >= end {
+                    self.position -= loop_length;
+                }
+            } else {
+                self.playing = false;
+                return 0.;
+            }
+        }
+        let output = interpolate(samples, self.position) * self.envelope.process();
+        self.position += self.step;
+        if self.envelope.status == envelope::Segment::Off {
+            self.playing = false;
+        }
+        output
+    }
+}
+
+pub struct SamplerModel {
+    zones: Vec<SampleZone>,
+    voices: [SamplerVoice; NUMBER_OF_VOICES],
+    settings: SamplerSettings,
+    sample_rate: f32,
+    note_counter: u64,
+    volume: f32,
+}
+
+impl SamplerModel {
+    ///the first zone containing a note plays it
+    pub fn with_zones(zones: Vec<SampleZone>) -> Self {
+        let mut model = Self::new();
+        model.zones = zones;
+        model
+    }
+
+    pub fn zones(&self) -> &[SampleZone] {
+        &self.zones
+    }
+
+    ///a pluck of the Pluck model, so there is a sound without any file
+    fn builtin_zone(sample_rate: f32) -> SampleZone {
+        let mut pluck: Box<dyn Synth> = Box::new(PluckModel::new());
+        pluck.init(sample_rate);
+        for capsule in pluck.get_parameters().capsules.iter() {
+            pluck.set_parameter((capsule.id, capsule.parameter.get_raw_value()));
+        }
+        let events = vec![TimedEvent {
+            time: 0.,
+            message: MidiMessage::NoteOn(DEFAULT_ROOT_NOTE),
+        }];
+        let length = (BUILTIN_SAMPLE_TIME * sample_rate) as usize;
+        let interleaved = render(&mut pluck, sample_rate, events, length);
+        let left = interleaved.into_iter().step_by(RENDER_CHANNELS as usize).collect();
+        SampleZone::new(Arc::new(Sample::from_samples("pluck", sample_rate, left)))
+    }
+
+    fn note_on(&mut self, midi_note: u8) {
+        let Some(zone_index) = self.zones.iter().position(|zone| zone.contains(midi_note)) else {
+            return;
+        };
+        let zone = &self.zones[zone_index];
+        if zone.sample.samples.len() < 2 {
+            return;
+        }
+        //a free voice, else the oldest one
+        let voice = match self.voices.iter_mut().find(|voice| !voice.playing) {
+            Some(voice) => voice,
+            None => self.voices.iter_mut().min_by_key(|voice| voice.started).unwrap(),
+        };
+        let semitones = midi_note as f32 - zone.root_note as f32 + self.settings.tune;
+        voice.zone = zone_index;
+        voice.note = Some(midi_note);
+        voice.step = zone.sample.sample_rate / self.sample_rate * 2_f32.powf(semitones / 12.);
+        voice.position = self.settings.start * (zone.sample.samples.len() - 1) as f32;
+        voice.playing = true;
+        voice.envelope.note_on();
+        self.note_counter += 1;
+        voice.started = self.note_counter;
+    }
+
+    fn note_off(&mut self, midi_note: u8) {
+        for voice in self.voices.iter_mut() {
+            if voice.note == Some(midi_note) {
+                voice.note = None;
+                if !self.settings.one_shot {
+                    voice.envelope.note_off();
+                }
+            }
+        }
+    }
+}
+
+impl HasDescription for SamplerModel {
+    const NAME: &'static str = "Sampler";
+    const DESCRIPTION: &'static str = "wav and aiff files mapped on key ranges, loop points, one shot or gated";
+}
+
+impl HasConstructor for SamplerModel {
+    fn new() -> Self {
+        SamplerModel {
+            zones: vec![],
+            voices: [SamplerVoice::new(); NUMBER_OF_VOICES],
+            settings: SamplerSettings {
+                start: 0.,
+                end: 1.,
+                looping: false,
+                loop_start: 0.,
+                one_shot: false,
+                tune: 0.,
+            },
+            sample_rate: 0.,
+            note_counter: 0,
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.voices.iter_mut().for_each(|x| x.envelope.init(sample_rate));
+        if self.zones.is_empty() {
+            self.zones.push(Self::builtin_zone(sample_rate));
+        }
+    }
+}
+
+impl HasMidiInput for SamplerModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.note_off(midi_note),
+            MidiMessage::NoteOn(midi_note) => self.note_on(midi_note),
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for SamplerModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.fill(0.);
+        for voice in self.voices.iter_mut() {
+            let samples = &self.zones[voice.zone].sample.samples;
+            for sample in left.iter_mut() {
+                if !voice.playing {
+                    break;
+                }
+                *sample += voice.process(samples, &self.settings);
+            }
+        }
+        for sample in left.iter_mut() {
+            //vca
+            *sample *= self.volume;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for SamplerModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = SamplerModelParamID;
+        type P = ParameterCapsule;
+
+        let params = Parameters {
+            capsules: vec![
+                //playback, in fractions of the sample
+                P::new(ID::Start as i32, "start", 0, 's', 0., 1., 1.),
+                P::new(ID::End as i32, "end", 35, 'e', 0., 1., 1.),
+                P::new(ID::Loop as i32, "loop", 0, 'l', 0., 1., 1.),
+                P::new(ID::LoopStart as i32, "loop-start", 0, 'p', 0., 1., 1.),
+                P::new(ID::OneShot as i32, "one-shot", 0, 'o', 0., 1., 1.),
+                //semitones added to the note
+                P::new(ID::Tune as i32, "tune", 17, 't', -17., 18., 1.),
+                //envelope
+                P::new(
+                    ID::EnvelopeAttack as i32,
+                    "env-atk",
+                    0,
+                    'a',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    ID::EnvelopeRelease as i32,
+                    "env-rel",
+                    3,
+                    'd',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = SamplerModelParamID;
+        let typed_id: SamplerModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Start => self.settings.start = new_value,
+            ID::End => self.settings.end = new_value,
+            ID::Loop => self.settings.looping = new_value > 0.5,
+            ID::LoopStart => self.settings.loop_start = new_value,
+            ID::OneShot => self.settings.one_shot = new_value > 0.5,
+            //the playing voices keep their pitch
+            ID::Tune => self.settings.tune = new_value.round(),
+            //envelope
+            ID::EnvelopeAttack => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_attack(new_value)),
+            ID::EnvelopeRelease => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_release(new_value)),
+        }
+    }
+}
+
+impl Synth for SamplerModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{render, with_defaults};
+
+    //100 samples of a ramp at 24kHz on C4, and a click on C3
+    fn sampler_model() -> SamplerModel {
+        let ramp = Sample::from_samples("ramp", 24000., (0..100).map(|i| i as f32 / 100.).collect());
+        let mut click = SampleZone::new(Arc::new(Sample::from_samples("click", 48000., vec![1.; 10])));
+        click.lowest_note = 48;
+        click.highest_note = 48;
+        with_defaults(SamplerModel::with_zones(vec![click, SampleZone::new(Arc::new(ramp))]))
+    }
+
+    #[test]
+    fn zones_and_pitch() {
+        let mut model = sampler_model();
+        assert_eq!(model.get_parameters().capsules.len(), NB_SYNTH_PARAM);
+        model.set_note(MidiMessage::NoteOn(48));
+        assert_eq!(model.voices[0].zone, 0);
+        //the ramp is at half the sample rate, an octave up reads it at its recorded speed
+        model.set_note(MidiMessage::NoteOn(72));
+        assert_eq!(model.voices[1].zone, 1);
+        assert_eq!(model.voices[1].step, 1.);
+        model.set_note(MidiMessage::NoteOn(60));
+        assert_eq!(model.voices[2].step, 0.5);
+
+        //without loop, the voices stop at the end of their sample
+        render(&mut model, 1000);
+        assert!(model.voices.iter().all(|voice| !voice.playing));
+    }
+
+    #[test]
+    fn gated_loop_and_one_shot() {
+        let mut model = sampler_model();
+        model.set_parameter((SamplerModelParamID::Loop as i32, 1.));
+        model.set_parameter((SamplerModelParamID::LoopStart as i32, 0.5));
+        model.set_note(MidiMessage::NoteOn(72));
+        let output = render(&mut model, 1000);
+        //still looping on the second half of the ramp long after its end
+        assert!(model.voices[0].playing);
+        assert!(output[900..].iter().all(|sample| *sample >= 0.45 * model.volume));
+
+        //released, the envelope fades it out
+        model.set_note(MidiMessage::NoteOff(72));
+        render(&mut model, 48000);
+        assert!(!model.voices[0].playing);
+
+        //a one shot ignores the note off and the loop
+        model.set_parameter((SamplerModelParamID::OneShot as i32, 1.));
+        model.set_note(MidiMessage::NoteOn(72));
+        model.set_note(MidiMessage::NoteOff(72));
+        assert!(render(&mut model, 50).iter().any(|sample| *sample > 0.));
+        render(&mut model, 100);
+        assert!(!model.voices[0].playing);
+    }
+
+    #[test]
+    fn parse_zones() {
+        let path = std::env::temp_dir().join("laminaria_zone_test.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        writer.write_sample(1000_i16).unwrap();
+        writer.finalize().unwrap();
+        let path = path.to_string_lossy();
+
+        let zone = SampleZone::parse(&path).unwrap();
+        assert_eq!((zone.lowest_note, zone.highest_note, zone.root_note), (0, 127, 60));
+        let zone = SampleZone::parse(&format!("{path}@36")).unwrap();
+        assert_eq!((zone.lowest_note, zone.highest_note, zone.root_note), (36, 36, 36));
+        let zone = SampleZone::parse(&format!("{path}@72-48@60")).unwrap();
+        assert_eq!((zone.lowest_note, zone.highest_note, zone.root_note), (48, 72, 60));
+        assert!(SampleZone::parse(&format!("{path}@200")).is_err());
+        assert!(SampleZone::parse("missing.wav").is_err());
+        std::fs::remove_file(path.as_ref()).unwrap();
+
+        //a '@' in the path is not a note
+        let directory = std::env::temp_dir().join("laminaria@zones");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("zone@2.wav");
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        writer.write_sample(1000_i16).unwrap();
+        writer.finalize().unwrap();
+        let path = path.to_string_lossy();
+        let zone = SampleZone::parse(&path).unwrap();
+        assert_eq!((zone.lowest_note, zone.highest_note, zone.root_note), (0, 127, 60));
+        let zone = SampleZone::parse(&format!("{path}@72-48@60")).unwrap();
+        assert_eq!((zone.lowest_note, zone.highest_note, zone.root_note), (48, 72, 60));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+///Audio setup chosen by the user, remembered between runs.
+///`None` means the default of the host, device or config.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AudioSettings {
+    pub host: Option<String>,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+}
+
+impl AudioSettings {
+    ///`<config dir>/laminaria/audio.conf`, ex: `~/.config/laminaria/audio.conf` on linux
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("laminaria").join("audio.conf"))
+    }
+
+    ///missing or unreadable file give the defaults
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| Self::parse(&text))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("no config directory"))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    ///one `key=value` per line, unknown keys and invalid values are ignored
+    pub fn parse(text: &str) -> Self {
+        let mut settings = Self::default();
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match key.trim() {
+                "host" => settings.host = Some(value),
+                "device" => settings.device = Some(value),
+                "sample_rate" => settings.sample_rate = value.parse().ok(),
+                "buffer_size" => settings.buffer_size = value.parse().ok(),
+                _ => {}
+            }
+        }
+        settings
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        if let Some(host) = &self.host {
+            text += &format!("host={host}\n");
+        }
+        if let Some(device) = &self.device {
+            text += &format!("device={device}\n");
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            text += &format!("sample_rate={sample_rate}\n");
+        }
+        if let Some(buffer_size) = self.buffer_size {
+            text += &format!("buffer_size={buffer_size}\n");
+        }
+        text
+    }
+
+    ///values set in `other` replace ours, used to apply the command line over the saved file
+    pub fn merge(&mut self, other: AudioSettings) {
+        self.host = other.host.or(self.host.take());
+        self.device = other.device.or(self.device.take());
+        self.sample_rate = other.sample_rate.or(self.sample_rate);
+        self.buffer_size = other.buffer_size.or(self.buffer_size);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AudioSettings;
+
+    #[test]
+    fn round_trip() {
+        let settings = AudioSettings {
+            host: Some("JACK".to_string()),
+            device: Some("cpal_client_out".to_string()),
+            sample_rate: Some(48000),
+            buffer_size: None,
+        };
+        assert_eq!(AudioSettings::parse(&settings.to_text()), settings);
+
+        let mut saved = AudioSettings::parse("host=ALSA\nbuffer_size=256\nnonsense\n");
+        saved.merge(AudioSettings {
+            host: Some("JACK".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(saved.host.as_deref(), Some("JACK"));
+        assert_eq!(saved.buffer_size, Some(256));
+    }
+}
@@ -1,43 +1,93 @@
 use num_derive::FromPrimitive;
 
-use crate::{envelope::{self, Envelope}, midi::MidiMessage, midibuffer::PolyMidiBuffer, oscillator::SineWave, parameters::{ParameterCapsule, Parameters}, synth::{HasConstructor, HasEngine, HasMidiInput, HasParameters, Synth}, HarmonicOscillator, ParameterUpdate};
+use crate::{envelope::{self, Envelope}, midi::MidiMessage, midibuffer::{Voice, VoiceManager, VoiceMode, DEFAULT_NUMBER_OF_VOICES}, oscillator::SineWave, parameters::{ParameterCapsule, Parameters}, synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth}, ParameterUpdate};
 
-const NUMBER_OF_VOICES: usize = 4;
-const VOICE_ITERATOR: std::ops::Range<usize> = 0..NUMBER_OF_VOICES;
-
-const NB_SYNTH_PARAM: usize = 3;
+const NB_SYNTH_PARAM: usize = 5;
 
 #[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
 pub enum SineModelParamID {
     EnvelopeAttack,
     EnvelopeRelease,
+    Glide,
+    Legato,
     Volume,
 }
 
+#[derive(Clone, Copy)]
+struct SineVoice {
+    envelope: Envelope,
+    oscillator: SineWave,
+}
+
+impl Voice for SineVoice {
+    type Context = ();
+
+    fn start(&mut self, _midi_note: u8, retrigger: bool, _: &mut ()) {
+        if retrigger {
+            self.envelope.note_on();
+        }
+    }
+
+    fn release(&mut self, _: &()) {
+        self.envelope.note_off();
+    }
+
+    fn steal(&mut self, _: &()) {
+        self.envelope.steal();
+    }
+
+    fn level(&self, _: &()) -> f32 {
+        self.envelope.value()
+    }
+
+    fn is_silent(&self, _: &()) -> bool {
+        self.envelope.status == envelope::Segment::Off
+    }
+
+    fn set_frequency(&mut self, frequency_hz: f32) {
+        self.oscillator.frequency_hz = frequency_hz;
+    }
+}
+
 pub struct SineModel {
-    envelopes: [Envelope; NUMBER_OF_VOICES],
-    oscillators: [SineWave; NUMBER_OF_VOICES],
-    midibuffer: PolyMidiBuffer,
+    voices: VoiceManager<SineVoice>,
     //parameters
     volume: f32,
 }
 
-impl HasConstructor for SineModel {
-    // Instantiate the memory
-    fn new() -> Self {
+impl HasDescription for SineModel {
+    const NAME: &'static str = "Sine";
+    const DESCRIPTION: &'static str = "plain sine voices, a template for new models";
+}
+
+impl SineModel {
+    ///`voices` notes at once, `mode` chooses the voice a new note steals
+    pub fn with_voices(voices: usize, mode: VoiceMode) -> Self {
+        let voice = SineVoice {
+            envelope: Envelope::new(),
+            oscillator: SineWave::new(),
+        };
         SineModel {
-            envelopes: [Envelope::new(); NUMBER_OF_VOICES],
-            oscillators: [SineWave::new(); NUMBER_OF_VOICES],
-            midibuffer: PolyMidiBuffer::new(NUMBER_OF_VOICES),
+            voices: VoiceManager::new(vec![voice; voices.max(1)], mode),
             volume: 0.5,
             // parameters: Parameters {},
         }
     }
+}
+
+impl HasConstructor for SineModel {
+    // Instantiate the memory
+    fn new() -> Self {
+        Self::with_voices(DEFAULT_NUMBER_OF_VOICES, VoiceMode::Oldest)
+    }
 
     // Init engine with the sample rate in the audio thread
     fn init(&mut self, sample_rate: f32) {
-        self.envelopes.iter_mut().for_each(|x| x.init(sample_rate));
-        self.oscillators.iter_mut().for_each(|x| x.init(sample_rate));
+        self.voices.init(sample_rate);
+        for voice in self.voices.iter_mut() {
+            voice.envelope.init(sample_rate);
+            voice.oscillator.init(sample_rate);
+        }
     }
 }
 
@@ -67,6 +117,9 @@ impl HasParameters for SineModel {
                     envelope::MAXIMUM_ENVELOPE_TIME,
                     2.,
                 ),
+                //mono
+                P::new(ID::Glide as i32, "glide", 0, 'i', 0., 2000., 2.),
+                P::new(ID::Legato as i32, "legato", 0, 'e', 0., 1., 1.),
                 //global
                 P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
             ],
@@ -85,15 +138,17 @@ impl HasParameters for SineModel {
         let typed_id: SineModelParamID = num::FromPrimitive::from_i32(id).unwrap();
         match typed_id {
             ID::Volume => self.volume = new_value,
+            ID::Glide => self.voices.set_glide_time(new_value),
+            ID::Legato => self.voices.set_legato(new_value > 0.5),
             // envelope
             ID::EnvelopeAttack => self
-                .envelopes
+                .voices
                 .iter_mut()
-                .for_each(|env| env.set_attack(new_value)),
+                .for_each(|voice| voice.envelope.set_attack(new_value)),
             ID::EnvelopeRelease => self
-                .envelopes
+                .voices
                 .iter_mut()
-                .for_each(|env| env.set_release(new_value)),
+                .for_each(|voice| voice.envelope.set_release(new_value)),
         }
     }
 }
@@ -102,42 +157,121 @@ impl HasEngine for SineModel{
 
     // runned for every sample, do whatever you want here
     fn process(&mut self) -> f32 {
-        let mut sample: f32 = 0.;
-        for i in VOICE_ITERATOR {
-            match self.envelopes[i].status {
-                envelope::Segment::Off => {}
-                _ => sample += self.oscillators[i].process() * self.envelopes[i].process(),
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    // runned for every buffer, voices are computed one after the other on the whole block
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        left.fill(0.);
+        for i in 0..self.voices.len() {
+            for sample in left.iter_mut() {
+                self.voices.update(i, &mut ());
+                let voice = &mut self.voices[i];
+                if voice.envelope.status == envelope::Segment::Off {
+                    break;
+                }
+                *sample += voice.oscillator.process() * voice.envelope.process();
             }
         }
-        sample /= 4.;
-
-        //vca
-        sample *= self.volume;
-
-        return sample;
+        for sample in left.iter_mut() {
+            //vca
+            *sample *= self.volume / self.voices.len() as f32;
+        }
+        right.copy_from_slice(left);
     }
 }
 
 impl HasMidiInput for SineModel {
     fn set_note(&mut self, message: MidiMessage) {
         match message {
-            MidiMessage::NoteOff(midi_note) => self.midibuffer.remove_note(midi_note),
-            MidiMessage::NoteOn(midi_note) => self.midibuffer.add_note(midi_note),
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut ()),
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut ()),
             _ => {}
         }
-
-        for i in VOICE_ITERATOR {
-            match self.midibuffer.notes.get(i) {
-                None => self.envelopes[i].note_off(),
-                Some(midi_note) => {
-                    self.envelopes[i].note_on();
-                    self.oscillators[i].set_note(*midi_note)
-                }
-            }
-        }
     }
 }
 
 impl Synth for SineModel {}
 
 // trait Synth: HasParameters + HasEngine + HasMidiInput;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::with_defaults;
+    use crate::outils::midi_to_frequence;
+
+    #[test]
+    fn stolen_voice_fades_out() {
+        let mut model = with_defaults(SineModel::with_voices(1, VoiceMode::Oldest));
+        let (mut left, mut right) = (vec![0.; 4800], vec![0.; 4800]);
+        model.set_note(MidiMessage::NoteOn(60));
+        model.process_block(&mut left[..480], &mut right[..480]);
+
+        //the next note waits for the voice to be silent
+        model.set_note(MidiMessage::NoteOn(72));
+        assert_eq!(model.voices.pending_note(0), Some(72));
+        model.process_block(&mut left[..480], &mut right[..480]);
+        assert_eq!(model.voices.pending_note(0), None);
+        assert!(model.voices[0].envelope.status == envelope::Segment::Attack);
+        assert_eq!(model.voices.allocator().notes, vec![Some(72)]);
+
+        //it is released on its own voice
+        model.set_note(MidiMessage::NoteOff(60));
+        assert!(model.voices[0].envelope.status == envelope::Segment::Attack);
+        model.set_note(MidiMessage::NoteOff(72));
+        assert!(model.voices[0].envelope.status == envelope::Segment::Release);
+    }
+
+    #[test]
+    fn notes_keep_their_voice() {
+        let mut model = SineModel::new();
+        model.init(48000.);
+        for midi_note in [60, 64, 67] {
+            model.set_note(MidiMessage::NoteOn(midi_note));
+        }
+        let (mut left, mut right) = (vec![0.; 480], vec![0.; 480]);
+        model.process_block(&mut left, &mut right);
+
+        //the middle note releases on its own pitch, the others don't move
+        model.set_note(MidiMessage::NoteOff(64));
+        let frequencies: Vec<f32> = model.voices.iter().map(|voice| voice.oscillator.frequency_hz).collect();
+        assert_eq!(frequencies[..3], [60, 64, 67].map(midi_to_frequence));
+        assert!(model.voices[1].envelope.status == envelope::Segment::Release);
+        assert!(model.voices[2].envelope.status == envelope::Segment::Attack);
+
+        //a new note takes the silent voice, the release goes on
+        model.set_note(MidiMessage::NoteOn(72));
+        assert_eq!(model.voices.allocator().voice_of(72), Some(3));
+        assert!(model.voices[1].envelope.status == envelope::Segment::Release);
+    }
+
+    #[test]
+    fn mono_legato_glides() {
+        let mut model = SineModel::with_voices(4, VoiceMode::Mono);
+        model.init(48000.);
+        model.set_parameter((SineModelParamID::Glide as i32, 100.));
+        model.set_parameter((SineModelParamID::Legato as i32, 1.));
+        let (mut left, mut right) = (vec![0.; 2400], vec![0.; 2400]);
+        model.set_note(MidiMessage::NoteOn(48));
+        model.process_block(&mut left, &mut right);
+        assert_eq!(model.voices[0].oscillator.frequency_hz, midi_to_frequence(48));
+
+        //the second key slides the pitch without a new attack
+        model.set_note(MidiMessage::NoteOn(60));
+        model.process_block(&mut left, &mut right);
+        let frequency = model.voices[0].oscillator.frequency_hz;
+        assert!(frequency > midi_to_frequence(48) && frequency < midi_to_frequence(60));
+        assert!(model.voices[0].envelope.status == envelope::Segment::Sustain);
+        model.process_block(&mut left, &mut right);
+        assert_eq!(model.voices[0].oscillator.frequency_hz, midi_to_frequence(60));
+
+        //releasing it goes back to the first key, still held
+        model.set_note(MidiMessage::NoteOff(60));
+        assert!(model.voices[0].envelope.status == envelope::Segment::Sustain);
+        model.set_note(MidiMessage::NoteOff(48));
+        assert!(model.voices[0].envelope.status == envelope::Segment::Release);
+    }
+}
@@ -0,0 +1,596 @@
+use crate::envelope;
+use crate::envelope::Envelope;
+use crate::midi::MidiMessage;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode};
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::sample::Sample;
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth};
+use crate::HarmonicModel;
+use crate::ParameterUpdate;
+use num_derive::FromPrimitive;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::f32::consts::{PI, TAU};
+use std::sync::Arc;
+
+const NUMBER_OF_VOICES: usize = 4;
+const FFT_SIZE: usize = 2048;
+//four overlapping windows
+const HOP: usize = FFT_SIZE / 4;
+const BINS: usize = FFT_SIZE / 2 + 1;
+//in seconds, frames kept from the sample or the capture
+const BUFFER_TIME: f32 = 6.;
+//in seconds, length of the harmonic model output analysed when there is no sample
+const CAPTURE_TIME: f32 = 2.;
+//the note playing the frames at their recorded pitch
+const ROOT_NOTE: u8 = 60;
+
+const NB_SYNTH_PARAM: usize = 10;
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum SpectralModelParamID {
+    Capture,
+    Freeze,
+    Position,
+    Speed,
+    Pitch,
+    Shift,
+    Blur,
+    EnvelopeAttack,
+    EnvelopeRelease,
+    Volume,
+}
+
+///one short time fourier transform, the frequencies are in bins, measured from the phase advance
+struct Frame {
+    magnitudes: Vec<f32>,
+    frequencies: Vec<f32>,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Frame {
+            magnitudes: vec![0.; BINS],
+            frequencies: vec![0.; BINS],
+        }
+    }
+}
+
+fn wrap_phase(phase: f32) -> f32 {
+    phase - TAU * (phase / TAU).round()
+}
+
+fn hann_window() -> Vec<f32> {
+    (0..FFT_SIZE)
+        .map(|i| 0.5 - 0.5 * (TAU * i as f32 / FFT_SIZE as f32).cos())
+        .collect()
+}
+
+///Analyse windows HOP samples apart, the phase of the previous one gives the exact frequency of each bin
+struct Analyser {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    previous_phases: Vec<f32>,
+}
+
+impl Analyser {
+    fn new(planner: &mut FftPlanner<f32>) -> Self {
+        let fft = planner.plan_fft_forward(FFT_SIZE);
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        Analyser {
+            fft,
+            window: hann_window(),
+            spectrum: vec![Complex::default(); FFT_SIZE],
+            scratch,
+            previous_phases: vec![0.; BINS],
+        }
+    }
+
+    ///`input` gives the FFT_SIZE samples of the window, no allocation
+    fn analyse(&mut self, input: impl Iterator<Item = f32>, frame: &mut Frame) {
+        for ((bin, sample), window) in self.spectrum.iter_mut().zip(input).zip(self.window.iter()) {
+            *bin = Complex::new(sample * window, 0.);
+        }
+        self.fft.process_with_scratch(&mut self.spectrum, &mut self.scratch);
+        for (k, bin) in self.spectrum[..BINS].iter().enumerate() {
+            let (magnitude, phase) = bin.to_polar();
+            //phase turned by the bin center frequency during a hop
+            let expected = TAU * k as f32 * HOP as f32 / FFT_SIZE as f32;
+            let deviation = wrap_phase(phase - self.previous_phases[k] - expected);
+            self.previous_phases[k] = phase;
+            frame.magnitudes[k] = magnitude;
+            frame.frequencies[k] = k as f32 + deviation * FFT_SIZE as f32 / (TAU * HOP as f32);
+        }
+    }
+}
+
+///a note, the phases of its bins run at the pitched frequencies
+struct SpectralVoice {
+    note: Option<u8>,
+    phases: Vec<f32>,
+    envelope: Envelope,
+}
+
+impl SpectralVoice {
+    fn new(_index: usize) -> Self {
+        SpectralVoice {
+            note: None,
+            phases: vec![0.; BINS],
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn note_on(&mut self, midi_note: u8) {
+        //neighbour bins of a hann window have opposite signs, it keeps the partials in one piece
+        if self.envelope.status == envelope::Segment::Off {
+            for (j, phase) in self.phases.iter_mut().enumerate() {
+                *phase = PI * (j % 2) as f32;
+            }
+        }
+        self.note = Some(midi_note);
+        self.envelope.note_on();
+    }
+}
+
+impl Voice for SpectralVoice {
+    type Context = ();
+
+    fn start(&mut self, midi_note: u8, _retrigger: bool, _: &mut ()) {
+        self.note_on(midi_note)
+    }
+
+    fn release(&mut self, _: &()) {
+        self.envelope.note_off()
+    }
+
+    fn steal(&mut self, _: &()) {
+        self.envelope.steal()
+    }
+
+    fn level(&self, _: &()) -> f32 {
+        self.envelope.value()
+    }
+
+    fn is_silent(&self, _: &()) -> bool {
+        self.envelope.status == envelope::Segment::Off
+    }
+}
+
+pub struct SpectralModel {
+    sample: Option<Arc<Sample>>,
+    sample_rate: f32,
+    //ring of analysed frames, `recorded` of them ending before `write_frame`
+    frames: Vec<Frame>,
+    write_frame: usize,
+    recorded: usize,
+    //in frames from the oldest one
+    playhead: f32,
+    //analysis of the model's own output while capturing
+    analyser: Option<Analyser>,
+    capture_ring: Vec<f32>,
+    capture_index: usize,
+    //synthesis
+    inverse_fft: Option<Arc<dyn Fft<f32>>>,
+    window: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    blurred: Vec<f32>,
+    //a voice's bins moved to their pitched place
+    pitched_magnitudes: Vec<f32>,
+    pitched_frequencies: Vec<f32>,
+    overlap: Vec<f32>,
+    ready: Vec<f32>,
+    ready_index: usize,
+    //one over the sum of the sounding envelopes, the captured output stays at the level of one voice
+    capture_gain: f32,
+    voices: VoiceManager<SpectralVoice>,
+    //parameters
+    capturing: bool,
+    freeze: bool,
+    position: f32,
+    speed: f32,
+    pitch: f32,
+    //in hertz
+    shift: f32,
+    blur: f32,
+    volume: f32,
+}
+
+impl SpectralModel {
+    ///frames of `sample` instead of the output of the harmonic model
+    pub fn with_sample(sample: Arc<Sample>) -> Self {
+        let mut model = Self::new();
+        model.sample = Some(sample);
+        model
+    }
+
+    fn frame_index(&self, position: usize) -> usize {
+        let capacity = self.frames.len();
+        (self.write_frame + capacity - self.recorded + position) % capacity
+    }
+
+    ///analyse the capture ring in the next frame, the oldest one is replaced once the ring is full
+    fn capture_frame(&mut self) {
+        let Some(analyser) = self.analyser.as_mut() else {
+            return;
+        };
+        let (newest, oldest) = self.capture_ring.split_at(self.capture_index);
+        analyser.analyse(
+            oldest.iter().chain(newest.iter()).copied(),
+            &mut self.frames[self.write_frame],
+        );
+        self.write_frame = (self.write_frame + 1) % self.frames.len();
+        if self.recorded == self.frames.len() {
+            //the playhead stays on the same frame
+            self.playhead = (self.playhead - 1.).max(0.);
+        } else {
+            self.recorded += 1;
+        }
+    }
+
+    ///next HOP samples : the frame under the playhead is pitched for each voice, then overlap added
+    fn synthesize(&mut self) {
+        if self.capturing {
+            self.capture_frame();
+        }
+        self.spectrum.fill(Complex::default());
+        if self.recorded > 0 {
+            let index = self.playhead.floor() as usize % self.recorded;
+            let fraction = self.playhead.fract();
+            let current = &self.frames[self.frame_index(index)];
+            let next = &self.frames[self.frame_index((index + 1) % self.recorded)];
+            for ((blurred, current), next) in self
+                .blurred
+                .iter_mut()
+                .zip(current.magnitudes.iter())
+                .zip(next.magnitudes.iter())
+            {
+                let magnitude = current * (1. - fraction) + next * fraction;
+                *blurred = *blurred * self.blur + magnitude * (1. - self.blur);
+            }
+
+            let shift = self.shift * FFT_SIZE as f32 / self.sample_rate;
+            let mut gains = 0.;
+            for i in 0..self.voices.len() {
+                //a stolen voice starts its pending note once faded
+                self.voices.update(i, &mut ());
+                let voice = &mut self.voices[i];
+                //the envelope moves a hop at a time
+                let mut gain = 0.;
+                for _ in 0..HOP {
+                    gain = voice.envelope.process();
+                }
+                let (Some(midi_note), false) = (voice.note, voice.envelope.status == envelope::Segment::Off) else {
+                    continue;
+                };
+                gains += gain;
+                let ratio = 2_f32.powf((midi_note as f32 - ROOT_NOTE as f32 + self.pitch) / 12.);
+                self.pitched_magnitudes.fill(0.);
+                for (k, (magnitude, frequency)) in
+                    self.blurred.iter().zip(current.frequencies.iter()).enumerate()
+                {
+                    let j = (k as f32 * ratio + shift).round();
+                    if j < 1. || j >= (BINS - 1) as f32 {
+                        continue;
+                    }
+                    self.pitched_magnitudes[j as usize] += magnitude;
+                    self.pitched_frequencies[j as usize] = frequency * ratio + shift;
+                }
+                for (j, bin) in self.spectrum[..BINS].iter_mut().enumerate() {
+                    if self.pitched_magnitudes[j] == 0. {
+                        continue;
+                    }
+                    let advance = TAU * self.pitched_frequencies[j] * HOP as f32 / FFT_SIZE as f32;
+                    voice.phases[j] = wrap_phase(voice.phases[j] + advance);
+                    *bin += Complex::from_polar(self.pitched_magnitudes[j] * gain, voice.phases[j]);
+                }
+            }
+            //real output, the negative frequencies mirror the positive ones
+            for j in 1..BINS - 1 {
+                self.spectrum[FFT_SIZE - j] = self.spectrum[j].conj();
+            }
+            if let Some(inverse_fft) = &self.inverse_fft {
+                inverse_fft.process_with_scratch(&mut self.spectrum, &mut self.scratch);
+            }
+            self.capture_gain = 1. / gains.max(1.);
+            if !self.freeze {
+                self.playhead = (self.playhead + self.speed) % self.recorded as f32;
+            }
+        }
+
+        //hann windows twice, four times overlapped, add up to 1.5
+        let normalization = 1. / (FFT_SIZE as f32 * 1.5);
+        for ((overlap, bin), window) in self
+            .overlap
+            .iter_mut()
+            .zip(self.spectrum.iter())
+            .zip(self.window.iter())
+        {
+            *overlap += bin.re * window * normalization;
+        }
+        self.ready.copy_from_slice(&self.overlap[..HOP]);
+        self.overlap.copy_within(HOP.., 0);
+        self.overlap[FFT_SIZE - HOP..].fill(0.);
+        self.ready_index = 0;
+    }
+}
+
+impl HasDescription for SpectralModel {
+    const NAME: &'static str = "Spectral";
+    const DESCRIPTION: &'static str = "phase vocoder on a sample or the synth's own sound, pitch, time, freeze and blur";
+}
+
+impl HasConstructor for SpectralModel {
+    fn new() -> Self {
+        SpectralModel {
+            sample: None,
+            sample_rate: 0.,
+            frames: vec![],
+            write_frame: 0,
+            recorded: 0,
+            playhead: 0.,
+            analyser: None,
+            capture_ring: vec![0.; FFT_SIZE],
+            capture_index: 0,
+            inverse_fft: None,
+            window: hann_window(),
+            spectrum: vec![Complex::default(); FFT_SIZE],
+            scratch: vec![],
+            blurred: vec![0.; BINS],
+            pitched_magnitudes: vec![0.; BINS],
+            pitched_frequencies: vec![0.; BINS],
+            overlap: vec![0.; FFT_SIZE],
+            ready: vec![0.; HOP],
+            ready_index: HOP,
+            capture_gain: 1.,
+            voices: VoiceManager::new((0..NUMBER_OF_VOICES).map(SpectralVoice::new).collect(), VoiceMode::Oldest),
+            capturing: false,
+            freeze: false,
+            position: 0.,
+            speed: 1.,
+            pitch: 0.,
+            shift: 0.,
+            blur: 0.,
+            volume: 0.5,
+        }
+    }
+
+    ///the source is analysed here, outside of the audio thread
+    fn init(&mut self, sample_rate: f32) {
+        //init again, the frames are analysed from the start
+        self.write_frame = 0;
+        self.recorded = 0;
+        self.playhead = 0.;
+        self.sample_rate = sample_rate;
+        self.voices.init(sample_rate);
+        self.voices.iter_mut().for_each(|voice| voice.envelope.init(sample_rate));
+        let mut planner = FftPlanner::new();
+        let inverse_fft = planner.plan_fft_inverse(FFT_SIZE);
+        self.scratch = vec![Complex::default(); inverse_fft.get_inplace_scratch_len()];
+        self.inverse_fft = Some(inverse_fft);
+
+        let capacity = (BUFFER_TIME * sample_rate / HOP as f32) as usize;
+        self.frames = (0..capacity).map(|_| Frame::new()).collect();
+        let source = match &self.sample {
+            Some(sample) => sample.resample(sample_rate),
+            None => HarmonicModel::render_chord(sample_rate, CAPTURE_TIME),
+        };
+        let mut analyser = Analyser::new(&mut planner);
+        for (i, window) in source.windows(FFT_SIZE).step_by(HOP).take(capacity + 1).enumerate() {
+            analyser.analyse(window.iter().copied(), &mut self.frames[self.write_frame]);
+            //the first window has no previous phases, it is overwritten
+            if i > 0 {
+                self.write_frame = (self.write_frame + 1) % capacity;
+                self.recorded += 1;
+            }
+        }
+        self.analyser = Some(analyser);
+    }
+}
+
+impl HasMidiInput for SpectralModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut ()),
+            MidiMessage::NoteOn(midi_note) => {
+                //from silence, the playhead goes back to the position
+                if self.voices.iter().all(|voice| voice.envelope.status == envelope::Segment::Off) {
+                    self.playhead = self.position * self.recorded.saturating_sub(1) as f32;
+                }
+                self.voices.note_on(midi_note, &mut ())
+            }
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for SpectralModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for sample in left.iter_mut() {
+            if self.ready_index == HOP {
+                self.synthesize();
+            }
+            let output = self.ready[self.ready_index];
+            self.ready_index += 1;
+            //the output feeds the frames back, like the frozen delay.
+            //Soft clipped too, the bins of a pitch down add up and would grow at each pass
+            if self.capturing {
+                self.capture_ring[self.capture_index] = (output * self.capture_gain).tanh();
+                self.capture_index = (self.capture_index + 1) % FFT_SIZE;
+            }
+            *sample = output * self.volume;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for SpectralModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = SpectralModelParamID;
+        type P = ParameterCapsule;
+
+        let params = Parameters {
+            capsules: vec![
+                //source
+                P::new(ID::Capture as i32, "capture", 0, 'c', 0., 1., 1.),
+                P::new(ID::Freeze as i32, "freeze", 0, 'f', 0., 1., 1.),
+                //playback
+                P::new(ID::Position as i32, "position", 0, 'p', 0., 1., 1.),
+                P::new(ID::Speed as i32, "speed", 10, 's', 0., 3.5, 1.),
+                //semitones added to the note
+                P::new(ID::Pitch as i32, "pitch", 17, 't', -17., 18., 1.),
+                //in hertz, moves all the partials by the same amount
+                P::new(ID::Shift as i32, "shift", 17, 'h', -340., 360., 1.),
+                P::new(ID::Blur as i32, "blur", 0, 'b', 0., 0.99, 0.5),
+                //envelope
+                P::new(
+                    ID::EnvelopeAttack as i32,
+                    "env-atk",
+                    3,
+                    'a',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    ID::EnvelopeRelease as i32,
+                    "env-rel",
+                    8,
+                    'd',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = SpectralModelParamID;
+        let typed_id: SpectralModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Capture => self.capturing = new_value > 0.5,
+            ID::Freeze => self.freeze = new_value > 0.5,
+            ID::Position => self.position = new_value,
+            ID::Speed => self.speed = new_value,
+            ID::Pitch => self.pitch = new_value.round(),
+            ID::Shift => self.shift = new_value,
+            ID::Blur => self.blur = new_value,
+            //envelope
+            ID::EnvelopeAttack => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_attack(new_value)),
+            ID::EnvelopeRelease => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_release(new_value)),
+        }
+    }
+}
+
+impl Synth for SpectralModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{peak, render, with_defaults};
+
+    //two seconds of a 375Hz sine, right on the 16th bin
+    fn sine_model() -> SpectralModel {
+        let samples = (0..96000)
+            .map(|i| (TAU * 375. * i as f32 / 48000.).sin() * 0.5)
+            .collect();
+        let mut model = with_defaults(SpectralModel::with_sample(Arc::new(Sample::from_samples("sine", 48000., samples))));
+        model.set_parameter((SpectralModelParamID::Volume as i32, 1.));
+        model
+    }
+
+    //in hertz, from the rising zero crossings
+    fn frequency(samples: &[f32]) -> f32 {
+        let crossings = samples.windows(2).filter(|pair| pair[0] < 0. && pair[1] >= 0.).count();
+        crossings as f32 * 48000. / samples.len() as f32
+    }
+
+    #[test]
+    fn resynthesis_follows_the_notes() {
+        let mut model = sine_model();
+        model.set_note(MidiMessage::NoteOn(ROOT_NOTE));
+        render(&mut model, 9600);
+        let root = render(&mut model, 24000);
+        assert!((frequency(&root) - 375.).abs() < 5., "{}", frequency(&root));
+        let peak = peak(&root);
+        assert!(peak > 0.3 && peak < 0.7, "{}", peak);
+
+        //an octave up, at the same speed
+        model.set_note(MidiMessage::NoteOff(ROOT_NOTE));
+        model.set_note(MidiMessage::NoteOn(ROOT_NOTE + 12));
+        render(&mut model, 9600);
+        let octave = render(&mut model, 24000);
+        assert!((frequency(&octave) - 750.).abs() < 10., "{}", frequency(&octave));
+
+        //shifted by 100Hz
+        model.set_parameter((SpectralModelParamID::Shift as i32, 100.));
+        render(&mut model, 9600);
+        assert!((frequency(&render(&mut model, 24000)) - 850.).abs() < 10.);
+    }
+
+    #[test]
+    fn freeze_holds_the_playhead() {
+        let mut model = sine_model();
+        model.set_parameter((SpectralModelParamID::Freeze as i32, 1.));
+        model.set_parameter((SpectralModelParamID::Position as i32, 0.5));
+        model.set_note(MidiMessage::NoteOn(ROOT_NOTE));
+        let playhead = model.playhead;
+        render(&mut model, 48000);
+        assert_eq!(model.playhead, playhead);
+
+        //the own output replaces the sine once captured
+        model.set_parameter((SpectralModelParamID::Capture as i32, 1.));
+        let recorded = model.recorded;
+        render(&mut model, HOP * 10);
+        assert_eq!(model.recorded, recorded + 10);
+    }
+
+    #[test]
+    fn capture_stays_bounded() {
+        let mut model = sine_model();
+        model.set_parameter((SpectralModelParamID::Capture as i32, 1.));
+        for midi_note in [ROOT_NOTE - 12, ROOT_NOTE, ROOT_NOTE + 4, ROOT_NOTE + 7] {
+            model.set_note(MidiMessage::NoteOn(midi_note));
+        }
+        //the frames are played and captured again several times
+        for _ in 0..3 * model.frames.len() {
+            let output = render(&mut model, HOP);
+            assert!(output.iter().all(|sample| sample.is_finite() && sample.abs() < 4.));
+        }
+    }
+
+    #[test]
+    fn init_twice() {
+        //longer than the ring, it fills it
+        let samples = (0..48000 * 7)
+            .map(|i| (TAU * 375. * i as f32 / 48000.).sin() * 0.5)
+            .collect();
+        let mut model = with_defaults(SpectralModel::with_sample(Arc::new(Sample::from_samples("sine", 48000., samples))));
+        model.init(48000.);
+        assert_eq!(model.recorded, model.frames.len());
+        model.set_note(MidiMessage::NoteOn(ROOT_NOTE));
+        assert!(render(&mut model, HOP * 4).iter().all(|sample| sample.is_finite()));
+    }
+}
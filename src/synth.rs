@@ -1,24 +1,50 @@
-use crate::{midi::MidiMessage, parameters::Parameters, ParameterUpdate};
+use crate::{midi::MidiMessage, parameters::Parameters, textparsing::PhonemeSequence, ParameterUpdate};
 
+///biggest block the audio thread and the render ask for, models can size their scratch memory with it
+pub const MAXIMUM_BLOCK_SIZE: usize = 512;
+
+///Parameters shown in the UI and set from it or from the midi CC
 pub trait HasParameters{
     fn get_parameters(&self) -> Parameters;
     fn set_parameter(&mut self, (id, new_value): ParameterUpdate){}
 }
 
+///The audio output
 pub trait HasEngine{
     fn process(&mut self) -> f32;
+    ///left and right sample, mono models get the same sample on both sides
+    fn process_stereo(&mut self) -> (f32, f32) {
+        let sample = self.process();
+        (sample, sample)
+    }
+    ///fill both buffers (same length, at most MAXIMUM_BLOCK_SIZE), default to one process_stereo call per sample
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (left_sample, right_sample) in left.iter_mut().zip(right.iter_mut()) {
+            (*left_sample, *right_sample) = self.process_stereo();
+        }
+    }
 }
 
+///Notes from the midi input
 pub trait HasMidiInput{
     fn set_note(&mut self, message: MidiMessage);
 }
 
 
+///`new` allocate the memory, `init` is called once the sample rate is known
 pub trait HasConstructor{
     fn new() -> Self where Self:Sized;
     fn init(&mut self, sample_rate:f32);
 }
 
+///A model playable by the audio engine
 pub trait Synth: HasParameters + HasEngine + HasMidiInput + HasConstructor + Send {
+    ///text typed in the UI or sent by ORCA, only the models singing it read it
+    fn set_text(&mut self, _phonemes: &PhonemeSequence) {}
+}
 
-}
\ No newline at end of file
+///name and one line description, used by the model registry
+pub trait HasDescription{
+    const NAME: &'static str;
+    const DESCRIPTION: &'static str;
+}
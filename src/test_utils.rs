@@ -0,0 +1,33 @@
+//fixtures shared by the tests of the models
+use crate::synth::Synth;
+
+///initialised at 48kHz, every parameter to its default
+pub fn with_defaults<M: Synth>(mut model: M) -> M {
+    model.init(48000.);
+    for capsule in model.get_parameters().capsules.iter() {
+        model.set_parameter((capsule.id, capsule.parameter.get_raw_value()));
+    }
+    model
+}
+
+pub fn model<M: Synth>() -> M {
+    with_defaults(M::new())
+}
+
+///`length` samples of both channels, in blocks of 256
+pub fn render_stereo<M: Synth>(model: &mut M, length: usize) -> (Vec<f32>, Vec<f32>) {
+    let mut left = vec![0.; length];
+    let mut right = vec![0.; length];
+    for (left, right) in left.chunks_mut(256).zip(right.chunks_mut(256)) {
+        model.process_block(left, right);
+    }
+    (left, right)
+}
+
+pub fn render<M: Synth>(model: &mut M, length: usize) -> Vec<f32> {
+    render_stereo(model, length).0
+}
+
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0., |peak: f32, sample| peak.max(sample.abs()))
+}
@@ -34,3 +34,194 @@ pub fn parse_text(string: &str) -> TextCharacteristic {
         - textcarac.number_of_vowel;
     return textcarac;
 }
+
+///Sounds of a text, vowels are held, consonants are short, pauses are the spaces and punctuation
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum Phoneme {
+    A,
+    E,
+    I,
+    O,
+    U,
+    P,
+    B,
+    T,
+    D,
+    K,
+    G,
+    F,
+    V,
+    S,
+    Z,
+    Sh,
+    Th,
+    H,
+    M,
+    N,
+    L,
+    R,
+    W,
+    Y,
+    Pause,
+}
+
+impl Phoneme {
+    pub fn is_vowel(self) -> bool {
+        matches!(self, Phoneme::A | Phoneme::E | Phoneme::I | Phoneme::O | Phoneme::U)
+    }
+
+    pub fn is_consonant(self) -> bool {
+        !self.is_vowel() && self != Phoneme::Pause
+    }
+}
+
+pub const MAXIMUM_PHONEMES: usize = 64;
+
+///Phonemes in a fixed array, so they can be sent to the audio thread without allocation
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct PhonemeSequence {
+    phonemes: [Phoneme; MAXIMUM_PHONEMES],
+    len: usize,
+}
+
+impl Default for PhonemeSequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PhonemeSequence {
+    pub fn new() -> Self {
+        PhonemeSequence {
+            phonemes: [Phoneme::Pause; MAXIMUM_PHONEMES],
+            len: 0,
+        }
+    }
+
+    ///false once the sequence is full
+    pub fn push(&mut self, phoneme: Phoneme) -> bool {
+        if self.len == MAXIMUM_PHONEMES {
+            return false;
+        }
+        self.phonemes[self.len] = phoneme;
+        self.len += 1;
+        true
+    }
+
+    pub fn as_slice(&self) -> &[Phoneme] {
+        &self.phonemes[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+fn is_vowel_letter(letter: Option<&char>) -> bool {
+    matches!(letter, Some('a' | 'e' | 'i' | 'o' | 'u' | 'y'))
+}
+
+///Read a text or an ORCA word as phonemes, roughly the latin way : ch sh th ph ck qu and ee oo ou ai au
+///are one sound, doubled letters are read once, spaces and punctuation are pauses, the rest is ignored.
+///Text longer than MAXIMUM_PHONEMES is cut.
+pub fn text_to_phonemes(text: &str) -> PhonemeSequence {
+    type Ph = Phoneme;
+    let letters: Vec<char> = text.to_lowercase().chars().collect();
+    let mut sequence = PhonemeSequence::new();
+    let mut previous: Option<Phoneme> = None;
+    let mut i = 0;
+    'letters: while i < letters.len() {
+        let next = letters.get(i + 1);
+        let (phonemes, length): (&[Phoneme], usize) = match (letters[i], next) {
+            ('c' | 's', Some('h')) => (&[Ph::Sh], 2),
+            ('t', Some('h')) => (&[Ph::Th], 2),
+            ('p', Some('h')) => (&[Ph::F], 2),
+            ('c', Some('k')) => (&[Ph::K], 2),
+            ('q', Some('u')) => (&[Ph::K, Ph::W], 2),
+            ('e', Some('e')) => (&[Ph::I], 2),
+            ('o', Some('o' | 'u')) => (&[Ph::U], 2),
+            ('a', Some('i')) => (&[Ph::E], 2),
+            ('a', Some('u')) => (&[Ph::O], 2),
+            ('a', _) => (&[Ph::A], 1),
+            ('e', _) => (&[Ph::E], 1),
+            ('i', _) => (&[Ph::I], 1),
+            ('o', _) => (&[Ph::O], 1),
+            ('u', _) => (&[Ph::U], 1),
+            //a consonant before a vowel, a vowel elsewhere
+            ('y', next) if is_vowel_letter(next) => (&[Ph::Y], 1),
+            ('y', _) => (&[Ph::I], 1),
+            //soft before e, i and y
+            ('c', Some('e' | 'i' | 'y')) => (&[Ph::S], 1),
+            ('c' | 'k' | 'q', _) => (&[Ph::K], 1),
+            ('x', _) => (&[Ph::K, Ph::S], 1),
+            ('j', _) => (&[Ph::Y], 1),
+            ('p', _) => (&[Ph::P], 1),
+            ('b', _) => (&[Ph::B], 1),
+            ('t', _) => (&[Ph::T], 1),
+            ('d', _) => (&[Ph::D], 1),
+            ('g', _) => (&[Ph::G], 1),
+            ('f', _) => (&[Ph::F], 1),
+            ('v', _) => (&[Ph::V], 1),
+            ('s', _) => (&[Ph::S], 1),
+            ('z', _) => (&[Ph::Z], 1),
+            ('h', _) => (&[Ph::H], 1),
+            ('m', _) => (&[Ph::M], 1),
+            ('n', _) => (&[Ph::N], 1),
+            ('l', _) => (&[Ph::L], 1),
+            ('r', _) => (&[Ph::R], 1),
+            ('w', _) => (&[Ph::W], 1),
+            (letter, _) if letter.is_whitespace() || letter.is_ascii_punctuation() => {
+                (&[Ph::Pause], 1)
+            }
+            _ => (&[], 1),
+        };
+        for phoneme in phonemes {
+            //no pause at the start, and doubled sounds are read once
+            let skipped = match previous {
+                None => *phoneme == Ph::Pause,
+                Some(previous) => previous == *phoneme,
+            };
+            if !skipped {
+                if !sequence.push(*phoneme) {
+                    break 'letters;
+                }
+                previous = Some(*phoneme);
+            }
+        }
+        i += length;
+    }
+    //no pause at the end either
+    if previous == Some(Ph::Pause) {
+        sequence.len -= 1;
+    }
+    sequence
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_phonemes() {
+        use Phoneme::*;
+        assert_eq!(
+            text_to_phonemes("Laminaria").as_slice(),
+            &[L, A, M, I, N, A, R, I, A]
+        );
+        assert_eq!(
+            text_to_phonemes("  the queen, shall sing! ").as_slice(),
+            &[Th, E, Pause, K, W, I, N, Pause, Sh, A, L, Pause, S, I, N, G]
+        );
+        assert_eq!(text_to_phonemes("yes 42 city").as_slice(), &[Y, E, S, Pause, S, I, T, I]);
+        assert_eq!(text_to_phonemes(&"la".repeat(100)).len(), MAXIMUM_PHONEMES);
+        //cut on a pause, it is trimmed like any ending pause
+        let cut = text_to_phonemes(&format!("{}m {}", "la".repeat(31), "la".repeat(10)));
+        assert_eq!(cut.len(), MAXIMUM_PHONEMES - 1);
+        assert_eq!(cut.as_slice().last(), Some(&M));
+        assert!(text_to_phonemes("...").is_empty());
+    }
+}
@@ -0,0 +1,20 @@
+use std::net::UdpSocket;
+use std::sync::mpsc::Sender;
+
+///ORCA `;` operator sends its text in udp packets, they are forwarded to the keyboard loop
+///which parses them and sends the phonemes to the audio thread
+pub fn listen_udp(port: u16, text_sender: Sender<String>) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(("127.0.0.1", port))?;
+    std::thread::Builder::new()
+        .name("UDP".to_string())
+        .spawn(move || {
+            let mut buffer = [0; 1024];
+            while let Ok(size) = socket.recv(&mut buffer) {
+                let text = String::from_utf8_lossy(&buffer[..size]).to_string();
+                if text_sender.send(text).is_err() {
+                    break;
+                }
+            }
+        })?;
+    Ok(())
+}
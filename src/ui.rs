@@ -1,6 +1,7 @@
-use crate::outils::get_orca_character;
-use crate::parameters::{Parameter, Parameters};
-use crate::ParameterUpdate;
+use laminaria::outils::{self, get_orca_character};
+use laminaria::parameters::{Parameter, Parameters};
+use laminaria::engine::EngineMessage;
+use laminaria::model_switch::ModelSwitcher;
 use crossterm::execute;
 use crossterm::{
     cursor, event, event::Event, event::KeyCode, event::KeyEvent, event::KeyEventKind,
@@ -9,9 +10,15 @@ use crossterm::{
 };
 use std::io::ErrorKind;
 use std::io::Result;
-use std::sync::{mpsc::Receiver, mpsc::Sender, Arc, Mutex};
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{mpsc::channel, mpsc::Receiver, mpsc::Sender, Arc, Mutex};
+use std::time::Duration;
 // pub type UiEvent = Option<i32>;
-use crate::midi::{connect_midi, MidiMessage};
+use crate::midi_input::connect_midi;
+use laminaria::midi::{cc_value_to_orca, TimedMidiMessage};
+use laminaria::queue::QueueSender;
+use laminaria::text_to_phonemes;
 
 ///Event sent by the keyboard loop and midi callback to update et refresh the UI
 pub enum UiEvent {
@@ -20,6 +27,12 @@ pub enum UiEvent {
     UpdateMidiportName(String),
     UpdateMidiChannel(u8),
     ToggleRawCCMode,
+    ///cc index and midi value, the midi callback leave the parameters update to the UI
+    ControlChange(u8, u8),
+    UpdateAudioSetup(String),
+    UpdateModelName(String),
+    ///text being typed, None once it is sent
+    UpdateText(Option<String>),
 }
 
 //little enum that allow me to simplify the key_code match by deferring all the mutex work to a more convenient and centralized place
@@ -32,20 +45,22 @@ enum ParameterModified {
 
 pub fn keyboard_input(
     parameters: Arc<Mutex<Parameters>>,
-    param_sender: Sender<ParameterUpdate>,
+    mut param_sender: QueueSender<EngineMessage>,
     gui_sender: Sender<UiEvent>,
-    midi_sender: Sender<MidiMessage>,
+    midi_sender: QueueSender<TimedMidiMessage>,
     midi_channel: u8,
-    number_of_params: usize,
+    mut model_switcher: ModelSwitcher,
+    text_receiver: Receiver<String>,
 ) -> Result<()> {
     let mut selected: i32 = 0;
-    let midi_channel = Arc::new(Mutex::new(midi_channel));
+    let midi_channel = Arc::new(AtomicU8::new(midi_channel));
+    //program changes are received by the midi callback, but models are switched here
+    let (program_sender, program_receiver): (Sender<u8>, Receiver<u8>) = channel();
     // need to get the midi as a variable to keep it in scope
     let mut _midi_connection = match connect_midi(
-        midi_sender.clone(),
-        parameters.clone(),
-        param_sender.clone(),
+        midi_sender,
         gui_sender.clone(),
+        program_sender.clone(),
         midi_channel.clone(),
     ) {
         Ok((midi_connection, port_name)) => {
@@ -61,12 +76,32 @@ pub fn keyboard_input(
         .send(UiEvent::Refresh)
         .map_err(|_err| std::io::Error::new(ErrorKind::Other, "no gui receiver"))?;
     gui_sender
-        .send(UiEvent::UpdateMidiChannel(*midi_channel.lock().unwrap()))
+        .send(UiEvent::UpdateMidiChannel(midi_channel.load(Ordering::Relaxed)))
         .map_err(|_err| std::io::Error::new(ErrorKind::Other, "no gui receiver"))?;
 
     let mut parameters_modified: Option<ParameterModified>;
     let mut ui_event = UiEvent::Refresh;
+    //Some while a text is typed, enter opens and sends it
+    let mut text_entry: Option<String> = None;
     loop {
+        while let Ok(program) = program_receiver.try_recv() {
+            switch_model(
+                program as usize,
+                &mut model_switcher,
+                &mut param_sender,
+                &parameters,
+                &gui_sender,
+                &mut selected,
+            );
+        }
+        while let Ok(text) = text_receiver.try_recv() {
+            let _ = param_sender.send(EngineMessage::Text(text_to_phonemes(&text)));
+        }
+        model_switcher.drop_retired();
+        //wait for a key a little time only, to check the program changes and the texts regularly
+        if !event::poll(Duration::from_millis(20)).unwrap_or(false) {
+            continue;
+        }
         if let Event::Key(KeyEvent { code, kind, .. }) =
             event::read().unwrap_or(Event::Key(KeyEvent::new(KeyCode::Down, KeyModifiers::NONE)))
         {
@@ -74,6 +109,24 @@ pub fn keyboard_input(
                 //got to refresh at the end, will be modified if the event involve more modification
                 ui_event = UiEvent::Refresh;
                 parameters_modified = None;
+                if let Some(text) = text_entry.as_mut() {
+                    match code {
+                        KeyCode::Enter => {
+                            let _ = param_sender.send(EngineMessage::Text(text_to_phonemes(text)));
+                            text_entry = None;
+                        }
+                        KeyCode::Esc => text_entry = None,
+                        KeyCode::Backspace => {
+                            text.pop();
+                        }
+                        KeyCode::Char(char) => text.push(char),
+                        _ => {}
+                    }
+                    gui_sender
+                        .send(UiEvent::UpdateText(text_entry.clone()))
+                        .map_err(|_err| std::io::Error::new(ErrorKind::Other, "no gui receiver"))?;
+                    continue;
+                }
                 match code {
                     KeyCode::Esc => {
                         disable_raw_mode().unwrap();
@@ -83,6 +136,7 @@ pub fn keyboard_input(
                         break;
                     }
                     KeyCode::Down => {
+                        let number_of_params = parameters.lock().unwrap().nb_param;
                         if selected < number_of_params as i32 - 1 {
                             selected += 1;
                         }
@@ -94,44 +148,57 @@ pub fn keyboard_input(
                         }
                         ui_event = UiEvent::UpdateSelection(selected);
                     }
+                    KeyCode::Enter => {
+                        text_entry = Some(String::new());
+                        ui_event = UiEvent::UpdateText(text_entry.clone());
+                    }
                     KeyCode::Right => parameters_modified = Some(ParameterModified::Increment),
                     KeyCode::Left => parameters_modified = Some(ParameterModified::Decrement),
                     KeyCode::Char(char) => {
                         match char {
                             '<' => {
-                                let mut midi_chan = midi_channel.lock().unwrap();
+                                let midi_chan = midi_channel.load(Ordering::Relaxed);
                                 //min channel 0
-                                if *midi_chan > 0 {
-                                    *midi_chan -= 1;
-                                    ui_event = UiEvent::UpdateMidiChannel(*midi_chan);
+                                if midi_chan > 0 {
+                                    midi_channel.store(midi_chan - 1, Ordering::Relaxed);
+                                    ui_event = UiEvent::UpdateMidiChannel(midi_chan - 1);
                                 }
                             }
                             '>' => {
-                                let mut midi_chan = midi_channel.lock().unwrap();
+                                let midi_chan = midi_channel.load(Ordering::Relaxed);
                                 //max channel 15
-                                if *midi_chan < 15 {
-                                    *midi_chan += 1;
-                                    ui_event = UiEvent::UpdateMidiChannel(*midi_chan);
+                                if midi_chan < 15 {
+                                    midi_channel.store(midi_chan + 1, Ordering::Relaxed);
+                                    ui_event = UiEvent::UpdateMidiChannel(midi_chan + 1);
                                 }
                             }
                             '!'=>{
                                     ui_event = UiEvent::ToggleRawCCMode;
 
                             }
+                            '+' => {
+                                let next = model_switcher.next();
+                                switch_model(next, &mut model_switcher, &mut param_sender, &parameters, &gui_sender, &mut selected);
+                            }
+                            '-' => {
+                                let previous = model_switcher.previous();
+                                switch_model(previous, &mut model_switcher, &mut param_sender, &parameters, &gui_sender, &mut selected);
+                            }
                             _ => parameters_modified = Some(ParameterModified::SetValue(char)),
                         }
                     }
                     KeyCode::Tab => {
+                        //get the ring sender back from the old connection
+                        let (_, midi_sender) = _midi_connection.close();
                         _midi_connection = match connect_midi(
-                            midi_sender.clone(),
-                            parameters.clone(),
-                            param_sender.clone(),
+                            midi_sender,
                             gui_sender.clone(),
+                            program_sender.clone(),
                             midi_channel.clone(),
                         ) {
                             Ok((midi_connection, port_name)) => {
                                 ui_event = UiEvent::UpdateMidiportName(port_name);
-                                *midi_channel.lock().unwrap() = 0;
+                                midi_channel.store(0, Ordering::Relaxed);
                                 midi_connection
                             }
                             Err(error) => panic!("can't connect to midi: {:?}", error),
@@ -162,8 +229,8 @@ pub fn keyboard_input(
                         ParameterModified::Decrement => parameter.decrement(),
                         ParameterModified::SetValue(char) => parameter.set_value(char),
                     }
-                    //get a copy of the parameter and send it to the audio thread
-                    param_sender.send((id, parameter.get_raw_value())).unwrap();
+                    //get a copy of the parameter and send it to the audio thread, a full ring is shown in the UI
+                    let _ = param_sender.send(EngineMessage::Parameter((id, parameter.get_raw_value())));
                 });
             }
         }
@@ -171,18 +238,39 @@ pub fn keyboard_input(
     Ok(())
 }
 
+///build and send the new model, then show its parameters
+fn switch_model(
+    index: usize,
+    model_switcher: &mut ModelSwitcher,
+    param_sender: &mut QueueSender<EngineMessage>,
+    parameters: &Arc<Mutex<Parameters>>,
+    gui_sender: &Sender<UiEvent>,
+    selected: &mut i32,
+) {
+    if model_switcher.switch(index, param_sender, parameters) {
+        *selected = 0;
+        gui_sender
+            .send(UiEvent::UpdateModelName(model_switcher.current_name()))
+            .unwrap();
+        gui_sender.send(UiEvent::UpdateSelection(0)).unwrap();
+    }
+}
+
 pub fn gui(
     parameters: Arc<Mutex<Parameters>>,
     receive_event: Receiver<UiEvent>,
-    number_of_params: usize,
+    overflows: Arc<AtomicUsize>,
 ) -> Result<()> {
 
     let mut local_parameters: Vec<Parameter> = Vec::new();
     let mut midi_channel: u8 = 0;
     let mut midi_port_name: String = "midi port".to_string();
+    let mut audio_setup: String = "".to_string();
+    let mut model_name: String = "".to_string();
     let mut selected: i32 = 0;
     let mut top_selection_index = 0;
     let mut raw_cc_display = false;
+    let mut text_entry: Option<String> = None;
     let default = (10 as u16, 10 as u16);
     //use this to get a name vector, il allow me to refer to a parameter via it's index rather than it's name
     loop {
@@ -202,6 +290,20 @@ pub fn gui(
             UI::ToggleRawCCMode => {
                 raw_cc_display = !raw_cc_display;
             }
+            UI::ControlChange(cc, midi_value) => {
+                //if cc is not bounded, do nothing
+                for capsule in parameters.lock().unwrap().capsules.iter_mut() {
+                    if outils::get_orca_integer(capsule.parameter.midicc).unwrap_or(0) == cc {
+                        capsule.parameter.value = cc_value_to_orca(midi_value);
+                    }
+                }
+            }
+            UI::UpdateAudioSetup(setup) => audio_setup = setup,
+            UI::UpdateModelName(name) => {
+                model_name = name;
+                top_selection_index = 0;
+            }
+            UI::UpdateText(text) => text_entry = text,
             UI::Refresh => {}
         };
         //need to be updated a each iteration to get new values
@@ -210,8 +312,8 @@ pub fn gui(
             local_parameters.push(parameter.parameter.clone());
         }
         let terminal_size = crossterm::terminal::size().unwrap_or(default);
-        //magic number to compensate the title bar (midi port and channel)
-        let bottom = top_selection_index + terminal_size.1 as i32 - 4;
+        //magic number to compensate the title bar (midi port and channel, audio setup)
+        let bottom = top_selection_index + terminal_size.1 as i32 - 5;
 
         if (selected - 2) < top_selection_index && top_selection_index > 0 {
             top_selection_index -= 1
@@ -224,12 +326,20 @@ pub fn gui(
             &local_parameters,
             midi_channel,
             &midi_port_name,
+            &audio_setup,
+            &model_name,
             selected,
             top_selection_index,
             terminal_size.1 as i32,
-            number_of_params,
+            local_parameters.len(),
             raw_cc_display,
+            overflows.load(Ordering::Relaxed),
         );
+        if let Some(text) = &text_entry {
+            print! {"{} {}_", "text :".to_string().italic(), text};
+            //no line end to flush it
+            std::io::stdout().flush()?;
+        }
     }
 }
 
@@ -237,16 +347,25 @@ fn update_display(
     parameters: &Vec<Parameter>,
     midi_channel: u8,
     midi_port_name: &String,
+    audio_setup: &str,
+    model_name: &str,
     selected: i32,
     top_selection_index: i32,
     size: i32,
     number_of_params: usize,
     raw_midi_cc: bool,
+    overflows: usize,
 ) {
     println!("{}", terminal::Clear(terminal::ClearType::All));
     println!("{}", cursor::MoveTo(0, 0));
     let orca_midi_channel = get_orca_character(midi_channel as i32);
     print! {"{} --- {} {}", midi_port_name, "channel".to_string().italic(), orca_midi_channel.unwrap()};
+    //messages dropped because the audio thread could not keep up
+    if overflows > 0 {
+        print! {" --- {} {}", "overflow".to_string().italic(), overflows};
+    }
+    print! {"\r\n"};
+    print! {"{} --- {}", model_name.bold(), audio_setup.italic()};
     print! {"\r\n"};
     print! {"\r\n"};
     let iterator;
@@ -256,7 +375,7 @@ fn update_display(
     } else
     //else, implement scroll
     {
-        let bottom = (top_selection_index + size - 4).clamp(0, number_of_params as i32);
+        let bottom = (top_selection_index + size - 5).clamp(0, number_of_params as i32);
         iterator = top_selection_index as usize..bottom as usize
     }
     for i in iterator {
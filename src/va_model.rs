@@ -0,0 +1,416 @@
+use crate::envelope;
+use crate::envelope::Envelope;
+use crate::filter::FilterType;
+use crate::midi::MidiMessage;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode};
+use crate::oscillator::{BlepWaveform, PolyBlepOscillator};
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth, MAXIMUM_BLOCK_SIZE};
+use crate::Biquad;
+use crate::Lfo;
+use crate::ParameterUpdate;
+use num_derive::FromPrimitive;
+
+const NUMBER_OF_VOICES: usize = 4;
+
+const NB_SYNTH_PARAM: usize = 16;
+//the filter envelope at full amount opens the cutoff by this many octaves
+const FILTER_ENVELOPE_OCTAVES: f32 = 6.;
+//the filter coefficients follow the envelope every this many samples
+const FILTER_CONTROL_RATE: usize = 16;
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum VaModelParamID {
+    Oscillator1Waveform,
+    Oscillator2Waveform,
+    Detune,
+    PulseWidth,
+    PwmDepth,
+    PwmRate,
+    OscillatorMix,
+    SubLevel,
+    FilterCutoff,
+    FilterResonance,
+    FilterEnvelopeAmount,
+    FilterEnvelopeAttack,
+    FilterEnvelopeRelease,
+    EnvelopeAttack,
+    EnvelopeRelease,
+    Volume,
+}
+
+///two oscillators and a square one octave below, through its own low-pass
+struct VaVoice {
+    oscillators: [PolyBlepOscillator; 2],
+    sub: PolyBlepOscillator,
+    low_pass: Biquad,
+    envelope: Envelope,
+    filter_envelope: Envelope,
+    //samples until the next filter update
+    control_counter: usize,
+    frequency_hz: f32,
+    //in semitones, of the second oscillator
+    detune: f32,
+}
+
+impl VaVoice {
+    fn new(index: usize) -> Self {
+        let mut oscillators = [PolyBlepOscillator::new(); 2];
+        for (i, oscillator) in oscillators.iter_mut().enumerate() {
+            oscillator.set_seed((index * 2 + i) as u32);
+        }
+        let mut sub = PolyBlepOscillator::new();
+        sub.waveform = BlepWaveform::Pulse;
+        VaVoice {
+            oscillators,
+            sub,
+            low_pass: Biquad::new(FilterType::LPF),
+            envelope: Envelope::new(),
+            filter_envelope: Envelope::new(),
+            control_counter: 0,
+            frequency_hz: 440.,
+            detune: 0.,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.oscillators.iter_mut().for_each(|x| x.init(sample_rate));
+        self.sub.init(sample_rate);
+        self.low_pass.init(sample_rate);
+        self.envelope.init(sample_rate);
+        self.filter_envelope.init(sample_rate);
+    }
+
+    fn tune(&mut self) {
+        self.oscillators[0].frequency_hz = self.frequency_hz;
+        self.oscillators[1].frequency_hz = self.frequency_hz * 2_f32.powf(self.detune / 12.);
+        self.sub.frequency_hz = self.frequency_hz / 2.;
+    }
+}
+
+impl Voice for VaVoice {
+    type Context = ();
+
+    fn start(&mut self, _midi_note: u8, retrigger: bool, _: &mut ()) {
+        if retrigger {
+            self.envelope.note_on();
+            self.filter_envelope.note_on();
+        }
+    }
+
+    fn release(&mut self, _: &()) {
+        self.envelope.note_off();
+        self.filter_envelope.note_off();
+    }
+
+    fn steal(&mut self, _: &()) {
+        self.envelope.steal();
+    }
+
+    fn level(&self, _: &()) -> f32 {
+        self.envelope.value()
+    }
+
+    fn is_silent(&self, _: &()) -> bool {
+        self.envelope.status == envelope::Segment::Off
+    }
+
+    fn set_frequency(&mut self, frequency_hz: f32) {
+        self.frequency_hz = frequency_hz;
+        self.tune();
+    }
+}
+
+pub struct VaModel {
+    voices: VoiceManager<VaVoice>,
+    pwm: Lfo,
+    //pulse width of each sample of the block, shared by the voices
+    pulse_widths: [f32; MAXIMUM_BLOCK_SIZE],
+    //parameters
+    pulse_width: f32,
+    pwm_depth: f32,
+    mix: f32,
+    sub_level: f32,
+    cutoff: f32,
+    resonance: f32,
+    filter_envelope_amount: f32,
+    volume: f32,
+}
+
+impl VaModel {
+    fn set_filter(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice
+                .low_pass
+                .set_frequence_and_resonance(self.cutoff, self.resonance);
+        }
+    }
+}
+
+impl HasDescription for VaModel {
+    const NAME: &'static str = "VA";
+    const DESCRIPTION: &'static str = "virtual analog, two PolyBLEP oscillators and a sub, resonant low-pass with its own envelope";
+}
+
+impl HasConstructor for VaModel {
+    fn new() -> Self {
+        VaModel {
+            voices: VoiceManager::new((0..NUMBER_OF_VOICES).map(VaVoice::new).collect(), VoiceMode::Oldest),
+            pwm: Lfo::new(1.),
+            pulse_widths: [0.5; MAXIMUM_BLOCK_SIZE],
+            pulse_width: 0.5,
+            pwm_depth: 0.,
+            mix: 0.5,
+            sub_level: 0.,
+            cutoff: 20000.,
+            resonance: 0.7,
+            filter_envelope_amount: 0.,
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.voices.init(sample_rate);
+        self.voices.iter_mut().for_each(|x| x.init(sample_rate));
+        self.pwm.init(sample_rate);
+    }
+}
+
+impl HasMidiInput for VaModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut ()),
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut ()),
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for VaModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let pulse_widths = &mut self.pulse_widths[..left.len()];
+        for pulse_width in pulse_widths.iter_mut() {
+            *pulse_width = (self.pulse_width + self.pwm.tick() * self.pwm_depth * 0.5).clamp(0.05, 0.95);
+        }
+
+        left.fill(0.);
+        for i in 0..self.voices.len() {
+            for (sample, pulse_width) in left.iter_mut().zip(pulse_widths.iter()) {
+                self.voices.update(i, &mut ());
+                let voice = &mut self.voices[i];
+                if voice.envelope.status == envelope::Segment::Off {
+                    break;
+                }
+                if voice.control_counter == 0 {
+                    //exponential, so the envelope sweeps the same number of octaves from any cutoff
+                    let octaves = self.filter_envelope_amount
+                        * FILTER_ENVELOPE_OCTAVES
+                        * voice.filter_envelope.process();
+                    voice.low_pass.modulate(self.cutoff * (2_f32.powf(octaves) - 1.));
+                    voice.control_counter = FILTER_CONTROL_RATE;
+                } else {
+                    voice.filter_envelope.process();
+                }
+                voice.control_counter -= 1;
+
+                voice.oscillators.iter_mut().for_each(|x| x.pulse_width = *pulse_width);
+                let oscillators = voice.oscillators[0].process() * (1. - self.mix)
+                    + voice.oscillators[1].process() * self.mix;
+                let mixed = oscillators + voice.sub.process() * self.sub_level;
+                *sample += voice.low_pass.process(mixed) * voice.envelope.process();
+            }
+        }
+
+        for sample in left.iter_mut() {
+            //vca
+            *sample *= self.volume / NUMBER_OF_VOICES as f32;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for VaModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = VaModelParamID;
+        type P = ParameterCapsule;
+        //saw, pulse, triangle, noise
+        let last_waveform = BlepWaveform::Noise as i32 as f32;
+
+        let params = Parameters {
+            capsules: vec![
+                //oscillators
+                P::new(ID::Oscillator1Waveform as i32, "osc1-wave", 0, '1', 0., last_waveform, 1.),
+                P::new(ID::Oscillator2Waveform as i32, "osc2-wave", 0, '2', 0., last_waveform, 1.),
+                //in semitones, fine around 0
+                P::new(ID::Detune as i32, "detune", 2, 'e', 0., 12., 2.),
+                P::new(ID::PulseWidth as i32, "pw", 17, 'p', 0.05, 0.95, 1.),
+                P::new(ID::PwmDepth as i32, "pwm-depth", 0, 'w', 0., 0.9, 1.),
+                P::new(ID::PwmRate as i32, "pwm-rate", 5, 'k', 0.05, 10., 2.),
+                P::new(ID::OscillatorMix as i32, "osc-mix", 17, 'm', 0., 1., 1.),
+                P::new(ID::SubLevel as i32, "sub", 0, 's', 0., 1., 1.),
+                //filter
+                P::new(ID::FilterCutoff as i32, "cutoff", 20, 'c', 20., 20000., 4.),
+                P::new(ID::FilterResonance as i32, "resonance", 5, 'q', 0.7, 10., 2.),
+                P::new(ID::FilterEnvelopeAmount as i32, "flt-env", 10, 'f', 0., 1., 1.),
+                P::new(
+                    ID::FilterEnvelopeAttack as i32,
+                    "flt-atk",
+                    2,
+                    'h',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    ID::FilterEnvelopeRelease as i32,
+                    "flt-rel",
+                    6,
+                    'j',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                //envelope
+                P::new(
+                    ID::EnvelopeAttack as i32,
+                    "env-atk",
+                    1,
+                    'a',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    ID::EnvelopeRelease as i32,
+                    "env-rel",
+                    5,
+                    'd',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = VaModelParamID;
+        let typed_id: VaModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            //oscillators
+            ID::Oscillator1Waveform | ID::Oscillator2Waveform => {
+                let oscillator = (typed_id == ID::Oscillator2Waveform) as usize;
+                let waveform: BlepWaveform =
+                    num::FromPrimitive::from_i32(new_value.round() as i32).unwrap_or(BlepWaveform::Noise);
+                self.voices
+                    .iter_mut()
+                    .for_each(|voice| voice.oscillators[oscillator].waveform = waveform);
+            }
+            ID::Detune => self.voices.iter_mut().for_each(|voice| {
+                voice.detune = new_value;
+                voice.tune()
+            }),
+            ID::PulseWidth => self.pulse_width = new_value,
+            ID::PwmDepth => self.pwm_depth = new_value,
+            ID::PwmRate => self.pwm.set_frequence(new_value),
+            ID::OscillatorMix => self.mix = new_value,
+            ID::SubLevel => self.sub_level = new_value,
+            //filter
+            ID::FilterCutoff => {
+                self.cutoff = new_value;
+                self.set_filter();
+            }
+            ID::FilterResonance => {
+                self.resonance = new_value;
+                self.set_filter();
+            }
+            ID::FilterEnvelopeAmount => self.filter_envelope_amount = new_value,
+            ID::FilterEnvelopeAttack => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.filter_envelope.set_attack(new_value)),
+            ID::FilterEnvelopeRelease => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.filter_envelope.set_release(new_value)),
+            //envelope
+            ID::EnvelopeAttack => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_attack(new_value)),
+            ID::EnvelopeRelease => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_release(new_value)),
+        }
+    }
+}
+
+impl Synth for VaModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::model;
+
+    fn energy(model: &mut VaModel) -> f32 {
+        let mut left = [0.; 512];
+        let mut right = [0.; 512];
+        model.process_block(&mut left, &mut right);
+        assert_eq!(left, right);
+        left.iter().map(|sample| sample * sample).sum()
+    }
+
+    #[test]
+    fn parameters_and_voices() {
+        let mut model: VaModel = model();
+        assert_eq!(model.get_parameters().capsules.len(), NB_SYNTH_PARAM);
+        assert_eq!(energy(&mut model), 0.);
+
+        model.set_note(MidiMessage::NoteOn(48));
+        assert!(energy(&mut model) > 0.);
+        assert_eq!(model.voices[0].sub.frequency_hz * 2., model.voices[0].oscillators[0].frequency_hz);
+
+        model.set_parameter((VaModelParamID::Detune as i32, 12.));
+        let voice = &model.voices[0];
+        assert!((voice.oscillators[1].frequency_hz / voice.oscillators[0].frequency_hz - 2.).abs() < 0.001);
+
+        model.set_parameter((VaModelParamID::Oscillator2Waveform as i32, 1.));
+        assert_eq!(model.voices[3].oscillators[1].waveform, BlepWaveform::Pulse);
+        assert_eq!(model.voices[3].oscillators[0].waveform, BlepWaveform::Saw);
+    }
+
+    #[test]
+    fn filter_envelope_opens_the_cutoff() {
+        let mut closed: VaModel = model();
+        let mut opened: VaModel = model();
+        for model in [&mut closed, &mut opened] {
+            model.set_parameter((VaModelParamID::FilterCutoff as i32, 200.));
+            model.set_parameter((VaModelParamID::EnvelopeAttack as i32, 10.));
+            model.set_parameter((VaModelParamID::FilterEnvelopeAttack as i32, 10.));
+        }
+        closed.set_parameter((VaModelParamID::FilterEnvelopeAmount as i32, 0.));
+        opened.set_parameter((VaModelParamID::FilterEnvelopeAmount as i32, 1.));
+        closed.set_note(MidiMessage::NoteOn(72));
+        opened.set_note(MidiMessage::NoteOn(72));
+        //past the attack
+        for _ in 0..4 {
+            energy(&mut closed);
+            energy(&mut opened);
+        }
+        assert!(energy(&mut opened) > energy(&mut closed) * 2.);
+    }
+}
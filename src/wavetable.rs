@@ -0,0 +1,290 @@
+use crate::outils::midi_to_frequence;
+use crate::sample::Sample;
+use anyhow::Context;
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::path::Path;
+
+///samples in one cycle, every frame is resampled to it
+pub const FRAME_SIZE: usize = 2048;
+///copies of the table with less and less harmonics, level `k` keeps `(FRAME_SIZE / 2) >> k` of them
+pub const NUMBER_OF_LEVELS: usize = 11;
+
+///Single cycle frames, stored once per band limit level.
+///Built outside of the audio thread, the playback only reads it.
+pub struct Wavetable {
+    pub name: String,
+    number_of_frames: usize,
+    //level, then frame, then sample
+    samples: Vec<f32>,
+}
+
+impl Wavetable {
+    ///`frames` are single cycles of any length, they are resampled to `FRAME_SIZE`
+    pub fn from_frames(name: &str, frames: &[Vec<f32>]) -> Self {
+        let number_of_frames = frames.len().max(1);
+        let mut samples = vec![0.; NUMBER_OF_LEVELS * number_of_frames * FRAME_SIZE];
+
+        let mut planner = FftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(FRAME_SIZE);
+        let inverse = planner.plan_fft_inverse(FRAME_SIZE);
+        let mut spectrum = vec![Complex::new(0., 0.); FRAME_SIZE];
+        let mut filtered = vec![Complex::new(0., 0.); FRAME_SIZE];
+
+        for (frame_index, frame) in frames.iter().enumerate() {
+            for (i, bin) in spectrum.iter_mut().enumerate() {
+                *bin = Complex::new(resample(frame, i as f32 / FRAME_SIZE as f32), 0.);
+            }
+            forward.process(&mut spectrum);
+            for level in 0..NUMBER_OF_LEVELS {
+                let highest_harmonic = (FRAME_SIZE / 2) >> level;
+                //no DC, and nothing above the highest harmonic on both sides of the spectrum
+                for (i, bin) in filtered.iter_mut().enumerate() {
+                    let harmonic = i.min(FRAME_SIZE - i);
+                    *bin = if harmonic == 0 || harmonic > highest_harmonic {
+                        Complex::new(0., 0.)
+                    } else {
+                        spectrum[i]
+                    };
+                }
+                inverse.process(&mut filtered);
+                let start = (level * number_of_frames + frame_index) * FRAME_SIZE;
+                for (sample, bin) in samples[start..start + FRAME_SIZE].iter_mut().zip(filtered.iter()) {
+                    *sample = bin.re / FRAME_SIZE as f32;
+                }
+            }
+        }
+
+        //the same gain for every frame, so the morph doesn't change the volume of the table
+        let peak = samples[..number_of_frames * FRAME_SIZE]
+            .iter()
+            .fold(0., |peak: f32, sample| peak.max(sample.abs()));
+        if peak > 0. {
+            samples.iter_mut().for_each(|sample| *sample /= peak);
+        }
+
+        Wavetable {
+            name: name.to_string(),
+            number_of_frames,
+            samples,
+        }
+    }
+
+    ///`frame_size` is the length of one cycle in the file, the usual one is 2048.
+    ///Multichannel files are mixed down, a file shorter than `frame_size` is one cycle.
+    pub fn from_wav(path: &Path, frame_size: usize) -> Result<Self, anyhow::Error> {
+        let sample = Sample::from_wav(path).context("can't load the wavetable")?;
+        let mono = &sample.samples;
+        let frames: Vec<Vec<f32>> = mono
+            .chunks(frame_size.max(1))
+            .filter(|frame| frame.len() == frame_size || mono.len() < frame_size)
+            .map(|frame| frame.to_vec())
+            .collect();
+        Ok(Self::from_frames(&sample.name, &frames))
+    }
+
+    pub fn number_of_frames(&self) -> usize {
+        self.number_of_frames
+    }
+
+    fn frame(&self, level: usize, frame_index: usize) -> &[f32] {
+        let start = (level * self.number_of_frames + frame_index) * FRAME_SIZE;
+        &self.samples[start..start + FRAME_SIZE]
+    }
+
+    ///`position` from 0 to 1 morphs from the first to the last frame, `phase` from 0 to 1
+    pub fn read(&self, level: usize, position: f32, phase: f32) -> f32 {
+        let position = position.clamp(0., 1.) * (self.number_of_frames - 1) as f32;
+        let frame_index = position.floor() as usize;
+        let next_frame_index = (frame_index + 1).min(self.number_of_frames - 1);
+        let morph = position - frame_index as f32;
+
+        let level = level.min(NUMBER_OF_LEVELS - 1);
+        let sample = resample(self.frame(level, frame_index), phase);
+        if morph == 0. {
+            return sample;
+        }
+        let next_sample = resample(self.frame(level, next_frame_index), phase);
+        sample + (next_sample - sample) * morph
+    }
+
+    ///the tables available without any file
+    pub fn builtin() -> Vec<Wavetable> {
+        let table = |name: &str, number_of_frames: usize, shape: &dyn Fn(f32, f32) -> f32| {
+            let frames: Vec<Vec<f32>> = (0..number_of_frames)
+                .map(|frame_index| {
+                    let morph = frame_index as f32 / (number_of_frames - 1) as f32;
+                    (0..FRAME_SIZE)
+                        .map(|i| shape(i as f32 / FRAME_SIZE as f32, morph))
+                        .collect()
+                })
+                .collect();
+            Wavetable::from_frames(name, &frames)
+        };
+        let sine = |phase: f32| (phase * std::f32::consts::TAU).sin();
+        let triangle = |phase: f32| 1. - 4. * (phase - 0.5).abs();
+        let saw = |phase: f32| 2. * phase - 1.;
+        let square = |phase: f32| if phase < 0.5 { 1. } else { -1. };
+
+        vec![
+            //sine, triangle, saw then square
+            table("basic", 4, &|phase, morph| match (morph * 3.).round() as usize {
+                0 => sine(phase),
+                1 => triangle(phase),
+                2 => saw(phase),
+                _ => square(phase),
+            }),
+            //pulse from square to a thin pulse
+            table("pulse", 16, &|phase, morph| {
+                if phase < 0.5 - morph * 0.45 {
+                    1.
+                } else {
+                    -1.
+                }
+            }),
+            //more and more harmonics added to the fundamental
+            table("harmonics", 16, &|phase, morph| {
+                let count = 1 + (morph * 15.) as usize;
+                (1..=count)
+                    .map(|harmonic| sine(phase * harmonic as f32) / harmonic as f32)
+                    .sum()
+            }),
+            //sine folded on itself harder and harder
+            table("fold", 16, &|phase, morph| {
+                (sine(phase) * (1. + morph * 6.)).sin()
+            }),
+        ]
+    }
+}
+
+///linear interpolation of a cycle at `phase` from 0 to 1
+fn resample(frame: &[f32], phase: f32) -> f32 {
+    if frame.is_empty() {
+        return 0.;
+    }
+    let position = phase.rem_euclid(1.) * frame.len() as f32;
+    let index = position.floor() as usize % frame.len();
+    let next_index = (index + 1) % frame.len();
+    let frac = position - position.floor();
+    frame[index] + (frame[next_index] - frame[index]) * frac
+}
+
+///Phase of one voice, and the band limit level matching its note
+#[derive(Clone, Copy)]
+pub struct WavetableOscillator {
+    pub frequency_hz: f32,
+    sample_rate: f32,
+    phase: f32,
+    level: usize,
+}
+
+impl Default for WavetableOscillator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WavetableOscillator {
+    pub fn new() -> Self {
+        WavetableOscillator {
+            frequency_hz: 440.,
+            sample_rate: 0.,
+            phase: 0.,
+            level: 0,
+        }
+    }
+
+    pub fn init(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.set_frequency(self.frequency_hz);
+    }
+
+    pub fn set_note(&mut self, midi_note: u8) {
+        self.set_frequency(midi_to_frequence(midi_note));
+    }
+
+    ///pick the level with the most harmonics staying under nyquist
+    pub fn set_frequency(&mut self, frequency_hz: f32) {
+        self.frequency_hz = frequency_hz;
+        let nyquist = self.sample_rate / 2.;
+        self.level = (0..NUMBER_OF_LEVELS)
+            .find(|level| ((FRAME_SIZE / 2) >> level) as f32 * frequency_hz < nyquist)
+            .unwrap_or(NUMBER_OF_LEVELS - 1);
+    }
+
+    pub fn process(&mut self, table: &Wavetable, position: f32) -> f32 {
+        let sample = table.read(self.level, position, self.phase);
+        self.phase = (self.phase + self.frequency_hz / self.sample_rate) % 1.;
+        sample
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn band_limited() {
+        let tables = Wavetable::builtin();
+        let basic = &tables[0];
+        assert_eq!(basic.number_of_frames(), 4);
+
+        //the first frame is a sine, the same at every level
+        for phase in [0.1, 0.25, 0.6] {
+            let sine = (phase * std::f32::consts::TAU).sin();
+            assert!((basic.read(0, 0., phase) - sine).abs() < 0.01);
+            assert!((basic.read(NUMBER_OF_LEVELS - 1, 0., phase) - sine).abs() < 0.01);
+        }
+        //the last level of the square only keeps the fundamental, so it's a sine too
+        let ratio = |level| basic.read(level, 1., 0.125) / basic.read(level, 1., 0.25);
+        assert!((ratio(NUMBER_OF_LEVELS - 1) - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01);
+        assert!(ratio(0) > 0.9);
+
+        let mut oscillator = WavetableOscillator::new();
+        oscillator.init(48000.);
+        oscillator.set_note(21);
+        let low = oscillator.level;
+        oscillator.set_note(108);
+        assert!(oscillator.level > low);
+        assert!(((FRAME_SIZE / 2) >> oscillator.level) as f32 * oscillator.frequency_hz < 24000.);
+    }
+
+    #[test]
+    fn morph_between_frames() {
+        let frames = vec![vec![1.; 4], vec![-1.; 4]];
+        let table = Wavetable::from_frames("test", &frames);
+        //no DC is kept, so flat frames are silent
+        assert_eq!(table.read(0, 0.5, 0.3), 0.);
+
+        let frames = vec![vec![1., -1.], vec![-1., 1.]];
+        let table = Wavetable::from_frames("test", &frames);
+        let first = table.read(0, 0., 0.);
+        let last = table.read(0, 1., 0.);
+        assert!((first + last).abs() < 0.001);
+        assert!(table.read(0, 0.5, 0.).abs() < 0.001);
+    }
+
+    #[test]
+    fn load_wav() {
+        let path = std::env::temp_dir().join("laminaria_wavetable_test.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        //two frames of 256 samples, a saw then its opposite, and a partial frame ignored
+        for i in 0..(256 * 2 + 100) {
+            let saw = (i % 256) as f32 / 128. - 1.;
+            let sample = if i < 256 { saw } else { -saw };
+            writer.write_sample((sample * 16000.) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let table = Wavetable::from_wav(&path, 256).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(table.number_of_frames(), 2);
+        assert_eq!(table.name, "laminaria_wavetable_test");
+        assert!((table.read(0, 0., 0.25) + table.read(0, 1., 0.25)).abs() < 0.01);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::buffer;
+use crate::buffer::DelayLine;
+use crate::buffer::DelayMode;
+use crate::envelope;
+use crate::envelope::Envelope;
+use crate::filter::FilterType;
+use crate::midi::MidiMessage;
+use crate::midibuffer::{Voice, VoiceManager, VoiceMode};
+use crate::outils;
+use crate::parameters::{ParameterCapsule, Parameters};
+use crate::reverb::Reverb;
+use crate::synth::{HasConstructor, HasDescription, HasEngine, HasMidiInput, HasParameters, Synth, MAXIMUM_BLOCK_SIZE};
+use crate::wavetable::{Wavetable, WavetableOscillator};
+use crate::Biquad;
+use crate::ParameterUpdate;
+use lazy_static::lazy_static;
+use num_derive::FromPrimitive;
+use std::sync::Arc;
+
+const NUMBER_OF_VOICES: usize = 4;
+
+const NB_SYNTH_PARAM: usize = 12;
+//close to 1 is slow, the position takes ~5ms to reach a new value at 48kHz
+const POSITION_SLEW: f32 = 0.995;
+
+lazy_static! {
+    //the band limited copies are long to compute, so they are shared by every instance
+    static ref BUILTIN_TABLES: Vec<Arc<Wavetable>> =
+        Wavetable::builtin().into_iter().map(Arc::new).collect();
+}
+
+#[derive(PartialEq, Debug, Copy, Clone, FromPrimitive)] //from primitive allow me to cast i32 as enum
+pub enum WavetableModelParamID {
+    Table,
+    Position,
+    EnvelopeAttack,
+    EnvelopeRelease,
+    FilterCutoff,
+    FilterResonance,
+    DelayTime,
+    DelayFeedback,
+    DelayDryWet,
+    ReverbDryWet,
+    ReverbTime,
+    Volume,
+}
+
+#[derive(Clone, Copy)]
+struct WavetableVoice {
+    envelope: Envelope,
+    oscillator: WavetableOscillator,
+}
+
+impl Voice for WavetableVoice {
+    type Context = ();
+
+    fn start(&mut self, _midi_note: u8, retrigger: bool, _: &mut ()) {
+        if retrigger {
+            self.envelope.note_on();
+        }
+    }
+
+    fn release(&mut self, _: &()) {
+        self.envelope.note_off();
+    }
+
+    fn steal(&mut self, _: &()) {
+        self.envelope.steal();
+    }
+
+    fn level(&self, _: &()) -> f32 {
+        self.envelope.value()
+    }
+
+    fn is_silent(&self, _: &()) -> bool {
+        self.envelope.status == envelope::Segment::Off
+    }
+
+    fn set_frequency(&mut self, frequency_hz: f32) {
+        self.oscillator.set_frequency(frequency_hz);
+    }
+}
+
+pub struct WavetableModel {
+    tables: Vec<Arc<Wavetable>>,
+    voices: VoiceManager<WavetableVoice>,
+    low_pass: Biquad,
+    delay: DelayLine,
+    reverb: Reverb,
+    //smoothed position of each sample of the block, shared by the voices
+    positions: [f32; MAXIMUM_BLOCK_SIZE],
+    //parameters
+    table_index: usize,
+    position_goal: f32,
+    position: f32,
+    cutoff: f32,
+    resonance: f32,
+    delay_dry_wet: f32,
+    volume: f32,
+}
+
+impl WavetableModel {
+    ///the builtin tables followed by `tables`, loaded from files with `Wavetable::from_wav`
+    pub fn with_tables(tables: Vec<Arc<Wavetable>>) -> Self {
+        let mut model = Self::new();
+        model.tables.extend(tables);
+        model
+    }
+
+    pub fn table_names(&self) -> Vec<String> {
+        self.tables.iter().map(|table| table.name.clone()).collect()
+    }
+}
+
+impl HasDescription for WavetableModel {
+    const NAME: &'static str = "Wavetable";
+    const DESCRIPTION: &'static str = "band limited wavetables morphing between frames, low-pass, delay and reverb";
+}
+
+impl HasConstructor for WavetableModel {
+    fn new() -> Self {
+        WavetableModel {
+            tables: BUILTIN_TABLES.clone(),
+            voices: VoiceManager::new(
+                vec![
+                    WavetableVoice {
+                        envelope: Envelope::new(),
+                        oscillator: WavetableOscillator::new(),
+                    };
+                    NUMBER_OF_VOICES
+                ],
+                VoiceMode::Oldest,
+            ),
+            low_pass: Biquad::new(FilterType::LPF),
+            delay: DelayLine::new(buffer::MAXIMUM_DELAY_TIME, DelayMode::Comb),
+            reverb: Reverb::new(),
+            positions: [0.; MAXIMUM_BLOCK_SIZE],
+            table_index: 0,
+            position_goal: 0.,
+            position: 0.,
+            cutoff: 20000.,
+            resonance: 0.7,
+            delay_dry_wet: 0.,
+            volume: 0.5,
+        }
+    }
+
+    fn init(&mut self, sample_rate: f32) {
+        self.voices.init(sample_rate);
+        for voice in self.voices.iter_mut() {
+            voice.envelope.init(sample_rate);
+            voice.oscillator.init(sample_rate);
+        }
+        self.low_pass.init(sample_rate);
+        self.delay.init(sample_rate);
+        self.reverb.init(sample_rate);
+    }
+}
+
+impl HasMidiInput for WavetableModel {
+    fn set_note(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOff(midi_note) => self.voices.note_off(midi_note, &mut ()),
+            MidiMessage::NoteOn(midi_note) => self.voices.note_on(midi_note, &mut ()),
+            _ => {}
+        }
+    }
+}
+
+impl HasEngine for WavetableModel {
+    fn process(&mut self) -> f32 {
+        let (mut left, mut right) = ([0.], [0.]);
+        self.process_block(&mut left, &mut right);
+        left[0]
+    }
+
+    fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let positions = &mut self.positions[..left.len()];
+        for position in positions.iter_mut() {
+            self.position = outils::slew_value(self.position_goal, self.position, POSITION_SLEW);
+            *position = self.position;
+        }
+
+        let table = &self.tables[self.table_index];
+        left.fill(0.);
+        for i in 0..self.voices.len() {
+            for (sample, position) in left.iter_mut().zip(positions.iter()) {
+                self.voices.update(i, &mut ());
+                let voice = &mut self.voices[i];
+                if voice.envelope.status == envelope::Segment::Off {
+                    break;
+                }
+                *sample += voice.oscillator.process(table, *position) * voice.envelope.process();
+            }
+        }
+
+        self.low_pass.process_block(left);
+        for sample in left.iter_mut() {
+            // EFFECTS
+            let delay = self.delay.process(*sample);
+            let wet = outils::equal_power_crossfade(*sample, delay, self.delay_dry_wet);
+            //vca
+            *sample = self.reverb.process(wet) * self.volume / NUMBER_OF_VOICES as f32;
+        }
+        right.copy_from_slice(left);
+    }
+}
+
+impl HasParameters for WavetableModel {
+    fn get_parameters(&self) -> Parameters {
+        type ID = WavetableModelParamID;
+        type P = ParameterCapsule;
+
+        let params = Parameters {
+            capsules: vec![
+                //oscillator
+                P::new(
+                    ID::Table as i32,
+                    "wt-table",
+                    0,
+                    'b',
+                    0.,
+                    (self.tables.len() - 1) as f32,
+                    1.,
+                ),
+                P::new(ID::Position as i32, "wt-pos", 0, 'p', 0., 1., 1.),
+                //envelope
+                P::new(
+                    ID::EnvelopeAttack as i32,
+                    "env-atk",
+                    3,
+                    'a',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                P::new(
+                    ID::EnvelopeRelease as i32,
+                    "env-dcy",
+                    3,
+                    'd',
+                    envelope::MINIMUM_ENVELOPE_TIME,
+                    envelope::MAXIMUM_ENVELOPE_TIME,
+                    2.,
+                ),
+                //filter
+                P::new(ID::FilterCutoff as i32, "cutoff", 35, 'c', 20., 20000., 4.),
+                P::new(ID::FilterResonance as i32, "resonance", 0, 'q', 0.7, 10., 2.),
+                //Delay
+                P::new(
+                    ID::DelayTime as i32,
+                    "dly-time",
+                    4,
+                    't',
+                    buffer::MINIMUM_DELAY_TIME,
+                    buffer::MAXIMUM_DELAY_TIME,
+                    2.,
+                ),
+                P::new(ID::DelayFeedback as i32, "dly-feed", 4, 'f', 0., 1.0, 1.),
+                P::new(ID::DelayDryWet as i32, "dly-wet", 0, 'w', 0., 1., 1.),
+                P::new(ID::ReverbDryWet as i32, "rvb-wet", 0, 'r', 0., 1., 1.),
+                P::new(ID::ReverbTime as i32, "rvb-time", 0, '9', 0., 0.99, 1.),
+                //global
+                P::new(ID::Volume as i32, "volume", 14, 'v', 0., 2., 2.),
+            ],
+            nb_param: NB_SYNTH_PARAM,
+        };
+        assert!(params.no_id_double());
+        assert!(params.no_cc_double());
+        params
+    }
+
+    fn set_parameter(&mut self, (id, new_value): ParameterUpdate) {
+        type ID = WavetableModelParamID;
+        let typed_id: WavetableModelParamID = num::FromPrimitive::from_i32(id).unwrap();
+        match typed_id {
+            ID::Volume => self.volume = new_value,
+            ID::Table => {
+                self.table_index = (new_value.round() as usize).min(self.tables.len() - 1)
+            }
+            ID::Position => self.position_goal = new_value,
+            // envelope
+            ID::EnvelopeAttack => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_attack(new_value)),
+            ID::EnvelopeRelease => self
+                .voices
+                .iter_mut()
+                .for_each(|voice| voice.envelope.set_release(new_value)),
+            //filter
+            ID::FilterCutoff => {
+                self.cutoff = new_value;
+                self.low_pass
+                    .set_frequence_and_resonance(self.cutoff, self.resonance)
+            }
+            ID::FilterResonance => {
+                self.resonance = new_value;
+                self.low_pass
+                    .set_frequence_and_resonance(self.cutoff, self.resonance)
+            }
+            //delay
+            ID::DelayDryWet => self.delay_dry_wet = new_value,
+            ID::DelayTime => self.delay.set_delay_time(new_value),
+            ID::DelayFeedback => {
+                self.delay.set_freeze(new_value > 0.99);
+                self.delay.set_feedback(new_value)
+            }
+            //reverb
+            ID::ReverbDryWet => self.reverb.dry_wet = new_value,
+            ID::ReverbTime => self.reverb.set_reverb_time(new_value),
+        }
+    }
+}
+
+impl Synth for WavetableModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::model;
+
+    #[test]
+    fn morph_and_tables() {
+        let mut model: WavetableModel = model();
+        assert_eq!(model.table_names()[0], "basic");
+
+        let mut left = [0.; 512];
+        let mut right = [0.; 512];
+        model.process_block(&mut left, &mut right);
+        assert!(left.iter().all(|sample| *sample == 0.));
+
+        model.set_note(MidiMessage::NoteOn(60));
+        model.process_block(&mut left, &mut right);
+        assert!(left.iter().any(|sample| sample.abs() > 0.001));
+        assert_eq!(left, right);
+
+        //the position glides toward its new value instead of jumping
+        model.set_parameter((WavetableModelParamID::Position as i32, 1.));
+        model.process_block(&mut left, &mut right);
+        assert!(model.positions[0] < 0.1);
+        assert!(model.positions[511] > model.positions[0]);
+
+        //out of range tables are clamped
+        model.set_parameter((WavetableModelParamID::Table as i32, 100.));
+        assert_eq!(model.table_index, model.tables.len() - 1);
+    }
+}